@@ -0,0 +1,22 @@
+// A combinational loop: y feeds back into its own input through an
+// inverter, so once it has a known value it toggles forever without
+// time moving on. The simulator gives up after --max-deltas delta
+// cycles and reports what was busy:
+//
+//   cargo run -- -n -d 100 examples/comb_loop.v
+
+module comb_loop;
+  reg enable;
+  wire y, y_n;
+  reg fb;
+
+  assign y_n = ~y;
+  assign y = enable ? fb : 1'b0;
+
+  always @(y_n) fb = y_n;
+
+  initial begin
+    enable = 0;
+    #10 enable = 1;
+  end
+endmodule
@@ -0,0 +1,22 @@
+// $display, $write and format specifiers
+
+module display;
+  reg [7:0] a;
+  reg [11:0] b;
+  reg [31:0] name;
+
+  initial begin
+    a = 8'd5;
+    b = 12'h0a5;
+    name = "tv2";
+    $display("hello from %m at time %0t", $time);
+    $display("a=%d a=%0d a=%4d a=%04d", a, a, a, a);
+    $display("b=%b b=%0b b=%o b=%h b=%0h b=%x", b, b, b, b, b, b);
+    $write("no newline, ");
+    $write("so this carries on: %s%c\n", name, 8'h21);
+    b[7:4] = 4'bxxxx;
+    b[1] = 1'bz;
+    $display("with x and z: b=%h b=%b b=%d a=", b, b, b, a);
+    #42 $display("%t|%5t|%0t|100%%", $time, $stime, $time);
+  end
+endmodule
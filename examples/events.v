@@ -0,0 +1,38 @@
+// Named events and wait. `->ev` wakes everything waiting at @(ev); an
+// event has no value, it just happens. wait (expr) carries straight on
+// if expr is already true, otherwise it waits until it is.
+
+module tb;
+  event go;
+  event done;
+  reg [3:0] count;
+  reg enable;
+
+  // counts up on each go, once it's enabled
+  always @(go) begin
+    wait (enable) count = count + 1;
+    $display("%t count is %0d", $time, count);
+    if (count == 3)
+      -> done;
+  end
+
+  initial begin
+    count = 0;
+    enable = 0;
+    #5 -> go;
+    #5 enable = 1;
+    #5 -> go;
+    #5 -> go;
+  end
+
+  initial begin
+    @(done) $display("%t done", $time);
+    wait (count == 3) $display("%t count is 3 already, no wait", $time);
+    wait (count > 3);
+    $display("%t never gets here", $time);
+  end
+
+  initial begin
+    wait (count == 2 && enable) $display("%t count got to 2", $time);
+  end
+endmodule
@@ -0,0 +1,20 @@
+// A free-running clock never runs out of events; $finish ends the
+// simulation instead.
+
+module finish;
+  reg clk;
+  reg [7:0] ticks;
+
+  initial clk = 0;
+  always #5 clk = ~clk;
+
+  initial ticks = 0;
+  always @(posedge clk) ticks <= ticks + 1;
+
+  initial begin
+    #100;
+    $display("%0d clock ticks by time %0t", ticks, $time);
+    $finish;
+    $display("never printed");
+  end
+endmodule
@@ -0,0 +1,70 @@
+// fork ... join starts each statement inside as a thread of its own.
+// join waits for all of them, join_any for the first one to finish and
+// join_none not at all; disable fork stops whatever's still running.
+
+module tb;
+  reg clk;
+  reg [7:0] data;
+  reg valid;
+  reg ready;
+
+  initial begin
+    clk = 0;
+    forever #5 clk = ~clk;
+  end
+
+  // the device is ready `data` clocks after it's asked
+  always @(posedge valid) begin
+    repeat (data) @(posedge clk);
+    ready = 1;
+    @(negedge clk) ready = 0;
+  end
+
+  task handshake (input [7:0] value);
+    begin
+      fork
+        begin
+          data = value;
+          valid = 1;
+          @(posedge ready) valid = 0;
+          $display("%t %m: %0d accepted", $time, value);
+        end
+        begin : timeout
+          #40 $display("%t %m: %0d timed out", $time, value);
+          valid = 0;
+        end
+      join_any
+      disable fork;
+    end
+  endtask
+
+  initial begin
+    valid = 0;
+    ready = 0;
+
+    // stimulus and checking side by side, carrying on once both are done
+    fork
+      repeat (3) @(posedge clk) data = data + 1;
+      begin
+        @(negedge clk) $display("%t first data %0d", $time, data);
+        @(negedge clk) $display("%t second data %0d", $time, data);
+      end
+      #1 data = 10;
+    join
+    $display("%t both threads done, data %0d", $time, data);
+
+    handshake(1);
+    #20 handshake(9);
+    #100 handshake(2);
+
+    // fire off a few watchers and don't wait for them
+    repeat (3) begin
+      fork
+        #(data) $display("%t a watcher is done, data is now %0d", $time, data);
+      join_none
+      #1 data = data + 5;
+    end
+    $display("%t started the watchers", $time);
+    #40 $finish;
+  end
+endmodule
@@ -0,0 +1,57 @@
+// Gate primitives. A full adder out of and/or/xor, a 3 input nand, a
+// buf driving two outputs and a pair of tristate buffers.
+// #(rise, fall, turn-off) delays are inertial: the 1 pulse on p is
+// shorter than the nand's fall delay so it never gets through.
+
+module full_adder(input a, b, cin, output sum, cout);
+  wire s1, c1, c2;
+  xor x1 (s1, a, b);
+  xor x2 (sum, s1, cin);
+  and a1 (c1, a, b);
+  and a2 (c2, s1, cin);
+  or  o1 (cout, c1, c2);
+endmodule
+
+module tb;
+  reg a, b, cin;
+  wire sum, cout;
+  reg p, q, r;
+  wire n, y1, y2;
+  reg d, sel;
+  wire low, high;
+
+  full_adder fa (a, b, cin, sum, cout);
+
+  nand #(2, 3) g1 (n, p, q, r);
+  buf (y1, y2, n);
+
+  bufif0 #(1, 1, 4) t0 (low, d, sel);
+  notif1 #(1, 1, 4) t1 (high, d, sel);
+
+  initial begin
+    {a, b, cin} = 0;
+    repeat (7) #1 {a, b, cin} = {a, b, cin} + 1;
+  end
+
+  initial $monitor("%t adder %b+%b+%b = %b%b", $time, a, b, cin, cout, sum);
+
+  initial begin
+    p = 1; q = 1; r = 0;
+    #10 r = 1;        // n falls 3 later
+    #5  r = 0;        // and rises 2 after that
+    #5  r = 1; #1 r = 0; // too short to get through
+  end
+
+  always @(n) $strobe("%t n = %b, y1 = %b, y2 = %b", $time, n, y1, y2);
+
+  initial begin
+    d = 0; sel = 1'bx;
+    #30 sel = 0;
+    #5  sel = 1;
+    #5  d = 1'bz;
+  end
+
+  always @(low or high) $display("%t low = %b, high = %b", $time, low, high);
+
+  initial #50 $finish;
+endmodule
@@ -0,0 +1,65 @@
+// Generate: a loop builds a shift register one stage at a time, each
+// stage in its own scope (tb.u_shift.stage[0], stage[1], ...), and a
+// generate if and case pick the output logic from parameters.
+
+module flop (input clk, input d, output reg q);
+  always @(posedge clk)
+    q <= d;
+endmodule
+
+module shifter #(parameter DEPTH = 4, INVERT = 0, MODE = 1)
+                (input clk, input din, output [DEPTH-1:0] taps, output dout, output parity);
+  wire [DEPTH:0] chain;
+  assign chain[0] = din;
+
+  genvar i;
+  generate
+    for (i = 0; i < DEPTH; i = i + 1) begin : stage
+      wire q;
+      flop u_ff (.clk(clk), .d(chain[i]), .q(q));
+      assign chain[i+1] = q;
+      assign taps[i] = q;
+    end
+  endgenerate
+
+  if (INVERT)
+    assign dout = ~chain[DEPTH];
+  else
+    assign dout = chain[DEPTH];
+
+  case (MODE)
+    0: begin : none
+      assign parity = 1'b0;
+    end
+    1: begin : even
+      assign parity = ^taps;
+    end
+    default: begin : odd
+      assign parity = ~^taps;
+    end
+  endcase
+endmodule
+
+module tb;
+  reg clk, din;
+  wire [5:0] taps;
+  wire dout, parity;
+
+  shifter #(.DEPTH(6), .INVERT(1)) u_shift (clk, din, taps, dout, parity);
+
+  initial begin
+    clk = 0;
+    din = 1;
+    #10 din = 0;
+    #20 din = 1;
+    #10 din = 0;
+  end
+
+  always #5 clk = ~clk;
+
+  initial begin
+    $monitor("%t din=%b taps=%b dout=%b parity=%b stage[2].q=%b",
+             $time, din, taps, dout, parity, u_shift.stage[2].q);
+    #100 $finish;
+  end
+endmodule
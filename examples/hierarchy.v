@@ -0,0 +1,39 @@
+// Module instances, named and positional port connections, and
+// hierarchical names. The VCD file has a scope for each instance.
+
+module flop (clk, d, q);
+  input clk;
+  input [3:0] d;
+  output reg [3:0] q;
+
+  always @(posedge clk)
+    q <= d;
+endmodule
+
+module pipeline (input clk, input [3:0] din, output [3:0] dout);
+  wire [3:0] mid;
+
+  flop u_first (.clk(clk), .d(din), .q(mid));
+  flop u_second (clk, mid, dout);
+endmodule
+
+module tb;
+  reg clk;
+  reg [3:0] data;
+  wire [3:0] out;
+
+  pipeline u_pipe (.clk(clk), .din(data), .dout(out));
+
+  initial begin
+    clk = 0;
+    data = 4'h1;
+    repeat (10) begin
+      #5 clk = 1;
+      #5 clk = 0;
+      data = data + 1;
+    end
+  end
+
+  initial
+    $monitor("%t %m: data=%h mid=%h out=%h", $time, data, tb.u_pipe.mid, u_pipe.dout);
+endmodule
@@ -0,0 +1,47 @@
+// Intra-assignment timing controls. "a = #3 b" works b out now and
+// assigns it 3 later, where "#3 a = b" waits first and reads b then.
+// "q <= #2 d" doesn't hold the procedure up at all: the update lands
+// in the nonblocking region 2 later, and several can be on their way.
+
+module tb;
+  reg clk;
+  reg [7:0] b, early, late;
+  reg [7:0] count, delayed, sampled, next_edge;
+
+  initial begin
+    clk = 0;
+    forever #5 clk = ~clk;
+  end
+
+  initial begin
+    b = 1;
+    fork
+      early = #3 b;
+      #3 late = b;
+      #1 b = 2;
+    join
+    $display("%t early = %0d, late = %0d", $time, early, late);
+
+    // b as it is now, assigned at the next rising clock
+    b = 7;
+    sampled = @(posedge clk) b + 1;
+    $display("%t sampled = %0d", $time, sampled);
+
+    // carries straight on, next_edge changes at the clock after
+    next_edge <= @(negedge clk) b;
+    b = 9;
+    $display("%t next_edge = %0d, not yet", $time, next_edge);
+    @(negedge clk) $strobe("%t next_edge = %0d", $time, next_edge);
+  end
+
+  // a 12 time unit transport delay on a counter clocked every 10
+  initial count = 0;
+  always @(posedge clk) begin
+    count <= count + 1;
+    delayed <= #12 count;
+  end
+
+  initial $monitor("%t count = %0d, delayed = %0d", $time, count, delayed);
+
+  initial #60 $finish;
+endmodule
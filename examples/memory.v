@@ -0,0 +1,55 @@
+// Memories. A ROM loaded from examples/rom.hex (run from the top of the
+// repository), with a gap left as x and an @address jumping to the end,
+// and a small RAM written a byte at a time and read back through a
+// continuous assignment, which follows every write. $writememh saves
+// the RAM to ram.hex. --dump-memories puts every word in the VCD file.
+
+module ram (clk, we, addr, wdata, rdata);
+  input clk, we;
+  input [1:0] addr;
+  input [7:0] wdata;
+  output [7:0] rdata;
+
+  reg [7:0] mem [0:3];
+
+  always @(posedge clk)
+    if (we)
+      mem[addr] <= wdata;
+
+  assign rdata = mem[addr];
+endmodule
+
+module tb;
+  reg [7:0] rom [0:15];
+  reg clk, we;
+  reg [1:0] addr;
+  reg [7:0] wdata;
+  wire [7:0] rdata;
+  reg [4:0] i;
+
+  ram u_ram (clk, we, addr, wdata, rdata);
+
+  always #5 clk = ~clk;
+
+  initial begin
+    $readmemh("examples/rom.hex", rom);
+    for (i = 0; i < 16; i = i + 1)
+      $display("rom[%0d] = %h", i, rom[i]);
+    rom[16] = 8'hff;  // out of range, with a warning
+
+    clk = 0;
+    we = 1;
+    for (i = 0; i < 4; i = i + 1) begin
+      addr = i;
+      wdata = rom[i * 2 + 1];
+      @(negedge clk);
+    end
+    we = 0;
+    for (i = 4; i > 0; i = i - 1) begin
+      addr = i - 1;
+      #1 $display("%0t: ram[%0d] = %h", $time, addr, rdata);
+    end
+    $writememh("ram.hex", u_ram.mem);
+    $finish;
+  end
+endmodule
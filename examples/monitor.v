@@ -0,0 +1,22 @@
+// $monitor and $strobe print from the postponed region, after the
+// nonblocking assignments have landed, so they always show the
+// settled values for the time step. The $monitor prints only when
+// one of its arguments has changed.
+
+module monitor;
+  reg clk;
+  reg [7:0] count;
+  wire [3:0] low = count[3:0];
+
+  initial begin
+    clk = 0;
+    count = 0;
+    $monitor("clk=%b count=%d (%h) low=%b", clk, count, count, low);
+    repeat (8) #5 clk = ~clk;
+  end
+
+  always @(posedge clk) begin
+    count <= count + 8'd7;
+    $strobe("strobe: count is now %d, was", count, count - 8'd7);
+  end
+endmodule
@@ -0,0 +1,66 @@
+// Nets with more than one driver. Two instances share a tristate bus
+// through their ports; both driving it at once is contention, x. An
+// open-drain line pulled up by a pullup, with one of its drivers in
+// another module on an inout port, wired-and and wired-or nets,
+// a weak driver overpowered by a strong one, and nets that are never
+// driven at all but still have a value.
+
+module driver(input en, input [3:0] data, output [3:0] bus);
+  assign bus = en ? data : 4'bz;
+endmodule
+
+// lets go of the line, or pulls it low
+module open_drain(input low, inout line);
+  bufif1 (line, 1'b0, low);
+endmodule
+
+module tb;
+  reg en_a, en_b;
+  wire [3:0] bus;
+  driver a (en_a, 4'h5, bus);
+  driver b (en_b, 4'hc, bus);
+
+  // open drain: anyone can pull it low, otherwise the pullup wins
+  reg low_a, low_b;
+  wire sda;
+  pullup (sda);
+  bufif1 (sda, 1'b0, low_a);
+  open_drain pin (low_b, sda);
+
+  wand all_ready;
+  wor any_error;
+  reg r1, r2, e1, e2;
+  assign all_ready = r1;
+  assign all_ready = r2;
+  assign any_error = e1;
+  assign any_error = e2;
+
+  reg w, s, s_en;
+  wire mixed;
+  assign (weak0, weak1) mixed = w;
+  assign mixed = s_en ? s : 1'bz;
+
+  tri0 floating_low;
+  tri1 floating_high;
+  supply0 gnd;
+  supply1 vdd;
+
+  initial begin
+    en_a = 0; en_b = 0;
+    low_a = 0; low_b = 0;
+    r1 = 1; r2 = 1; e1 = 0; e2 = 0;
+    w = 1; s = 0; s_en = 0;
+    #1 en_a = 1;
+    #1 en_a = 0; en_b = 1;
+    #1 en_a = 1;
+    #1 low_a = 1; r2 = 0; e1 = 1;
+    #1 low_b = 1; s_en = 1;
+    #1 low_a = 0; low_b = 0; r2 = 1; e1 = 0; s_en = 0;
+  end
+
+  initial $monitor("%t bus=%b sda=%b all_ready=%b any_error=%b mixed=%b",
+    $time, bus, sda, all_ready, any_error, mixed);
+
+  initial #1 $display("%t floating_low=%b floating_high=%b gnd=%b vdd=%b",
+    $time, floating_low, floating_high, gnd, vdd);
+endmodule
@@ -0,0 +1,41 @@
+// Parameters: defaults in the module header and body, #(...) overrides
+// by position and by name, a defparam, and a localparam worked out
+// from the others. Parameters set widths, delays and part selects.
+
+module counter #(parameter WIDTH = 4, STEP = 1) (input clk, output reg [WIDTH-1:0] count);
+  localparam MAX = (1 << WIDTH) - 1;
+  parameter DELAY = 1;
+
+  initial count = 0;
+
+  always @(posedge clk)
+    #DELAY count <= (count == MAX) ? 0 : count + STEP;
+endmodule
+
+module tb;
+  parameter HALF = 5;
+  localparam BIG = 8;
+  reg clk;
+  wire [3:0] small;
+  wire [BIG-1:0] big;
+  wire [5:0] odd;
+
+  counter u_small (.clk(clk), .count(small));
+  counter #(BIG, 3) u_big (clk, big);
+  counter #(.WIDTH(6)) u_odd (.clk(clk), .count(odd));
+  defparam u_odd.STEP = 5, u_odd.DELAY = 2;
+
+  wire [3:0] top_nibble;
+  assign #(HALF - 4) top_nibble = big[BIG-1:4];
+
+  initial begin
+    clk = 0;
+    repeat (20) begin
+      #HALF clk = 1;
+      #HALF clk = 0;
+    end
+  end
+
+  initial
+    $monitor("%t small=%d big=%d odd=%d top_nibble=%h", $time, small, big, odd, top_nibble);
+endmodule
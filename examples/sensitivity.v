@@ -0,0 +1,45 @@
+// Event controls on expressions, and @* which works out what to wait
+// on by itself. @(b[3]) wakes on any change to b, but only carries on
+// if bit 3 is different; @(posedge a & en) waits for a & en to rise.
+
+module tb;
+  reg a, en;
+  reg [3:0] b;
+  reg [1:0] sel;
+  reg y, mux;
+
+  // combinational logic without a hand-written sensitivity list
+  always @* y = (a & en) | b[0];
+
+  always @(*) begin
+    case (sel)
+      0: mux = a;
+      1: mux = en;
+      2: mux = b[1];
+      default: mux = 1'b0;
+    endcase
+  end
+
+  initial begin
+    forever @(b[3]) $display("%t b[3] is now %b (b = %b)", $time, b[3], b);
+  end
+
+  initial begin
+    forever @(posedge a & en) $display("%t a & en rose", $time);
+  end
+
+  initial
+    $monitor("%t a=%b en=%b b=%b sel=%0d: y=%b mux=%b", $time, a, en, b, sel, y, mux);
+
+  initial begin
+    a = 0; en = 0; b = 0; sel = 0;
+    #10 a = 1;
+    #10 en = 1;
+    #10 b = 4'b0001;
+    #10 b = 4'b1001;
+    #10 b = 4'b1111;
+    #10 a = 0; sel = 2;
+    #10 b = 4'b0000;
+    #10 a = 1; sel = 1;
+  end
+endmodule
@@ -0,0 +1,35 @@
+// The built-in demo design, written out in Verilog
+`timescale 1ns/1ps
+
+module shift_register;
+  reg clk, a, b;
+  wire y;
+  reg ff1_out, ff2_out, ff3_out;
+
+  initial begin
+    clk = 0;
+    #5 clk = 1;
+    #5 clk = 0;
+    #5 clk = 1;
+    #5 clk = 0;
+    #5 clk = 1;
+    #5 clk = 0;
+    #5 clk = 1;
+    #5 clk = 0;
+  end
+
+  initial begin
+    a = 1; #7 a = 0; #7 a = 1; #7 a = 1; #7 a = 0;
+  end
+
+  initial begin
+    b = 1; #11 b = 1; #11 b = 0;
+  end
+
+  always @(a or b)
+    y = a & b;
+
+  always @(posedge clk) ff1_out <= y;
+  always @(posedge clk) ff2_out <= ff1_out;
+  always @(posedge clk) ff3_out <= ff2_out;
+endmodule
@@ -0,0 +1,59 @@
+// Signed arithmetic, and integer, time and real variables. An integer
+// counts down past zero, signed operands divide and compare as signed
+// but one unsigned operand makes the whole expression unsigned, >>>
+// copies the sign bit, and $signed and $unsigned change how a value is
+// read. Reals round to the nearest integer when they're assigned to
+// one and show up in the VCD file as "r" records.
+
+module tb;
+  integer i;
+  reg signed [7:0] s;
+  reg [7:0] u;
+  reg [3:0] small;
+  time t;
+  real r, step;
+
+  function real average;
+    input real a, b;
+    average = (a + b) / 2;
+  endfunction
+
+  initial begin
+    for (i = 3; i >= 0; i = i - 1)
+      $display("countdown %0d", i);
+    $display("after the loop i = %0d", i);
+
+    s = -7;
+    u = 8'd200;
+    $display("-7 / 2 = %0d, -7 %% 2 = %0d", s / 2, s % 2);
+    $display("s < 1 is %b, signed", s < 1);
+    $display("s < 1'b1 is %b, unsigned", s < 1'b1);
+    $display("s = %d, %b, %h", s, s, s);
+    $display("s >>> 2 = %b, s >> 2 = %b", s >>> 2, s >> 2);
+    $display("$signed(u) = %0d, $unsigned(s) = %0d", $signed(u), $unsigned(s));
+    small = 4'b1100;
+    i = $signed(small);
+    $display("sign extended %b to %0d", small, i);
+    i = small;
+    $display("zero extended %b to %0d", small, i);
+    i = -2 ** 3;
+    $display("-2 ** 3 = %0d", i);
+
+    #5 t = $time;
+    $display("t = %0t", t);
+
+    step = 0.25;
+    r = 1.5e1;
+    #5 r = r * step + 1;
+    $display("r = %f, %e, %g, %8.3f", r, r, r, r);
+    $display("r = %g, r / 3 = %g, %0d", r * 1e-6, r / 3, r);
+    $display("r = ", r, " average ", average(r, 2.0));
+    i = 2.5;
+    $display("2.5 rounds to %0d", i);
+    i = -2.5;
+    $display("-2.5 rounds to %0d, $rtoi(3.9) = %0d", i, $rtoi(3.9));
+    r = $itor(u) / 8;
+    $display("$itor(u) / 8 = %f at %0t", r, $realtime);
+    #5 r = 0.0;
+  end
+endmodule
@@ -0,0 +1,81 @@
+// Tasks and functions. A function works out a value in no time and can
+// be used in any expression; a task can wait, and hands its outputs
+// back when it returns. Automatic ones get their own variables on every
+// call, so two processes can be inside one at once, and a function can
+// call itself.
+
+module tb;
+  reg clk;
+  reg tx;
+  reg [7:0] got;
+
+  function [7:0] add_sat (input [7:0] a, input [7:0] b);
+    reg [8:0] sum;
+    begin
+      sum = a + b;
+      add_sat = sum[8] ? 8'hff : sum[7:0];
+    end
+  endfunction
+
+  function automatic [31:0] factorial;
+    input [31:0] n;
+    factorial = (n <= 1) ? 1 : n * factorial(n - 1);
+  endfunction
+
+  // serial out, least significant bit first
+  task send (input [7:0] data);
+    reg [3:0] i;
+    begin
+      @(negedge clk) tx = 0;
+      for (i = 0; i < 8; i = i + 1)
+        @(negedge clk) tx = data[i];
+      @(negedge clk) tx = 1;
+    end
+  endtask
+
+  task receive (output [7:0] data);
+    reg [3:0] i;
+    begin
+      @(posedge clk);
+      while (tx)
+        @(posedge clk);
+      for (i = 0; i < 8; i = i + 1)
+        @(posedge clk) data = {tx, data[7:1]};
+    end
+  endtask
+
+  // both callers are in here at the same time, each with its own wait
+  task automatic later (input [7:0] id, input [7:0] wait_for);
+    begin
+      #wait_for;
+      $display("%t %m: caller %0d waited %0d", $time, id, wait_for);
+    end
+  endtask
+
+  initial begin
+    clk = 0;
+    forever #5 clk = ~clk;
+  end
+
+  initial begin
+    tx = 1;
+    $display("add_sat(200, 100) = %0d, add_sat(20, 10) = %0d", add_sat(200, 100), add_sat(20, 10));
+    $display("5! = %0d, 10! = %0d", factorial(5), factorial(10));
+    send(8'ha5);
+    send(8'h3c);
+  end
+
+  initial begin
+    repeat (2) begin
+      receive(got);
+      $display("%t received %h", $time, got);
+    end
+  end
+
+  initial later(1, 30);
+  initial begin
+    #10 later(2, 5);
+  end
+
+  initial #300 $finish;
+endmodule
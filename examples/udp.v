@@ -0,0 +1,86 @@
+// User-defined primitives. A combinational mux, a rising-edge D flop
+// with an active-low clear, and a latch. Rows on levels win over rows
+// on edges, so the clear holds q at 0 whatever the clock does, and an
+// edge the table doesn't list takes the flop's output to x.
+
+primitive mux2 (y, s, a, b);
+  output y;
+  input s, a, b;
+  table
+  // s a b : y
+     0 0 ? : 0;
+     0 1 ? : 1;
+     1 ? 0 : 0;
+     1 ? 1 : 1;
+     x 0 0 : 0;  // same either way
+     x 1 1 : 1;
+  endtable
+endprimitive
+
+primitive dff (q, clk, d, clr_n);
+  output q;
+  reg q;
+  input clk, d, clr_n;
+  initial q = 0;
+  table
+  // clk   d clr_n : q : q+
+     ?     ?   0   : ? : 0;  // clear
+     r     0   1   : ? : 0;
+     r     1   1   : ? : 1;
+     (?0)  ?   ?   : ? : -;  // falling clock
+     (1x)  ?   ?   : ? : -;
+     ?     *   ?   : ? : -;  // d moving
+     ?     ?  (?1) : ? : -;  // clear let go
+  endtable
+endprimitive
+
+primitive latch (q, en, d);
+  output q;
+  reg q;
+  input en, d;
+  table
+     1 0 : ? : 0;
+     1 1 : ? : 1;
+     0 ? : ? : -;
+  endtable
+endprimitive
+
+module tb;
+  reg s, a, b;
+  wire y;
+  reg clk, d, clr_n;
+  wire q, q_slow, l;
+
+  mux2 m1 (y, s, a, b);
+  dff ff1 (q, clk, d, clr_n);
+  dff #(2, 3) ff2 (q_slow, clk, d, clr_n);
+  latch l1 (l, clk, d);
+
+  initial begin
+    a = 0; b = 1;
+    s = 0; #1 s = 1; #1 s = 1'bx; #1 a = 1; #1 s = 0;
+  end
+
+  initial $monitor("%t mux s=%b a=%b b=%b y=%b", $time, s, a, b, y);
+
+  initial begin
+    clk = 0;
+    forever #5 clk = ~clk;
+  end
+
+  initial begin
+    clr_n = 0; d = 1;
+    #12 clr_n = 1;
+    #10 d = 0;
+    #10 d = 1;
+    #2  clr_n = 0;
+    #6  clr_n = 1;
+    #13 clk = 1'bx; // (0x) isn't in the table
+  end
+
+  always @(q or q_slow or l)
+    $display("%t clk=%b d=%b clr_n=%b q=%b q_slow=%b l=%b",
+      $time, clk, d, clr_n, q, q_slow, l);
+
+  initial #60 $finish;
+endmodule
@@ -0,0 +1,41 @@
+// #0 and the inactive region
+//
+// All four initial blocks start at time 0, in no guaranteed order.
+// The stratified event queue still pins down what each one sees:
+//
+//   active   - blocking assignments, procedures running
+//   inactive - procedures resuming after a #0
+//   NBA      - nonblocking assignment updates
+//
+// so at the end of time 0:
+//
+//   early = 1  the #0 lets "a = 1" run first, whatever the order
+//   stale = 0  nonblocking updates land after the inactive region,
+//              so the #0 still sees the old value of n
+//   fresh = 1  a #1 is a new time step, long after the update
+//
+// (If #0 went back through the time heap like any other delay, it
+// would resume after the nonblocking update and stale would be 1.)
+
+module zero_delay;
+  reg a, n;
+  reg early, stale, fresh;
+
+  initial begin
+    #0 early = a;
+  end
+
+  initial begin
+    a = 1;
+  end
+
+  initial begin
+    n = 0;
+    n <= 1;
+  end
+
+  initial begin
+    #0 stale = n;
+    #1 fresh = n;
+  end
+endmodule
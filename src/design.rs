@@ -0,0 +1,465 @@
+//! Verilog design units
+//!
+//! What the parser hands back: modules holding declarations, instances
+//! of other modules, and the procedures and continuous assignments
+//! that the engine will simulate, and user-defined primitives.
+
+use std::fmt;
+use std::rc::Rc;
+
+use gate::GateKind;
+use net::{DriveStrength, NetKind};
+use procedure::{Procedure, ContinuousAssign, Expression, Statement};
+use udp::Table;
+use value::Logic;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum DeclKind {
+    Input,
+    Output,
+    Inout,
+    Reg,
+    Integer, // 32 bits, signed
+    Time, // 64 bits, unsigned
+    Real, // and realtime
+    Net(NetKind), // wire, tri, wand...
+    Event, // named, for -> and @
+}
+
+impl fmt::Display for DeclKind {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DeclKind::Input => write!(f, "input"),
+            DeclKind::Output => write!(f, "output"),
+            DeclKind::Inout => write!(f, "inout"),
+            DeclKind::Reg => write!(f, "reg"),
+            DeclKind::Integer => write!(f, "integer"),
+            DeclKind::Time => write!(f, "time"),
+            DeclKind::Real => write!(f, "real"),
+            DeclKind::Net(kind) => write!(f, "{}", kind),
+            DeclKind::Event => write!(f, "event"),
+        }
+    }
+}
+
+impl DeclKind {
+
+    pub fn is_direction(self) -> bool {
+        matches!(self, DeclKind::Input | DeclKind::Output | DeclKind::Inout)
+    }
+
+    // what procedures assign to, and so what can be a memory
+    pub fn is_variable(self) -> bool {
+        matches!(self, DeclKind::Reg | DeclKind::Integer | DeclKind::Time | DeclKind::Real)
+    }
+
+    // integers, times and reals are as wide as they are, without a range
+    pub fn width(self) -> Option<usize> {
+        match self {
+            DeclKind::Integer => Some(32),
+            DeclKind::Time | DeclKind::Real => Some(64),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Declaration {
+    pub kind: DeclKind,
+    pub name: String,
+    pub range: Option<(Expression, Expression)>, // [msb:lsb]
+    pub signed: bool, // "reg signed [7:0] a;"
+    pub array: Option<(Expression, Expression)>, // a memory's [first:last] word
+}
+
+impl fmt::Display for Declaration {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if self.signed {
+            write!(f, " signed")?;
+        }
+        match self.range {
+            Some((ref msb, ref lsb)) => write!(f, " [{}:{}] {}", msb, lsb, self.name)?,
+            None => write!(f, " {}", self.name)?,
+        }
+        match self.array {
+            Some((ref first, ref last)) => write!(f, " [{}:{}]", first, last),
+            None => Ok(()),
+        }
+    }
+}
+
+// "parameter [7:0] INIT = 8'h5a;", which instances can override
+// unless it's a localparam
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub name: String,
+    pub range: Option<(Expression, Expression)>,
+    pub value: Expression,
+    pub local: bool,
+}
+
+impl fmt::Display for Parameter {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        let kind = if self.local { "localparam" } else { "parameter" };
+        match self.range {
+            Some((ref msb, ref lsb)) => write!(f, "{} [{}:{}] {} = {}",
+                kind, msb, lsb, self.name, self.value),
+            None => write!(f, "{} {} = {}", kind, self.name, self.value),
+        }
+    }
+}
+
+// "defparam u1.WIDTH = 8;"
+#[derive(Debug, Clone)]
+pub struct Defparam {
+    pub path: String,
+    pub value: Expression,
+}
+
+impl fmt::Display for Defparam {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        write!(f, "defparam {} = {}", self.path, self.value)
+    }
+}
+
+// how an instance's ports are hooked up, None for a port left open
+#[derive(Debug, Clone)]
+pub enum Connections {
+    Positional(Vec<Option<Expression>>),      // u1 (a, , y)
+    Named(Vec<(String, Option<Expression>)>), // u1 (.a(a), .y(y))
+}
+
+#[derive(Debug, Clone)]
+pub struct Instance {
+    pub module: String,
+    pub name: String,
+    pub parameters: Connections, // #(8) or #(.WIDTH(8))
+    pub connections: Connections,
+}
+
+impl fmt::Display for Connections {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        let conns: Vec<String> = match *self {
+            Connections::Positional(ref exprs) => exprs.iter().map(|e| match *e {
+                Some(ref e) => format!("{}", e),
+                None => String::new(),
+            }).collect(),
+            Connections::Named(ref ports) => ports.iter().map(|(port, e)| match *e {
+                Some(ref e) => format!(".{}({})", port, e),
+                None => format!(".{}()", port),
+            }).collect(),
+        };
+        write!(f, "{}", conns.join(", "))
+    }
+}
+
+impl Connections {
+
+    pub fn is_empty(&self) -> bool {
+        match *self {
+            Connections::Positional(ref exprs) => exprs.is_empty(),
+            Connections::Named(ref ports) => ports.is_empty(),
+        }
+    }
+}
+
+impl fmt::Display for Instance {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        if self.parameters.is_empty() {
+            write!(f, "{} {} ({})", self.module, self.name, self.connections)
+        } else {
+            write!(f, "{} #({}) {} ({})", self.module, self.parameters, self.name,
+                self.connections)
+        }
+    }
+}
+
+// "and (weak0, weak1) #(1, 2) g1 (y, a, b, c);", outputs first
+#[derive(Debug, Clone)]
+pub struct Gate {
+    pub kind: GateKind,
+    pub strength: DriveStrength,
+    pub name: Option<String>,
+    pub delays: Vec<Expression>, // rise, fall, turn-off
+    pub terminals: Vec<Expression>,
+}
+
+impl fmt::Display for Gate {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if self.strength != self.kind.strength() {
+            write!(f, " {}", self.strength)?;
+        }
+        if !self.delays.is_empty() {
+            let delays: Vec<String> = self.delays.iter().map(|d| format!("{}", d)).collect();
+            write!(f, " #({})", delays.join(", "))?;
+        }
+        if let Some(ref name) = self.name {
+            write!(f, " {}", name)?;
+        }
+        let terminals: Vec<String> = self.terminals.iter().map(|t| format!("{}", t)).collect();
+        write!(f, " ({})", terminals.join(", "))
+    }
+}
+
+impl Gate {
+
+    pub fn outputs(&self) -> &[Expression] {
+        &self.terminals[..self.kind.outputs(self.terminals.len())]
+    }
+
+    pub fn inputs(&self) -> &[Expression] {
+        &self.terminals[self.kind.outputs(self.terminals.len())..]
+    }
+}
+
+// "primitive dff (q, clk, d); ... table ... endtable endprimitive",
+// with the output first
+#[derive(Debug, Clone)]
+pub struct Primitive {
+    pub name: String,
+    pub ports: Vec<String>,
+    pub initial: Option<Logic>, // "initial q = 1;", sequential ones only
+    pub table: Table,
+}
+
+impl Primitive {
+
+    #[allow(dead_code)]
+    pub fn show(&self) {
+        println!("primitive {} ({})", self.name, self.ports.join(", "));
+        if let Some(initial) = self.initial {
+            println!(" initial {} = {};", self.ports[0], initial);
+        }
+        for row in &self.table.rows {
+            println!("  {}", row);
+        }
+        println!("endprimitive");
+    }
+}
+
+// an instance of a primitive, once elaboration has found out that's
+// what it is. The terminals are the ports in order
+#[derive(Debug, Clone)]
+pub struct PrimitiveInstance {
+    pub primitive: Rc<Primitive>,
+    pub name: String,
+    pub delays: Vec<Expression>, // rise, fall
+    pub terminals: Vec<Expression>,
+}
+
+impl fmt::Display for PrimitiveInstance {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.primitive.name)?;
+        if !self.delays.is_empty() {
+            let delays: Vec<String> = self.delays.iter().map(|d| format!("{}", d)).collect();
+            write!(f, " #({})", delays.join(", "))?;
+        }
+        let terminals: Vec<String> = self.terminals.iter().map(|t| format!("{}", t)).collect();
+        write!(f, " {} ({})", self.name, terminals.join(", "))
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum SubroutineKind {
+    Task,
+    Function,
+}
+
+impl fmt::Display for SubroutineKind {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SubroutineKind::Task => write!(f, "task"),
+            SubroutineKind::Function => write!(f, "function"),
+        }
+    }
+}
+
+// a task or function. A function's value is the variable with its
+// own name; an automatic one gets fresh variables on every call
+#[derive(Debug, Clone)]
+pub struct Subroutine {
+    pub kind: SubroutineKind,
+    pub name: String,
+    pub automatic: bool,
+    pub ports: Vec<String>,        // the arguments, in order
+    pub decls: Vec<Declaration>,   // the arguments' and everything else's
+    pub stmts: Rc<Vec<Statement>>, // lowered, like a procedure's
+}
+
+impl Subroutine {
+
+    // input, output or inout
+    pub fn direction(&self, port: &str) -> Option<DeclKind> {
+        self.decls.iter()
+            .find(|d| d.name == port && d.kind.is_direction())
+            .map(|d| d.kind)
+    }
+}
+
+// the items in a generate block; a named block is a scope of its own
+#[derive(Clone)]
+pub struct GenBlock {
+    pub name: Option<String>,
+    pub items: Vec<ModuleItem>,
+}
+
+// structure built at elaboration time, from constant conditions
+#[derive(Clone)]
+pub enum Generate {
+    For {               // for (i = 0; i < N; i = i + 1) begin : stage
+        var: String,
+        init: Expression,
+        cond: Expression,
+        step: Expression,
+        block: GenBlock,
+    },
+    If {
+        cond: Expression,
+        then_block: GenBlock,
+        else_block: Option<GenBlock>,
+    },
+    Case {
+        expr: Expression,
+        arms: Vec<(Vec<Expression>, GenBlock)>,
+        default: Option<GenBlock>,
+    },
+}
+
+impl fmt::Display for Generate {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Generate::For{ref var, ref init, ref cond, ref step, ..} => {
+                write!(f, "for ({} = {}; {}; {} = {})", var, init, cond, var, step)
+            },
+            Generate::If{ref cond, ..} => write!(f, "if ({})", cond),
+            Generate::Case{ref expr, ..} => write!(f, "case ({})", expr),
+        }
+    }
+}
+
+impl Generate {
+
+    // every block that might be built, taken or not
+    pub fn blocks(&self) -> Vec<&GenBlock> {
+        match *self {
+            Generate::For{ref block, ..} => vec![block],
+            Generate::If{ref then_block, ref else_block, ..} => {
+                let mut blocks = vec![then_block];
+                blocks.extend(else_block);
+                blocks
+            },
+            Generate::Case{ref arms, ref default, ..} => {
+                let mut blocks: Vec<&GenBlock> = arms.iter().map(|arm| &arm.1).collect();
+                blocks.extend(default);
+                blocks
+            },
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum ModuleItem {
+    Decl(Declaration),
+    Proc(Procedure),
+    Assign(ContinuousAssign),
+    Instance(Instance),
+    Gate(Gate),
+    Param(Parameter),
+    Defparam(Defparam),
+    Genvar(String),
+    Generate(Generate),
+    Subroutine(Subroutine),
+}
+
+// what a source file holds
+pub enum Description {
+    Module(Module),
+    Primitive(Primitive),
+}
+
+pub struct Module {
+    pub name: String,
+    pub ports: Vec<String>,
+    pub items: Vec<ModuleItem>,
+}
+
+impl Module {
+
+    // input, output or inout, if it's a port at all
+    pub fn direction(&self, port: &str) -> Option<DeclKind> {
+        for decl in self.decls() {
+            match decl.kind {
+                DeclKind::Input | DeclKind::Output | DeclKind::Inout if decl.name == port => {
+                    return Some(decl.kind);
+                },
+                _ => {},
+            }
+        }
+        None
+    }
+
+    pub fn params(&self) -> Vec<&Parameter> {
+        let mut params: Vec<&Parameter> = vec![];
+        for item in &self.items {
+            if let ModuleItem::Param(ref param) = *item {
+                params.push(param);
+            }
+        }
+        params
+    }
+
+    pub fn decls(&self) -> Vec<&Declaration> {
+        let mut decls: Vec<&Declaration> = vec![];
+        for item in &self.items {
+            if let ModuleItem::Decl(ref decl) = *item {
+                decls.push(decl);
+            }
+        }
+        decls
+    }
+
+    #[allow(dead_code)]
+    pub fn show(&self) {
+        println!("module {} ({})", self.name, self.ports.join(", "));
+        show_items(&self.items, " ");
+        println!("endmodule");
+    }
+}
+
+#[allow(dead_code)]
+fn show_items(items: &[ModuleItem], indent: &str) {
+    for item in items {
+        match *item {
+            ModuleItem::Decl(ref decl) => println!("{}{}", indent, decl),
+            ModuleItem::Proc(ref p) => p.show(),
+            ModuleItem::Assign(ref ca) => println!("{}{};", indent, ca),
+            ModuleItem::Instance(ref inst) => println!("{}{};", indent, inst),
+            ModuleItem::Gate(ref gate) => println!("{}{};", indent, gate),
+            ModuleItem::Param(ref param) => println!("{}{};", indent, param),
+            ModuleItem::Defparam(ref dp) => println!("{}{};", indent, dp),
+            ModuleItem::Genvar(ref var) => println!("{}genvar {};", indent, var),
+            ModuleItem::Subroutine(ref sub) => {
+                println!("{}{} {} ({})", indent, sub.kind, sub.name, sub.ports.join(", "));
+                for decl in &sub.decls {
+                    println!("{}  {}", indent, decl);
+                }
+                for (i, stmt) in sub.stmts.iter().enumerate() {
+                    println!("{}  {:3}: {}", indent, i, stmt);
+                }
+            },
+            ModuleItem::Generate(ref gen) => {
+                println!("{}{}", indent, gen);
+                for block in gen.blocks() {
+                    match block.name {
+                        Some(ref name) => println!("{}begin : {}", indent, name),
+                        None => println!("{}begin", indent),
+                    }
+                    show_items(&block.items, &format!("{}  ", indent));
+                    println!("{}end", indent);
+                }
+            },
+        }
+    }
+}
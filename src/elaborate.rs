@@ -0,0 +1,1019 @@
+//! Elaboration
+//!
+//! Flattens the module hierarchy into what the engine simulates. Every
+//! variable gets its hierarchical name, so `q` in instance `u1` of
+//! module `top` is `top.u1.q`. An input port becomes a continuous
+//! assignment across the instance boundary. An output or inout port on
+//! a whole net of its width becomes that net, so the drivers on both
+//! sides resolve together, strengths and all; any other output drives
+//! its connection through an assignment too.
+//!
+//! Parameters are worked out per instance, from the module's defaults,
+//! the `#(...)` overrides on the instance and any `defparam` aimed at
+//! it, and are then folded into the code as plain numbers.
+//!
+//! Instances of user-defined primitives don't get flattened: each one
+//! goes to the engine whole, its terminals named from the outside.
+//!
+//! Generate loops, ifs and cases are built here too. A named generate
+//! block is a scope, so the loop `for (...) begin : stage` makes
+//! `stage[0]`, `stage[1]`, ... each with its own copy of what's inside.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::mem;
+use std::rc::Rc;
+
+use design::*;
+use eval::{Scope, constant_bounds};
+use net::NetKind;
+use parser;
+use procedure::*;
+use value::{Logic, Type, MAX_WIDTH};
+
+// a generate loop going round more than this is probably stuck
+const MAX_GENERATE_LOOPS: usize = 100000;
+
+// the whole design, flattened
+#[derive(Default)]
+pub struct Design {
+    pub decls: Vec<Declaration>,
+    pub procs: Vec<Procedure>,
+    pub assigns: Vec<ContinuousAssign>,
+    pub gates: Vec<Gate>,
+    pub udps: Vec<PrimitiveInstance>,
+    pub subroutines: Vec<Subroutine>,
+}
+
+// Build the design under `top`, or under every module nobody
+// instantiates if there's no top given
+pub fn elaborate(descriptions: &[Description], top: Option<&str>) -> Result<Design, String> {
+    let mut modules: Vec<&Module> = vec![];
+    let mut library: HashMap<&str, &Module> = HashMap::new();
+    let mut primitives: HashMap<&str, Rc<Primitive>> = HashMap::new();
+    for d in descriptions {
+        let (name, taken) = match *d {
+            Description::Module(ref m) => {
+                modules.push(m);
+                (&m.name, library.insert(&m.name, m).is_some())
+            },
+            Description::Primitive(ref p) => {
+                (&p.name, primitives.insert(&p.name, Rc::new(p.clone())).is_some())
+            },
+        };
+        if taken || (library.contains_key(name.as_str()) && primitives.contains_key(name.as_str())) {
+            return Err(format!("module '{}' is defined more than once", name));
+        }
+    }
+
+    let tops: Vec<&Module> = match top {
+        Some(top) => match library.get(top) {
+            Some(m) => vec![*m],
+            None => return Err(format!("top module '{}' not found", top)),
+        },
+        None => {
+            let mut used: HashSet<&str> = HashSet::new();
+            for m in &modules {
+                instantiated(&m.items, &mut used);
+            }
+            modules.into_iter().filter(|m| !used.contains(m.name.as_str())).collect()
+        },
+    };
+    if tops.is_empty() {
+        return Err("no modules to simulate".to_string());
+    }
+
+    let mut elab = Elaborator {
+        library,
+        primitives,
+        roots: tops.iter().map(|m| m.name.clone()).collect(),
+        stack: vec![],
+        defparams: HashMap::new(),
+        levels: vec![],
+        ports: vec![],
+        design: Design::default(),
+    };
+    for m in tops {
+        note!("*INFO* Top module is {}", m.name);
+        elab.module(m, &m.name, HashMap::new())?;
+    }
+    if let Some(path) = elab.defparams.keys().min() {
+        return Err(format!("defparam {}: no such parameter", path));
+    }
+    connect_ports(&mut elab.design, elab.ports)?;
+    check_calls(&mut elab.design)?;
+    Ok(elab.design)
+}
+
+struct Elaborator<'a> {
+    library: HashMap<&'a str, &'a Module>,
+    primitives: HashMap<&'a str, Rc<Primitive>>,
+    roots: Vec<String>, // the top modules' names
+    stack: Vec<String>, // the modules we're inside, to catch recursion
+    defparams: HashMap<String, Value>, // by full name, until they're used
+    levels: Vec<Level>, // the generate blocks we're inside, in this module
+    ports: Vec<Port>, // outputs and inouts, connected once everything is declared
+    design: Design,
+}
+
+// an output or inout port of instance `inst`, and what it's connected to
+struct Port {
+    inst: String,
+    name: String,
+    kind: DeclKind,
+    outer: Expression,
+}
+
+// a scope inside a module instance: the instance itself, or a
+// generate block in it
+#[derive(Clone)]
+struct Level {
+    path: String,
+    names: HashSet<String>,   // declared here, so found here
+    genvars: HashSet<String>,
+}
+
+impl Level {
+
+    fn new(path: &str, items: &[ModuleItem]) -> Level {
+        let mut level = Level {
+            path: path.to_string(),
+            names: HashSet::new(),
+            genvars: HashSet::new(),
+        };
+        level.declare(items);
+        level
+    }
+
+    // unnamed generate blocks don't make a scope, so what they
+    // declare belongs out here
+    fn declare(&mut self, items: &[ModuleItem]) {
+        for item in items {
+            match *item {
+                ModuleItem::Decl(ref decl) => {
+                    self.names.insert( block_name(&decl.name) );
+                },
+                ModuleItem::Instance(ref inst) => {
+                    self.names.insert(inst.name.clone());
+                },
+                ModuleItem::Subroutine(ref sub) => {
+                    self.names.insert(sub.name.clone());
+                },
+                ModuleItem::Genvar(ref var) => {
+                    self.genvars.insert(var.clone());
+                },
+                ModuleItem::Generate(ref gen) => {
+                    for block in gen.blocks() {
+                        match block.name {
+                            Some(ref name) => {
+                                self.names.insert(name.clone());
+                            },
+                            None => self.declare(&block.items),
+                        }
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+impl<'a> Elaborator<'a> {
+
+    // everything in module `m`, as the instance at `path`, with
+    // `overrides` from the instance's #(...)
+    fn module(&mut self, m: &Module, path: &str, overrides: HashMap<String, Value>)
+        -> Result<(), String>
+    {
+        if self.stack.contains(&m.name) {
+            return Err(format!("{}: module {} can't contain itself", path, m.name));
+        }
+        self.stack.push(m.name.clone());
+        let params = self.parameters(m, path, overrides)?;
+
+        // generate blocks in the parent module are no help in here
+        let levels = mem::replace(&mut self.levels, vec![ Level::new(path, &m.items) ]);
+        let result = self.items(&m.items, path, &params);
+        self.levels = levels;
+        result?;
+        self.stack.pop();
+        Ok(())
+    }
+
+    // the items of a module or a generate block, where `path` is the
+    // innermost scope
+    fn items(&mut self, items: &[ModuleItem], path: &str, params: &HashMap<String, Value>)
+        -> Result<(), String>
+    {
+        let levels = self.levels.clone();
+        let roots = self.roots.clone();
+        let rename = |var: &str| resolve(&levels, var, &roots);
+        let nested = self.levels.len() > 1;
+        let mut params = params.clone();
+
+        // defparams here reach down into the instances below, and the
+        // highest one wins
+        for item in items {
+            if let ModuleItem::Defparam(ref dp) = *item {
+                let value = constant(&dp.value, &params, path)?;
+                self.defparams.entry(rename(&dp.path)).or_insert(value);
+            }
+        }
+
+        for item in items {
+            match *item {
+                ModuleItem::Decl(ref decl) => {
+                    let mut decl = decl.clone();
+                    decl.name = rename(&decl.name);
+                    decl.range = vector_range(&decl, &params, path)?;
+                    decl.array = constant_range(&decl.array, &params, path)?;
+                    self.design.decls.push(decl);
+                },
+                ModuleItem::Proc(ref p) => {
+                    let mut p = p.clone();
+                    let folder = Folder::new(&params);
+                    p.rewrite(&folder);
+                    // @(g[i].q) isn't an operand
+                    p.rename(&|var: &str| folder.scopes(var));
+                    folder.finish(path)?;
+                    p.rename(&rename);
+                    p.scope = path.to_string();
+                    self.design.procs.push(p);
+                },
+                ModuleItem::Assign(ref ca) => {
+                    let mut ca = ca.clone();
+                    let folder = Folder::new(&params);
+                    ca.rewrite(&folder);
+                    folder.finish(path)?;
+                    ca.rename(&rename);
+                    self.design.assigns.push(ca);
+                },
+                ModuleItem::Instance(ref inst) => {
+                    self.instance(inst, path, &params)?;
+                },
+                ModuleItem::Gate(ref gate) => {
+                    let mut gate = gate.clone();
+                    for dly in &mut gate.delays {
+                        *dly = Expression::Const(Operand::Literal(constant(dly, &params, path)?));
+                    }
+                    let folder = Folder::new(&params);
+                    for terminal in &mut gate.terminals {
+                        terminal.rewrite(&folder);
+                        terminal.rename(&rename);
+                    }
+                    folder.finish(path)?;
+                    self.design.gates.push(gate);
+                },
+                ModuleItem::Generate(ref gen) => {
+                    self.generate(gen, path, &params)?;
+                },
+                ModuleItem::Subroutine(ref sub) => {
+                    self.subroutine(sub, path, &params)?;
+                },
+                ModuleItem::Param(ref param) if nested => {
+                    // the module's own were worked out up front, these
+                    // are local to a generate block
+                    let value = constant(&param.value, &params, path)?;
+                    info!("*INFO* Parameter {}.{} = {}", path, param.name, value);
+                    params.insert(param.name.clone(), value);
+                },
+                ModuleItem::Param(_) |
+                ModuleItem::Defparam(_) |
+                ModuleItem::Genvar(_) => {},
+            }
+        }
+        Ok(())
+    }
+
+    // a task or function, whose arguments and variables live inside it
+    fn subroutine(&mut self, sub: &Subroutine, path: &str, params: &HashMap<String, Value>)
+        -> Result<(), String>
+    {
+        let full = format!("{}.{}", path, sub.name);
+        let outer = self.levels.clone();
+        let mut levels = self.levels.clone();
+        levels.push( Level {
+            path: full.clone(),
+            names: sub.decls.iter().map(|d| block_name(&d.name)).collect(),
+            genvars: HashSet::new(),
+        });
+        let roots = self.roots.clone();
+        let rename = |var: &str| resolve(&levels, var, &roots);
+        let calls = |name: &str| resolve(&outer, name, &roots);
+
+        let mut sub = sub.clone();
+        for decl in &mut sub.decls {
+            decl.name = rename(&decl.name);
+            decl.range = vector_range(decl, params, path)?;
+            self.design.decls.push(decl.clone());
+        }
+        for port in &mut sub.ports {
+            *port = rename(port);
+        }
+        let folder = Folder::new(params);
+        for stmt in Rc::make_mut(&mut sub.stmts).iter_mut() {
+            stmt.rewrite(&folder);
+            stmt.rename_apart(&|var: &str| rename(&folder.scopes(var)), &calls);
+        }
+        folder.finish(&full)?;
+        sub.name = full;
+
+        if sub.kind == SubroutineKind::Function {
+            for stmt in sub.stmts.iter() {
+                match *stmt {
+                    Statement::Delay{..} | Statement::AtChange{..} | Statement::AtExpr{..} |
+                    Statement::Wait{..} | Statement::Call{..} | Statement::Fork{..} |
+                    Statement::NonBlockingAfter{..} => {
+                        return Err(format!("{}: a function can't wait, fork or call a task: {}",
+                            sub.name, stmt));
+                    },
+                    _ => {},
+                }
+            }
+        }
+        self.design.subroutines.push(sub);
+        Ok(())
+    }
+
+    fn generate(&mut self, gen: &Generate, path: &str, params: &HashMap<String, Value>)
+        -> Result<(), String>
+    {
+        match *gen {
+            Generate::For{ref var, ref init, ref cond, ref step, ref block} => {
+                if !self.levels.iter().any(|level| level.genvars.contains(var)) {
+                    return Err(format!("{}: {} isn't declared as a genvar", path, var));
+                }
+                let name = block.name.clone().unwrap_or_default();
+                let mut params = params.clone();
+                let mut value = constant(init, &params, path)?;
+                let mut built: HashSet<u64> = HashSet::new();
+                loop {
+                    params.insert(var.clone(), value.clone());
+                    match constant(cond, &params, path)?.truth() {
+                        Logic::One => {},
+                        Logic::Zero => break,
+                        _ => return Err(format!("{}: generate loop condition {} is unknown",
+                            path, cond)),
+                    }
+                    let n = match value.to_u64() {
+                        Some(n) => n,
+                        None => return Err(format!("{}: genvar {} is unknown", path, var)),
+                    };
+                    if !built.insert(n) {
+                        return Err(format!("{}: {}[{}] is generated twice", path, name, n));
+                    }
+                    if built.len() > MAX_GENERATE_LOOPS {
+                        return Err(format!("{}: generate loop over {} doesn't finish", path, var));
+                    }
+                    let scope = format!("{}[{}]", name, n);
+                    self.block(&block.items, &format!("{}.{}", path, scope), &params)?;
+                    value = constant(step, &params, path)?;
+                }
+                Ok(())
+            },
+            Generate::If{ref cond, ref then_block, ref else_block} => {
+                match constant(cond, params, path)?.truth() {
+                    Logic::One => self.gen_block(then_block, path, params),
+                    Logic::Zero => match *else_block {
+                        Some(ref block) => self.gen_block(block, path, params),
+                        None => Ok(()),
+                    },
+                    _ => Err(format!("{}: generate condition {} is unknown", path, cond)),
+                }
+            },
+            Generate::Case{ref expr, ref arms, ref default} => {
+                let value = constant(expr, params, path)?;
+                for (labels, block) in arms {
+                    for label in labels {
+                        let label = constant(label, params, path)?;
+                        if value.case_eq(&label).truth() == Logic::One {
+                            return self.gen_block(block, path, params);
+                        }
+                    }
+                }
+                match *default {
+                    Some(ref block) => self.gen_block(block, path, params),
+                    None => Ok(()),
+                }
+            },
+        }
+    }
+
+    // the chosen block of a generate if or case
+    fn gen_block(&mut self, block: &GenBlock, path: &str, params: &HashMap<String, Value>)
+        -> Result<(), String>
+    {
+        match block.name {
+            Some(ref name) => self.block(&block.items, &format!("{}.{}", path, name), params),
+            None => self.items(&block.items, path, params),
+        }
+    }
+
+    // a named generate block, a scope of its own
+    fn block(&mut self, items: &[ModuleItem], path: &str, params: &HashMap<String, Value>)
+        -> Result<(), String>
+    {
+        self.levels.push( Level::new(path, items) );
+        let result = self.items(items, path, params);
+        self.levels.pop();
+        result
+    }
+
+    // the parameters of the instance at `path`, in the order they're
+    // declared so each can use the ones before it
+    fn parameters(&mut self, m: &Module, path: &str, overrides: HashMap<String, Value>)
+        -> Result<HashMap<String, Value>, String>
+    {
+        let mut params: HashMap<String, Value> = HashMap::new();
+        for item in &m.items {
+            let param = match *item {
+                ModuleItem::Param(ref param) => param,
+                _ => continue,
+            };
+            let full = format!("{}.{}", path, param.name);
+            let value = match self.defparams.remove(&full) {
+                Some(_) if param.local => {
+                    return Err(format!("defparam {}: can't override a localparam", full));
+                },
+                Some(value) => value,
+                None => match overrides.get(&param.name) {
+                    Some(value) => value.clone(),
+                    None => constant(&param.value, &params, path)?,
+                },
+            };
+            let value = match param.range {
+                Some((ref msb, ref lsb)) => {
+                    let msb = constant(msb, &params, path)?.to_u64();
+                    let lsb = constant(lsb, &params, path)?.to_u64();
+                    match (msb, lsb) {
+                        (Some(msb), Some(lsb)) => {
+                            // a range makes it unsigned, otherwise it's what its value is
+                            value.resize((msb.max(lsb) - msb.min(lsb) + 1) as usize)
+                                .with_type(Type::Unsigned)
+                        },
+                        _ => return Err(format!("{}: parameter {} has an unknown range", path, param.name)),
+                    }
+                },
+                None => value,
+            };
+            info!("*INFO* Parameter {} = {}", full, value);
+            params.insert(param.name.clone(), value);
+        }
+        Ok(params)
+    }
+
+    // hook up an instance's ports, then build what's inside it
+    fn instance(&mut self, inst: &Instance, path: &str, params: &HashMap<String, Value>)
+        -> Result<(), String>
+    {
+        let child = match self.library.get(inst.module.as_str()) {
+            Some(m) => *m,
+            None => match self.primitives.get(inst.module.as_str()) {
+                Some(prim) => return self.primitive(inst, prim.clone(), path, params),
+                None => return Err(format!("{}: unknown module '{}'", path, inst.module)),
+            },
+        };
+        let inst_path = format!("{}.{}", path, inst.name);
+
+        // overrides are worked out in here, before going inside
+        let mut overrides: HashMap<String, Value> = HashMap::new();
+        match inst.parameters {
+            Connections::Positional(ref exprs) => {
+                let settable: Vec<&Parameter> = child.params().into_iter()
+                    .filter(|p| !p.local).collect();
+                if exprs.len() > settable.len() {
+                    return Err(format!("{}: {} has {} parameters but {} are given",
+                        inst_path, child.name, settable.len(), exprs.len()));
+                }
+                for (param, expr) in settable.iter().zip(exprs) {
+                    if let Some(ref expr) = *expr {
+                        overrides.insert(param.name.clone(), constant(expr, params, path)?);
+                    }
+                }
+            },
+            Connections::Named(ref names) => {
+                for (name, expr) in names {
+                    match child.params().iter().find(|p| p.name == *name) {
+                        None => return Err(format!("{}: {} has no parameter '{}'",
+                            inst_path, child.name, name)),
+                        Some(p) if p.local => return Err(format!(
+                            "{}: can't override localparam '{}'", inst_path, name)),
+                        Some(_) => {},
+                    }
+                    if let Some(ref expr) = *expr {
+                        overrides.insert(name.clone(), constant(expr, params, path)?);
+                    }
+                }
+            },
+        }
+
+        // pair up the ports with what they're connected to
+        let mut conns: Vec<(String, Expression)> = vec![];
+        match inst.connections {
+            Connections::Positional(ref exprs) => {
+                if exprs.len() > child.ports.len() {
+                    return Err(format!("{}: {} has {} ports but {} are connected",
+                        inst_path, child.name, child.ports.len(), exprs.len()));
+                }
+                for (port, expr) in child.ports.iter().zip(exprs) {
+                    if let Some(ref expr) = *expr {
+                        conns.push( (port.clone(), expr.clone()) );
+                    }
+                }
+            },
+            Connections::Named(ref ports) => {
+                for (port, expr) in ports {
+                    if !child.ports.contains(port) {
+                        return Err(format!("{}: {} has no port '{}'",
+                            inst_path, child.name, port));
+                    }
+                    if let Some(ref expr) = *expr {
+                        conns.push( (port.clone(), expr.clone()) );
+                    }
+                }
+            },
+        }
+
+        let levels = self.levels.clone();
+        let roots = self.roots.clone();
+        let rename = |var: &str| resolve(&levels, var, &roots);
+        for (port, mut outer) in conns {
+            let folder = Folder::new(params);
+            outer.rewrite(&folder);
+            folder.finish(path)?;
+            outer.rename(&rename);
+            match child.direction(&port) {
+                Some(DeclKind::Input) => {
+                    let inner = Operand::Identifier(format!("{}.{}", inst_path, port));
+                    self.design.assigns.push(ContinuousAssign::new(inner, outer, None));
+                },
+                Some(kind) => {
+                    if outer.lvalue().is_none() {
+                        return Err(format!("{}: {} port '{}' can't drive {}",
+                            inst_path, kind, port, outer));
+                    }
+                    self.ports.push(Port { inst: inst_path.clone(), name: port, kind, outer });
+                },
+                None => return Err(format!("{}: port '{}' of {} has no direction",
+                    inst_path, port, child.name)),
+            }
+        }
+
+        self.module(child, &inst_path, overrides)
+    }
+
+    // an instance of a primitive: the #(...) are its delays, and there's
+    // a terminal for each port in order
+    fn primitive(&mut self, inst: &Instance, prim: Rc<Primitive>, path: &str,
+        params: &HashMap<String, Value>) -> Result<(), String>
+    {
+        let inst_path = format!("{}.{}", path, inst.name);
+        let mut delays: Vec<Expression> = vec![];
+        match inst.parameters {
+            Connections::Positional(ref exprs) if exprs.len() <= 2 => {
+                for expr in exprs.iter().flatten() {
+                    delays.push( Expression::Const(Operand::Literal(constant(expr, params, path)?)) );
+                }
+            },
+            Connections::Positional(_) => {
+                return Err(format!("{}: a primitive has at most 2 delays: rise and fall", inst_path));
+            },
+            Connections::Named(_) => {
+                return Err(format!("{}: {} is a primitive, it has no parameters",
+                    inst_path, prim.name));
+            },
+        }
+        let exprs = match inst.connections {
+            Connections::Positional(ref exprs) => exprs,
+            Connections::Named(_) => {
+                return Err(format!("{}: the ports of primitive {} can only be connected in order",
+                    inst_path, prim.name));
+            },
+        };
+        if exprs.len() != prim.ports.len() || exprs.iter().any(|e| e.is_none()) {
+            return Err(format!("{}: {} has {} ports and they all have to be connected",
+                inst_path, prim.name, prim.ports.len()));
+        }
+
+        let levels = self.levels.clone();
+        let roots = self.roots.clone();
+        let rename = |var: &str| resolve(&levels, var, &roots);
+        let folder = Folder::new(params);
+        let mut terminals: Vec<Expression> = vec![];
+        for expr in exprs.iter().flatten() {
+            let mut expr = expr.clone();
+            expr.rewrite(&folder);
+            expr.rename(&rename);
+            terminals.push(expr);
+        }
+        folder.finish(path)?;
+        if terminals[0].lvalue().is_none() {
+            return Err(format!("{}: output {} can't be driven", inst_path, terminals[0]));
+        }
+        self.design.udps.push( PrimitiveInstance {
+            primitive: prim,
+            name: inst_path,
+            delays,
+            terminals,
+        });
+        Ok(())
+    }
+}
+
+// evaluates constant expressions, where only parameters may appear
+struct Constants<'a> {
+    params: &'a HashMap<String, Value>,
+}
+
+impl<'a> Scope for Constants<'a> {
+
+    fn value_of(&self, var: &str) -> Value {
+        self.params.get(var).cloned().unwrap_or_else(|| Value::x(1))
+    }
+
+    fn var_width(&self, var: &str) -> usize {
+        self.params.get(var).map_or(1, |v| v.width())
+    }
+
+    fn var_type(&self, var: &str) -> Type {
+        self.params.get(var).map_or(Type::Unsigned, |v| v.ty())
+    }
+
+    fn var_offset(&self, var: &str, i: usize) -> Option<usize> {
+        if i < self.var_width(var) {
+            Some(i)
+        } else {
+            None
+        }
+    }
+}
+
+fn constant(expr: &Expression, params: &HashMap<String, Value>, path: &str)
+    -> Result<Value, String>
+{
+    for var in expr.get_identifiers() {
+        if !params.contains_key(&var) {
+            return Err(format!("{}: {} is not a constant, '{}' isn't a parameter",
+                path, expr, var));
+        }
+    }
+    Ok( Constants { params }.evaluate_self(expr) )
+}
+
+// a declaration's [msb:lsb] or [first:last] with the parameters folded in
+fn constant_range(range: &Option<(Expression, Expression)>, params: &HashMap<String, Value>,
+    path: &str) -> Result<Option<(Expression, Expression)>, String>
+{
+    match *range {
+        Some((ref a, ref b)) => Ok(Some((
+            Expression::Const(Operand::Literal(constant(a, params, path)?)),
+            Expression::Const(Operand::Literal(constant(b, params, path)?)),
+        ))),
+        None => Ok(None),
+    }
+}
+
+// a declaration's [msb:lsb], which has to fit in a Value
+fn vector_range(decl: &Declaration, params: &HashMap<String, Value>, path: &str)
+    -> Result<Option<(Expression, Expression)>, String>
+{
+    let range = constant_range(&decl.range, params, path)?;
+    if let Some((Expression::Const(Operand::Literal(ref msb)),
+                 Expression::Const(Operand::Literal(ref lsb)))) = range
+    {
+        if let (Some(msb), Some(lsb)) = (msb.to_u64(), lsb.to_u64()) {
+            let width = msb.abs_diff(lsb) + 1;
+            if width > MAX_WIDTH as u64 {
+                return Err(format!("{} is {} bits wide, vectors wider than {} bits are not supported",
+                    decl.name, width, MAX_WIDTH));
+            }
+        }
+    }
+    Ok(range)
+}
+
+// swaps parameters for their values, and works out part select bounds
+struct Folder<'a> {
+    params: &'a HashMap<String, Value>,
+    errors: RefCell<Vec<String>>,
+}
+
+impl<'a> Folder<'a> {
+
+    fn new(params: &'a HashMap<String, Value>) -> Folder<'a> {
+        Folder { params, errors: RefCell::new(vec![]) }
+    }
+
+    // "g[i - 1].o" inside a generate loop is "g[2].o" once i is known
+    fn scopes(&self, var: &str) -> String {
+        let mut name = String::new();
+        let mut index = String::new();
+        let mut depth = 0;
+        for c in var.chars() {
+            match c {
+                '[' if depth == 0 => {
+                    depth = 1;
+                    name.push(c);
+                    continue;
+                },
+                '[' => depth += 1,
+                ']' if depth == 1 => {
+                    depth = 0;
+                    name.push_str( &self.scope_index(var, &index) );
+                    index.clear();
+                },
+                ']' => depth -= 1,
+                _ => {},
+            }
+            if depth > 0 {
+                index.push(c);
+            } else {
+                name.push(c);
+            }
+        }
+        name
+    }
+
+    fn scope_index(&self, var: &str, index: &str) -> String {
+        if index.parse::<u64>().is_ok() {
+            return index.to_string();
+        }
+        let expr = match parser::parse_expression(index) {
+            Ok(expr) => expr,
+            Err(why) => {
+                self.errors.borrow_mut().push(format!("{}: {}", var, why.msg));
+                return index.to_string();
+            },
+        };
+        let n = if expr.get_identifiers().iter().all(|v| self.params.contains_key(v)) {
+            Constants { params: self.params }.evaluate_self(&expr).to_u64()
+        } else {
+            None
+        };
+        match n {
+            Some(n) => n.to_string(),
+            None => {
+                self.errors.borrow_mut().push(format!(
+                    "{}: generate scope index {} isn't a constant", var, index));
+                index.to_string()
+            },
+        }
+    }
+
+    fn finish(self, path: &str) -> Result<(), String> {
+        match self.errors.into_inner().into_iter().next() {
+            Some(error) => Err(format!("{}: {}", path, error)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a> Rewrite for Folder<'a> {
+
+    // called innermost first, so bounds only have numbers left in them
+    fn operand(&self, op: &mut Operand) {
+        op.rename(&|var: &str| self.scopes(var));
+        if let Operand::PartSelect(ref var, ref mut msb, ref mut lsb) = *op {
+            let fold_bound = |bound: &mut Expression| {
+                if bound.get_identifiers().is_empty() {
+                    let value = Constants { params: self.params }.evaluate_self(bound);
+                    *bound = Expression::Const(Operand::Literal(value));
+                } else {
+                    self.errors.borrow_mut().push(format!(
+                        "part select of {} has a bound that isn't constant: {}", var, bound));
+                }
+            };
+            fold_bound(msb);
+            fold_bound(lsb);
+        }
+        let vars = op.get_identifiers();
+        if !vars.is_empty() && vars.iter().all(|var| self.params.contains_key(var)) {
+            let value = Constants { params: self.params }.lookup(op);
+            *op = Operand::Literal(value);
+        }
+    }
+}
+
+// every task and function call has to have something to call, and
+// the right arguments for it
+// Outputs and inouts on a whole net of the same width become that net:
+// their own name is dropped and everything uses the outer one. Other
+// outputs get an assignment to what they're connected to, which can't
+// work the other way, so an inout has to be on a whole net.
+fn connect_ports(design: &mut Design, ports: Vec<Port>) -> Result<(), String> {
+    let mut aliases: HashMap<String, String> = HashMap::new();
+    for port in ports {
+        let inner = format!("{}.{}", port.inst, port.name);
+        match port.outer {
+            Expression::Const(Operand::Identifier(ref outer))
+                if net_width(&design.decls, &inner).is_some() &&
+                   net_width(&design.decls, &inner) == net_width(&design.decls, outer) =>
+            {
+                if *outer != inner {
+                    aliases.insert(inner, outer.clone());
+                }
+            },
+            _ if port.kind == DeclKind::Inout => {
+                return Err(format!("{}: inout port '{}' has to be connected to a whole net of its width, not {}",
+                    port.inst, port.name, port.outer));
+            },
+            ref outer => {
+                let id = outer.lvalue().expect("checked when the instance was built");
+                let inner = Expression::Const(Operand::Identifier(inner));
+                design.assigns.push(ContinuousAssign::new(id, inner, None));
+            },
+        }
+    }
+    if aliases.is_empty() {
+        return Ok(());
+    }
+
+    // a port on a port on a net is that net
+    let names: Vec<String> = aliases.keys().cloned().collect();
+    for name in names {
+        let mut net = aliases[&name].clone();
+        for _ in 0..aliases.len() {
+            match aliases.get(&net) {
+                Some(outer) => net = outer.clone(),
+                None => break,
+            }
+        }
+        aliases.insert(name, net);
+    }
+
+    // a tri0 inside a plain wire makes the net a tri0, as the standard has it
+    let mut kinds: Vec<Declaration> = vec![];
+    for decl in &design.decls {
+        if let (Some(net), DeclKind::Net(kind)) = (aliases.get(&decl.name), decl.kind) {
+            if !plain(kind) && !design.decls.iter().any(|d| d.name == *net && !plain_net(d.kind)) {
+                kinds.push(Declaration {
+                    kind: decl.kind, name: net.clone(), range: None, signed: false, array: None,
+                });
+            }
+        }
+    }
+    design.decls.retain(|d| !aliases.contains_key(&d.name));
+    design.decls.append(&mut kinds);
+
+    let rename = |var: &str| aliases.get(var).cloned().unwrap_or_else(|| var.to_string());
+    let same = |name: &str| name.to_string();
+    for p in &mut design.procs {
+        p.rename(&rename);
+    }
+    for ca in &mut design.assigns {
+        ca.rename(&rename);
+    }
+    for term in design.gates.iter_mut().flat_map(|g| g.terminals.iter_mut())
+        .chain(design.udps.iter_mut().flat_map(|u| u.terminals.iter_mut()))
+    {
+        term.rename(&rename);
+    }
+    for sub in &mut design.subroutines {
+        for stmt in Rc::make_mut(&mut sub.stmts).iter_mut() {
+            stmt.rename_apart(&rename, &same);
+        }
+    }
+    Ok(())
+}
+
+// how wide `name` is if it's a net, and not a variable or a memory;
+// a name nobody declared is a one bit wire
+fn net_width(decls: &[Declaration], name: &str) -> Option<usize> {
+    let mut width = 1;
+    for decl in decls.iter().filter(|d| d.name == name) {
+        match decl.kind {
+            DeclKind::Input | DeclKind::Output | DeclKind::Inout | DeclKind::Net(_) => {},
+            _ => return None,
+        }
+        if decl.array.is_some() {
+            return None;
+        }
+        if let Some((ref msb, ref lsb)) = decl.range {
+            let (msb, lsb) = constant_bounds(msb, lsb)?;
+            width = msb.abs_diff(lsb) + 1;
+        }
+    }
+    Some(width)
+}
+
+// a wire or tri, which gives way to any other kind of net
+fn plain(kind: NetKind) -> bool {
+    kind == NetKind::Wire || kind == NetKind::Tri
+}
+
+// anything but a tri0, wand, supply1...
+fn plain_net(kind: DeclKind) -> bool {
+    match kind {
+        DeclKind::Net(kind) => plain(kind),
+        _ => true,
+    }
+}
+
+fn check_calls(design: &mut Design) -> Result<(), String> {
+    let checker = Checker {
+        subroutines: design.subroutines.iter().map(|s| (s.name.clone(), s.clone())).collect(),
+        errors: RefCell::new(vec![]),
+    };
+    for p in &mut design.procs {
+        p.rewrite(&checker);
+    }
+    for ca in &mut design.assigns {
+        ca.rewrite(&checker);
+    }
+    for sub in &mut design.subroutines {
+        for stmt in Rc::make_mut(&mut sub.stmts).iter_mut() {
+            stmt.rewrite(&checker);
+        }
+    }
+    match checker.errors.into_inner().into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+struct Checker {
+    subroutines: HashMap<String, Subroutine>,
+    errors: RefCell<Vec<String>>,
+}
+
+impl Checker {
+
+    fn check(&self, kind: SubroutineKind, name: &str, args: &[Expression]) {
+        let sub = match self.subroutines.get(name) {
+            Some(sub) if sub.kind == kind => sub,
+            _ => {
+                self.errors.borrow_mut().push(format!("no {} called {}", kind, name));
+                return;
+            },
+        };
+        if args.len() != sub.ports.len() {
+            self.errors.borrow_mut().push(format!("{} takes {} arguments but is given {}",
+                name, sub.ports.len(), args.len()));
+            return;
+        }
+        for (port, arg) in sub.ports.iter().zip(args) {
+            let dir = sub.direction(port);
+            if dir != Some(DeclKind::Input) && arg.lvalue().is_none() {
+                self.errors.borrow_mut().push(format!("{}: argument {} can't be assigned to {}",
+                    name, arg, port));
+            }
+        }
+    }
+}
+
+impl Rewrite for Checker {
+
+    fn function(&self, name: &mut String, args: &[Expression]) {
+        self.check(SubroutineKind::Function, name, args);
+    }
+
+    fn task(&self, name: &mut String, args: &[Expression]) {
+        self.check(SubroutineKind::Task, name, args);
+    }
+}
+
+// the modules instantiated in `items`, in any generate block that
+// might be built
+fn instantiated<'a>(items: &'a [ModuleItem], used: &mut HashSet<&'a str>) {
+    for item in items {
+        match *item {
+            ModuleItem::Instance(ref inst) => {
+                used.insert(&inst.module);
+            },
+            ModuleItem::Generate(ref gen) => {
+                for block in gen.blocks() {
+                    instantiated(&block.items, used);
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+// the full name of `var`, from the innermost scope that declares it
+fn resolve(levels: &[Level], var: &str, roots: &[String]) -> String {
+    let first = var.split(&['.', '['][..]).next().unwrap_or(var);
+    for level in levels.iter().rev() {
+        if level.names.contains(first) {
+            return format!("{}.{}", level.path, var);
+        }
+    }
+    hierarchical(&levels[0].path, var, roots)
+}
+
+// what a declaration puts in its scope: "k", or the block "main" for
+// "main.k", which was declared inside begin : main
+fn block_name(name: &str) -> String {
+    name.split('.').next().unwrap_or(name).to_string()
+}
+
+// the full name of `var` as seen from the instance at `path`; names
+// starting at a top module are already as full as they get
+fn hierarchical(path: &str, var: &str, roots: &[String]) -> String {
+    match var.split('.').next() {
+        Some(first) if var.contains('.') && roots.iter().any(|r| r == first) => var.to_string(),
+        _ => format!("{}.{}", path, var),
+    }
+}
+
@@ -2,24 +2,270 @@
 use procedure::*;
 use timeheap::*;
 use vcd::*;
+use value::{Logic, Type, MAX_WIDTH};
+use eval::{Scope, constant_bounds};
+use design::{Declaration, DeclKind, Gate, PrimitiveInstance, Subroutine, SubroutineKind};
+use elaborate::Design;
+use gate::{self, GateKind};
+use net::{self, DriveStrength, NetKind};
+use format::{self, Arg};
+use memfile::{self, Item};
+use output::{Sink, Stdout};
+use logging;
 
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
 use std::collections::VecDeque;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::iter;
+use std::mem;
+use std::rc::Rc;
+use std::slice;
 
 
 //use procedure::Value;
 
+// more delta cycles than this in one time step is probably a loop
+pub const DEFAULT_MAX_DELTAS: usize = 10000;
+
+// and a procedure that runs more than this many statements per delta
+// cycle allowed without waiting is probably going round a zero-delay
+// loop, "always c = ~c;", that would never hand back to the scheduler
+const STATEMENTS_PER_DELTA: usize = 100;
+
+// task and function calls nested deeper than this are runaway recursion
+const MAX_CALL_DEPTH: usize = 1000;
+
+// why the simulation stopped
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum RunResult {
+    Finish,       // $finish
+    Stop,         // $stop
+    EventStarved, // nothing left to do
+    TimeLimit,    // the next event is past the max time
+    DeltaLimit,   // a time step that wouldn't settle
+}
+
+impl fmt::Display for RunResult {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RunResult::Finish => write!(f, "$finish called"),
+            RunResult::Stop => write!(f, "$stop called"),
+            RunResult::EventStarved => write!(f, "no more events"),
+            RunResult::TimeLimit => write!(f, "time limit reached"),
+            RunResult::DeltaLimit => write!(f, "delta cycle limit reached"),
+        }
+    }
+}
+
+// what sits in the active queue
+enum Event {
+    Resume(ProcId),      // carry on running a procedure
+    Execute(Statement),  // eg a nonblocking assignment's update
+    Update(AssignId),    // re-evaluate a continuous assignment
+    Gate(GateId),        // and a gate
+    Udp(UdpId),          // or a user-defined primitive
+    Drive(AssignId, Value), // a delayed continuous assignment landing
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Event::Resume(pid) => write!(f, "resume procedure {}", pid),
+            Event::Execute(ref stmt) => write!(f, "{}", stmt),
+            Event::Update(aid) => write!(f, "update continuous assignment {}", aid),
+            Event::Gate(gid) => write!(f, "evaluate gate {}", gid),
+            Event::Udp(uid) => write!(f, "evaluate primitive {}", uid),
+            Event::Drive(aid, ref value) => write!(f, "continuous assignment {} drives {}",
+                aid, value),
+        }
+    }
+}
+
+// what sits in the time heap
+enum Wakeup {
+    Resume(ProcId),
+    // a delayed continuous assignment update, only good if nothing
+    // has been scheduled for the same assignment since
+    Assign{aid: AssignId, ticket: usize, value: Value},
+    // "q <= #2 d", due in the nonblocking region then
+    Nba(Statement),
+    // a gate's output changing, unless it's changed its mind since
+    Gate{gid: GateId, ticket: usize, value: Logic},
+    // and a primitive's
+    Udp{uid: UdpId, ticket: usize, value: Logic},
+}
+
+type GateId = usize;
+
+// the bits of a variable that something reads, or that a change
+// touched: the lowest and highest offsets from the lsb, or all of them
+type Bits = Option<(usize, usize)>;
+
+fn overlap(a: Bits, b: Bits) -> bool {
+    match (a, b) {
+        (Some((lo, hi)), Some((other_lo, other_hi))) => lo <= other_hi && other_lo <= hi,
+        _ => true,
+    }
+}
+
+// a gate primitive, ready to be evaluated a bit at a time
+struct GateInst {
+    kind: GateKind,
+    outputs: Vec<Operand>,
+    inputs: Vec<Expression>,
+    delays: Vec<Time>,
+    out: Logic,    // what it drives, or will once the delay is up
+    ticket: usize, // latest scheduled change of output
+    gate: Gate,    // as written, for reports
+}
+
+type UdpId = usize;
+
+// what puts a value on a net; each has its own say in the net's value
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Driver {
+    Assign(AssignId),
+    Gate(GateId),
+    Udp(UdpId),
+}
+
+// an instance of a user-defined primitive, which looks its output up
+// in the table
+struct UdpInst {
+    output: Operand,
+    inputs: Vec<Expression>,
+    delays: Vec<Time>,
+    last: Vec<Logic>, // the inputs at the last look-up, for edges
+    state: Logic,     // the output, or where it's going
+    ticket: usize,
+    inst: PrimitiveInstance,
+}
+
+// $monitor: print the arguments whenever one of them changes
+struct Monitor {
+    args: Vec<Argument>,
+    scope: String,
+    last: Option<Vec<Arg>>, // what was printed last time
+    on: bool,               // $monitoron/$monitoroff
+}
+
+// what's been busy in the current time step, to point the finger
+// when it won't settle
+#[derive(Default)]
+struct Activity {
+    toggles: HashMap<String, usize>,  // variable changes
+    wakes: HashMap<ProcId, usize>,    // procedures resumed
+    updates: HashMap<AssignId, usize>, // continuous assignments evaluated
+    gates: HashMap<GateId, usize>,     // and gates
+    udps: HashMap<UdpId, usize>,       // and primitives
+}
+
+impl Activity {
+
+    fn clear(&mut self) {
+        self.toggles.clear();
+        self.wakes.clear();
+        self.updates.clear();
+        self.gates.clear();
+        self.udps.clear();
+    }
+}
+
+// the busiest few, busiest first
+fn busiest<K: Clone + Ord>(counts: &HashMap<K, usize>) -> Vec<(K, usize)> {
+    let mut counts: Vec<(K, usize)> = counts.iter().map(|(k, n)| (k.clone(), *n)).collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts.truncate(REPORT_LENGTH);
+    counts
+}
+
+const REPORT_LENGTH: usize = 10;
+
+// what the engine knows about a declared variable
+struct Variable {
+    msb: usize,
+    lsb: usize,
+    net: bool, // undriven nets start as z, everything else as x
+    kind: NetKind, // how a net's drivers are resolved
+    array: Option<(usize, usize)>, // a memory's first and last word
+    ty: Type, // signed, unsigned or real
+}
+
+impl Variable {
+
+    fn width(&self) -> usize {
+        if self.msb >= self.lsb {
+            self.msb - self.lsb + 1
+        } else {
+            self.lsb - self.msb + 1
+        }
+    }
+
+    // a bit index as written in the source, as an offset from the lsb
+    fn offset(&self, i: usize) -> Option<usize> {
+        if self.msb >= self.lsb && i >= self.lsb && i <= self.msb {
+            Some(i - self.lsb)
+        } else if self.msb < self.lsb && i >= self.msb && i <= self.lsb {
+            Some(self.lsb - i)
+        } else {
+            None
+        }
+    }
+
+    fn holds(&self, addr: usize) -> bool {
+        match self.array {
+            Some((first, last)) => addr >= first.min(last) && addr <= first.max(last),
+            None => false,
+        }
+    }
+}
+
+// "mem[5]", the name word 5 of a memory goes by in the symbol table
+fn word_name(var: &str, addr: usize) -> String {
+    format!("{}[{}]", var, addr)
+}
+
 pub struct Engine {
     procedures: Vec<Procedure>,
-    q_active: VecDeque<Statement>,
+    running: Vec<ProcId>, // the procedure running now, and any function it's in
+    free: Vec<ProcId>, // finished forked threads, whose slots can be reused
+    subroutines: HashMap<String, Rc<Subroutine>>, // tasks and functions
+    automatic: HashSet<String>, // their variables that live in a call's frame
+    returned: Option<Value>, // the value of the function that just finished
+    assigns: Vec<ContinuousAssign>,
+    fanout: HashMap<String, Vec<(AssignId, Bits)>>, // which assigns read a variable, and which bits
+    tickets: Vec<usize>, // latest scheduled update of each assign
+    pending: Vec<Option<Value>>, // and the value it's bringing
+    gates: Vec<GateInst>,
+    gate_fanout: HashMap<String, Vec<(GateId, Bits)>>, // which gates read a variable
+    udps: Vec<UdpInst>,
+    udp_fanout: HashMap<String, Vec<(UdpId, Bits)>>,
+    drivers: HashMap<String, Vec<(Driver, Value, DriveStrength)>>, // on each net
+    q_active: VecDeque<Event>,
+    q_inactive: VecDeque<Event>, // #0 waits here
     q_nba: VecDeque<Statement>, // nonblocking assignments
+    q_postponed: VecDeque<(String, Vec<Argument>)>, // $strobes, printed once all is settled
+    monitor: Option<Monitor>,
+    output: Box<dyn Sink>, // where $display and friends write to
     symtable: HashMap<String, Value>,
+    variables: HashMap<String, Variable>, // declared ones, anyway
+    events: HashSet<String>, // named events, which have no value
     waiting: HashMap<Edge, HashSet<ProcId>>,
-    timeheap: TimeHeap,
+    watches: HashMap<ProcId, Vec<(EventExpr, Value)>>, // @(a & b) and its last value
+    timeheap: TimeHeap<Wakeup>,
     time: Time,
     vars: Vec<String>, // list of vars in the design
     dumper: Option<VcdWriter>, // created later
+    vcd_file: Option<String>, // where to dump, if anywhere
+    dump_memories: bool, // every word of every memory goes in the VCD too
+    max_time: Option<Time>, // don't simulate beyond this
+    max_deltas: Option<usize>, // nor spin on one time step longer than this
+    result: Option<RunResult>, // set once it's time to stop
+    activity: Activity, // this time step's, for the delta cycle report
+    errors: Vec<String>, // what couldn't be set up, init() reports them
 }
 
 
@@ -28,305 +274,1596 @@ impl Engine {
     pub fn new() -> Engine {
         Engine {
             symtable: HashMap::new(),
+            variables: HashMap::new(),
+            events: HashSet::new(),
             procedures: vec![],
+            running: vec![],
+            free: vec![],
+            subroutines: HashMap::new(),
+            automatic: HashSet::new(),
+            returned: None,
+            assigns: vec![],
+            fanout: HashMap::new(),
+            tickets: vec![],
+            pending: vec![],
+            gates: vec![],
+            gate_fanout: HashMap::new(),
+            udps: vec![],
+            udp_fanout: HashMap::new(),
+            drivers: HashMap::new(),
             waiting: HashMap::new(),
+            watches: HashMap::new(),
             q_active: VecDeque::new(),
+            q_inactive: VecDeque::new(),
             q_nba: VecDeque::new(),
+            q_postponed: VecDeque::new(),
+            monitor: None,
+            output: Box::new(Stdout),
             timeheap: TimeHeap::new(),
             time: 0,
             vars: vec![],
             dumper: None,
+            vcd_file: Some("waves.vcd".to_string()),
+            dump_memories: false,
+            max_time: None,
+            max_deltas: Some(DEFAULT_MAX_DELTAS),
+            result: None,
+            activity: Activity::default(),
+            errors: vec![],
+        }
+    }
+
+    // "output [7:0] q;" and "reg [7:0] q;" both describe the same q
+    pub fn declare(&mut self, decl: &Declaration) {
+        if decl.kind == DeclKind::Event {
+            self.events.insert(decl.name.clone());
+            return;
+        }
+        let var = self.variables.entry(decl.name.clone())
+            .or_insert(Variable {
+                msb: 0, lsb: 0, net: true, kind: NetKind::Wire, array: None, ty: Type::Unsigned,
+            });
+        if let Some((ref msb, ref lsb)) = decl.range {
+            match constant_bounds(msb, lsb) {
+                Some((msb, lsb)) => {
+                    var.msb = msb;
+                    var.lsb = lsb;
+                },
+                None => note!("*WARNING* {} has a range that isn't constant", decl.name),
+            }
+        }
+        if let Some((ref first, ref last)) = decl.array {
+            match constant_bounds(first, last) {
+                Some(bounds) => var.array = Some(bounds),
+                None => note!("*WARNING* memory {} has bounds that aren't constant", decl.name),
+            }
+        }
+        if let Some(width) = decl.kind.width() {
+            var.msb = width - 1;
+            var.lsb = 0;
         }
+        match decl.kind {
+            // ports are nets unless also declared as a variable
+            DeclKind::Reg | DeclKind::Time => var.net = false,
+            DeclKind::Integer => {
+                var.net = false;
+                var.ty = Type::Signed;
+            },
+            DeclKind::Real => {
+                var.net = false;
+                var.ty = Type::Real;
+            },
+            DeclKind::Net(kind) => var.kind = kind,
+            _ => {},
+        }
+        if decl.signed {
+            var.ty = Type::Signed;
+        }
+    }
+
+    // a declared variable, or the memory a word belongs to
+    fn variable(&self, var: &str) -> Option<&Variable> {
+        if let Some(v) = self.variables.get(var) {
+            return Some(v);
+        }
+        let memory = var.strip_suffix(']')
+            .and_then(|word| word.rfind('[').map(|i| &word[..i]));
+        memory.and_then(|m| self.variables.get(m)).filter(|v| v.array.is_some())
+    }
+
+    fn initial_value(&self, var: &str) -> Value {
+        match self.variable(var) {
+            Some(v) if v.ty == Type::Real => Value::real(0.0),
+            Some(v) if v.net => net::resolve(v.kind, v.width(), iter::empty()),
+            Some(v) => Value::x(v.width()),
+            None => Value::x(1),
+        }
+    }
+
+    pub fn set_vcd_file(&mut self, filename: Option<String>) {
+        self.vcd_file = filename;
+    }
+
+    pub fn set_dump_memories(&mut self, dump: bool) {
+        self.dump_memories = dump;
+    }
+
+    pub fn set_max_time(&mut self, max_time: Option<Time>) {
+        self.max_time = max_time;
     }
 
-    pub fn init(&mut self) {
-        println!("*INFO* Initialising timeheap");
+    pub fn set_max_deltas(&mut self, max_deltas: Option<usize>) {
+        self.max_deltas = max_deltas;
+    }
+
+    #[cfg(test)]
+    pub fn set_output(&mut self, output: Box<dyn Sink>) {
+        self.output = output;
+    }
+
+    pub fn init(&mut self) -> Result<(), String> {
+        note!("*INFO* Initialising timeheap");
         // fill the timeheap, set all trigger times to 0
-        if self.procedures.is_empty() {
-            panic!("*ERROR* no procedures to simulate");
+        if self.procedures.is_empty() && self.assigns.is_empty() && self.gates.is_empty() &&
+            self.udps.is_empty()
+        {
+            return Err("no procedures to simulate".to_string());
+        }
+        if let Some(error) = self.errors.first() {
+            return Err(error.clone());
         }
 
         for i in 0..self.procedures.len() {
-            self.timeheap.push(i, 0);
+            self.timeheap.push(Wakeup::Resume(i), 0);
+        }
+
+        // continuous assignments all get evaluated once at the start
+        for aid in 0..self.assigns.len() {
+            self.q_active.push_front( Event::Update(aid) );
+        }
+        for gid in 0..self.gates.len() {
+            self.q_active.push_front( Event::Gate(gid) );
+        }
+        for uid in 0..self.udps.len() {
+            self.q_active.push_front( Event::Udp(uid) );
         }
 
-        println!("*INFO* Gathering variables used in the design");
+        note!("*INFO* Gathering variables used in the design");
         self.get_identifier_list();
-        self.show_identifiers();
+        for var in &self.vars {
+            let value = self.initial_value(var);
+            self.symtable.insert(var.clone(), value);
+        }
+        if debugging!() {
+            self.show_identifiers();
+        }
 
-        println!("*INFO* Opening VCD file");
-        self.dumper = VcdWriter::new("waves.vcd");
+        if let Some(ref filename) = self.vcd_file {
+            note!("*INFO* Opening VCD file {}", filename);
+            self.dumper = VcdWriter::new(filename);
+        }
+        let widths: Vec<usize> = self.vars.iter().map(|var| self.var_width(var)).collect();
+        let reals: Vec<bool> = self.vars.iter().map(|var| self.var_type(var) == Type::Real).collect();
         if let Some(ref mut vcd) = self.dumper {
             vcd.write_header();
-            vcd.declare_vars(&self.vars);
+            vcd.declare_vars(&self.vars, &widths, &reals);
         }
-
+        Ok(())
     }
 
 
     fn get_identifier_list(&mut self) {
         self.vars.clear();
+        // declared but never used still go in the waveforms
+        // but automatic variables only exist while their call does
+        let mut declared: Vec<&String> = self.variables.keys()
+            .filter(|var| !self.automatic.contains(*var))
+            .collect();
+        declared.sort();
+        for var in declared {
+            self.vars.push(var.clone());
+        }
         for pid in &self.procedures {
             let proc_vars = pid.get_identifiers();
             for var in proc_vars {
+                if !self.vars.contains(&var) && !self.automatic.contains(&var) {
+                    self.vars.push(var);
+                }
+            }
+        }
+        for ca in &self.assigns {
+            for var in ca.get_identifiers() {
+                if !self.vars.contains(&var) {
+                    self.vars.push(var);
+                }
+            }
+        }
+        for g in &self.gates {
+            for var in g.gate.terminals.iter().flat_map(|t| t.get_identifiers()) {
                 if !self.vars.contains(&var) {
                     self.vars.push(var);
                 }
             }
         }
+        for u in &self.udps {
+            for var in u.inst.terminals.iter().flat_map(|t| t.get_identifiers()) {
+                if !self.vars.contains(&var) {
+                    self.vars.push(var);
+                }
+            }
+        }
+
+        // a memory has no value of its own, only its words do
+        let variables = &self.variables;
+        self.vars.retain(|var| variables.get(var).is_none_or(|v| v.array.is_none()));
+        if self.dump_memories {
+            let mut memories: Vec<(&String, usize, usize)> = variables.iter()
+                .filter_map(|(var, v)| v.array.map(|(first, last)| (var, first, last)))
+                .collect();
+            memories.sort();
+            for (var, first, last) in memories {
+                for addr in first.min(last)..=first.max(last) {
+                    self.vars.push(word_name(var, addr));
+                }
+            }
+        }
     }
 
-    pub fn run(&mut self) {
+    pub fn run(&mut self) -> RunResult {
         // simulation loop
-        println!("\n*INFO* Starting simulation");
+        note!("\n*INFO* Starting simulation");
         let mut c_loop = 1;
-        loop {
-            println!("======================================================");
-            println!("*INFO* Time: {}ns + {}", self.time, c_loop);
+        let result = loop {
+            if let Some(result) = self.result {
+                break result;
+            }
+            if self.max_deltas.is_some_and(|max_deltas| c_loop > max_deltas) {
+                println!("*ERROR* Time {} didn't settle after {} delta cycles",
+                         self.time, c_loop - 1);
+                self.show_activity();
+                break RunResult::DeltaLimit;
+            }
+
+            info!("======================================================");
+            info!("*INFO* Time: {}ns + {}", self.time, c_loop);
             //self.show_symtable();
             //self.show_queues();
-            self.show_blocked_pids();
+            if debugging!() {
+                self.show_blocked_pids();
+            }
 
             if !self.q_active.is_empty() {
-                println!("*INFO* Emptying active queue");
-                while let Some(stmt) = self.q_active.pop_back() {
-                    self.execute(stmt);
+                // one delta cycle: whatever this wakes up waits for the next
+                info!("*INFO* Emptying active queue");
+                let c_events = self.q_active.len();
+                for _ in 0..c_events {
+                    let event = match self.q_active.pop_back() {
+                        Some(event) => event,
+                        None => break,
+                    };
+                    match event {
+                        Event::Resume(pid) => {
+                            // a disabled thread's wakeups are left to go stale
+                            if !self.procedures[pid].finished {
+                                *self.activity.wakes.entry(pid).or_default() += 1;
+                                self.run_proc(pid);
+                            }
+                        },
+                        Event::Execute(stmt) => {
+                            info!("*INFO* Executing: {}", stmt);
+                            self.execute(stmt);
+                        },
+                        Event::Update(aid) => self.update_assign(aid),
+                        Event::Gate(gid) => self.update_gate(gid),
+                        Event::Udp(uid) => self.update_udp(uid),
+                        Event::Drive(aid, value) => {
+                            let ca = &self.assigns[aid];
+                            let driver = (Driver::Assign(aid), ca.strength);
+                            self.drive(ca.id.clone(), value, Some(driver));
+                        },
+                    }
+                    if self.result.is_some() {
+                        break;
+                    }
+                }
+
+            } else if !self.q_inactive.is_empty() {
+                info!("*INFO* Moving inactive events to active");
+                while let Some(event) = self.q_inactive.pop_back() {
+                    self.q_active.push_front(event);
                 }
 
             } else if !self.q_nba.is_empty() {
-                println!("*INFO* Moving nonblocking assignments to active");
+                info!("*INFO* Moving nonblocking assignments to active");
                 while let Some(stmt) = self.q_nba.pop_back() {
-                    self.q_active.push_front(stmt);
+                    self.q_active.push_front( Event::Execute(stmt) );
                 }
 
             } else {
-                println!("*INFO* Update VCD");
+                info!("*INFO* Postponed region");
+                self.postponed();
+
+                info!("*INFO* Update VCD");
                 if let Some(ref mut vcd) = self.dumper {
                     vcd.dump(self.time, &self.vars, &self.symtable);
                 }
 
-                println!("*INFO* Get events from procedures");
+                info!("*INFO* Get events from procedures");
                 c_loop = 0;
-                let c_stmt = self.get_events();
-
-                if c_stmt == 0 {
-                    println!("*INFO* Event starved!");
-                    break;
+                if let Some(result) = self.get_events() {
+                    break result;
                 }
             }
             c_loop += 1;
-        }
-        println!("======================================================");
-        println!("\n*INFO* Finished at time {}", self.time);
+        };
+        info!("======================================================");
+        note!("\n*INFO* Finished at time {}: {}", self.time, result);
         if let Some(ref mut vcd) = self.dumper {
             vcd.dump(self.time, &self.vars, &self.symtable);
         }
-        self.show_blocked_pids();
-        self.show_symtable();
+        if debugging!() {
+            self.show_blocked_pids();
+        }
+        if logging::verbosity() >= logging::NORMAL {
+            self.show_symtable();
+        }
+        result
     }
 
 
     fn execute(&mut self, stmt: Statement) {
-        println!("*INFO* Executing: {}", stmt);
         match stmt {
 
             Statement::BlockingAssign{id, expr} => {
-                match id {
-                    Operand::Identifier(var) => {
-                        let value = self.evaluate(expr);
-                        self.update_variable(&var, value);
-                    },
-                    Operand::Literal(_) => {
-                    },
-                }
+                let value = self.evaluate_for(&id, &expr);
+                self.assign(id, value);
             },
 
             Statement::NonBlockingAssign{id, expr} => {
-                match id {
-                    Operand::Identifier(i) => {
-                        let val = self.evaluate(expr);
-                        let stmt = Statement::BlockingAssign{
-                            id: Operand::Identifier(i),
-                            expr: Expression::Const( Operand::Literal(val) ),
-                        };
-                        self.schedule_nba(stmt);
-                    },
-                    Operand::Literal(_) => {
-                    },
-                }
+                let val = self.evaluate_for(&id, &expr);
+                // the target is fixed now too, "a[i] <= b" uses today's i
+                let stmt = Statement::BlockingAssign{
+                    id: self.resolve_lvalue(id),
+                    expr: Expression::Const( Operand::Literal(val) ),
+                };
+                self.schedule_nba(stmt);
             },
 
             _ => {
-                println!("*WARNING* Statement not implemented: {}", stmt);
+                note!("*WARNING* Statement not implemented: {}", stmt);
             },
 
         }
     }
 
-    fn lookup(&mut self, op: Operand) -> Value {
-        match op {
-            Operand::Literal(num) => num,
-            Operand::Identifier(id) => {
-                if let Some(n) = self.symtable.get(&id) {
-                    *n
-                } else {
-                    println!("*WARNING* variable {} is not defined, using 0", id);
-                    0
+    fn system_task(&mut self, pid: ProcId, name: &str, args: Vec<Argument>,
+        block: Option<String>)
+    {
+        let mut scope = self.procedures[pid].current_scope().to_string();
+        if let Some(block) = block {
+            scope = format!("{}.{}", scope, block);
+        }
+        match name {
+            "$display" | "$write" => {
+                let values = self.eval_args(&args);
+                let mut text = format::render(&values, &scope);
+                if name == "$display" {
+                    text.push('\n');
                 }
-            }
+                self.output.write_str(&text);
+            },
+            "$monitor" => {
+                // a new $monitor replaces the old one
+                self.monitor = Some(Monitor { args, scope, last: None, on: true });
+            },
+            "$monitoron" | "$monitoroff" => {
+                if let Some(ref mut monitor) = self.monitor {
+                    monitor.on = name == "$monitoron";
+                    // switching back on prints straight away
+                    monitor.last = None;
+                }
+            },
+            "$finish" | "$stop" => {
+                note!("*INFO* {} called at time {}", name, self.time);
+                self.result = Some( if name == "$finish" {
+                    RunResult::Finish
+                } else {
+                    RunResult::Stop
+                });
+            },
+            "$strobe" => {
+                self.q_postponed.push_front( (scope, args) );
+            },
+            "$readmemh" | "$readmemb" => {
+                self.readmem(name, &args);
+            },
+            "$writememh" | "$writememb" => {
+                self.writemem(name, &args);
+            },
+            _ => {
+                note!("*WARNING* System task not implemented: {}", name);
+            },
         }
     }
 
-    fn evaluate(&mut self, expr: Expression) -> Value {
-        match expr {
-            Expression::Const(a) => {
-                self.lookup(a)
-            },
-            Expression::Not(b) => {
-                self.lookup(b)
+    // $readmemh("file", mem [, start [, end]]): the words go in from
+    // start towards end, which are the memory's own bounds by default
+    fn readmem(&mut self, name: &str, args: &[Argument]) {
+        let (file, var, start, end) = match self.memory_args(name, args) {
+            Some(args) => args,
+            None => return,
+        };
+        let text = match fs::read_to_string(&file) {
+            Ok(text) => text,
+            Err(why) => {
+                note!("*WARNING* {}: can't read {}: {}", name, file, why);
+                return;
             },
-            Expression::And(a,b) => {
-                self.lookup(a) & self.lookup(b)
+        };
+        let base = if name.ends_with('h') { 'h' } else { 'b' };
+        let items = match memfile::parse(&text, base, self.var_width(&var)) {
+            Ok(items) => items,
+            Err(why) => {
+                note!("*WARNING* {}: {} {}", name, file, why);
+                return;
             },
-            Expression::Or(a,b) => {
-                self.lookup(a) | self.lookup(b)
+        };
+        let within = |addr: usize| addr >= start.min(end) && addr <= start.max(end);
+        let mut addr = Some(start);
+        for item in items {
+            match item {
+                Item::Address(a) => addr = Some(a),
+                Item::Word(word) => {
+                    match addr {
+                        Some(a) if within(a) => self.write_word(&var, a, word),
+                        _ => {
+                            note!("*WARNING* {}: {} runs past {}[{}:{}]", name, file, var, start, end);
+                            return;
+                        },
+                    }
+                    addr = addr.and_then(|a| if end >= start { a.checked_add(1) } else { a.checked_sub(1) });
+                },
             }
         }
     }
 
+    // $writememh("file", mem [, start [, end]]): a word a line
+    fn writemem(&mut self, name: &str, args: &[Argument]) {
+        let (file, var, start, end) = match self.memory_args(name, args) {
+            Some(args) => args,
+            None => return,
+        };
+        let spec = if name.ends_with('h') { 'h' } else { 'b' };
+        let addrs: Vec<usize> = if end >= start {
+            (start..=end).collect()
+        } else {
+            (end..=start).rev().collect()
+        };
+        let mut text = String::new();
+        for addr in addrs {
+            text += &format::radix(&self.word(&var, addr), spec);
+            text.push('\n');
+        }
+        if let Err(why) = fs::write(&file, text) {
+            note!("*WARNING* {}: can't write {}: {}", name, file, why);
+        }
+    }
 
-    pub fn add_proc(&mut self, p: Procedure) {
-        self.procedures.push(p);
+    // the file, the memory and the first and last word of it to load or save
+    fn memory_args(&mut self, name: &str, args: &[Argument])
+        -> Option<(String, String, usize, usize)>
+    {
+        let file = match args.first() {
+            Some(Argument::Str(file)) => file.clone(),
+            _ => {
+                note!("*WARNING* {}: the first argument has to be a file name", name);
+                return None;
+            },
+        };
+        let (var, bounds) = match args.get(1) {
+            Some(Argument::Expr(Expression::Const(Operand::Identifier(var)))) => {
+                match self.variables.get(var).and_then(|v| v.array) {
+                    Some(bounds) => (var.clone(), bounds),
+                    None => {
+                        note!("*WARNING* {}: {} isn't a memory", name, var);
+                        return None;
+                    },
+                }
+            },
+            _ => {
+                note!("*WARNING* {}: the second argument has to be a memory", name);
+                return None;
+            },
+        };
+        if args.len() > 4 {
+            note!("*WARNING* {}: too many arguments", name);
+            return None;
+        }
+        let mut addrs = vec![];
+        for arg in &args[2..] {
+            let addr = match *arg {
+                Argument::Expr(ref expr) => self.index(expr),
+                Argument::Str(_) => None,
+            };
+            match addr {
+                Some(addr) if self.variables[&var].holds(addr) => addrs.push(addr),
+                _ => {
+                    note!("*WARNING* {}: {} isn't an address in {}", name, arg, var);
+                    return None;
+                },
+            }
+        }
+        let start = addrs.first().cloned().unwrap_or(bounds.0);
+        let end = addrs.get(1).cloned().unwrap_or(bounds.1);
+        Some( (file, var, start, end) )
     }
 
+    // the last thing in a time step, nothing changes from here on
+    fn postponed(&mut self) {
+        while let Some((scope, args)) = self.q_postponed.pop_back() {
+            let args = self.eval_args(&args);
+            let text = format::render(&args, &scope) + "\n";
+            self.output.write_str(&text);
+        }
 
-    fn schedule_nba(&mut self, stmt: Statement) {
-        self.q_nba.push_front(stmt);
+        let args = match self.monitor {
+            Some(ref monitor) if monitor.on => monitor.args.clone(),
+            _ => return,
+        };
+        let args = self.eval_args(&args);
+        let mut text: Option<String> = None;
+        if let Some(ref mut monitor) = self.monitor {
+            // a new time on its own isn't a change
+            let changed = match monitor.last {
+                Some(ref last) => changes(last, &args, &monitor.args),
+                None => true,
+            };
+            if changed {
+                text = Some( format::render(&args, &monitor.scope) + "\n" );
+            }
+            monitor.last = Some(args);
+        }
+        if let Some(text) = text {
+            self.output.write_str(&text);
+        }
     }
 
-    fn update_time(&mut self, time: Time) {
-        self.time = time;
-        println!("*INFO* Time is now {}", self.time);
+    fn eval_args(&mut self, args: &[Argument]) -> Vec<Arg> {
+        let mut values: Vec<Arg> = vec![];
+        for arg in args {
+            match *arg {
+                Argument::Str(ref s) => values.push( Arg::Str(s.clone()) ),
+                Argument::Expr(ref expr) if self.expr_type(expr) == Type::Real => {
+                    values.push( Arg::Val(Value::real(self.evaluate_real(expr))) );
+                },
+                Argument::Expr(ref expr) => values.push( Arg::Val(self.evaluate_self(expr)) ),
+            }
+        }
+        values
     }
 
-    // Pump the time heap until we find something to execute, or
-    // we run out of future events.
-    // We may turn up empty-handed from a procedure if the procedure
-    // ends on a delay statement.
-    fn get_events(&mut self) -> usize {
-        let mut c_stmt:usize = 0;
+    // write to an lvalue, truncating or zero-extending to fit
+    fn assign(&mut self, id: Operand, value: Value) {
+        self.drive(id, value, None);
+    }
 
-        loop {
-            let (nexttime, proc_ids) = self.timeheap.activate();
-            if let Some(time) = nexttime {
-                self.update_time(time);
-                println!("*INFO* Activating: {:?}", proc_ids);
-                // grab events from the active procedures and queue them up
-                for pid in proc_ids {
-                    c_stmt += self.get_events_from_pid(pid);
+    // write to an lvalue for a driver of a net, or straight to the
+    // variable for a procedure
+    fn drive(&mut self, id: Operand, value: Value, driver: Option<(Driver, DriveStrength)>) {
+        match id {
+            Operand::Identifier(var) => {
+                self.store(&var, value, driver);
+            },
+            Operand::BitSelect(var, addr) if self.is_memory(&var) => {
+                match self.index(&addr) {
+                    Some(addr) => self.write_word(&var, addr, value),
+                    None => note!("*WARNING* {}[{}] is out of range, not assigned", var, addr),
+                }
+            },
+            Operand::BitSelect(var, bit) => {
+                let offset = self.index(&bit).and_then(|i| self.var_offset(&var, i));
+                if let Some(offset) = offset {
+                    let mut new_value = self.driven(&var, driver);
+                    new_value.set_slice(offset, &value.resize(1));
+                    self.store(&var, new_value, driver);
+                } else {
+                    note!("*WARNING* {}[{}] is out of range, not assigned", var, bit);
                 }
-                // we found something to execute, so stop pumping the timeheap
-                if c_stmt > 0 {
-                    break;
+            },
+            Operand::PartSelect(var, msb, lsb) => {
+                let offsets = constant_bounds(&msb, &lsb)
+                    .and_then(|(m, l)| self.var_offset(&var, m).and_then(|a|
+                        self.var_offset(&var, l).map(|b| (a, b))));
+                match offsets {
+                    Some((a, b)) => {
+                        let width = a.max(b) - a.min(b) + 1;
+                        let mut new_value = self.driven(&var, driver);
+                        new_value.set_slice(a.min(b), &value.resize(width));
+                        self.store(&var, new_value, driver);
+                    },
+                    None => {
+                        note!("*WARNING* {}[{}:{}] is out of range, not assigned",
+                            var, msb, lsb);
+                    },
                 }
-            } else {
-                println!("*INFO* Time starved");
-                break;
-            }
+            },
+            Operand::Concat(ops) => {
+                // the last operand gets the least significant bits
+                let width: usize = ops.iter().map(|op| self.operand_width(op)).sum();
+                let value = value.resize(width);
+                let mut offset = 0;
+                for op in ops.into_iter().rev() {
+                    let w = self.operand_width(&op);
+                    self.drive(op, value.slice(offset, w), driver);
+                    offset += w;
+                }
+            },
+            Operand::Literal(_) => {
+                note!("*WARNING* can't assign to a literal");
+            },
         }
-        c_stmt
     }
 
+    // memories are only ever written a word at a time, and anything
+    // reading any of the memory hears about it
+    fn write_word(&mut self, var: &str, addr: usize, value: Value) {
+        if !self.variables.get(var).is_some_and(|v| v.holds(addr)) {
+            note!("*WARNING* {}[{}] is out of range, not assigned", var, addr);
+            return;
+        }
+        let word = word_name(var, addr);
+        let value = self.fit(var, value);
+        if self.value_of(&word) == value {
+            info!("*INFO* No change: {} = {}", word, value);
+            return;
+        }
+        self.update_variable(&word, value);
+        self.wake(&Edge::Any(var.to_string()));
+        self.fan_out(var, None);
+    }
 
-    fn get_events_from_pid(&mut self, pid: ProcId) -> usize {
-        let mut c_stmt:usize = 0;
-        let p = &mut self.procedures[pid];
-        println!("  P: {} -- {} of {}", pid, p.counter, p.stmts.len() ); 
-        while let Some(stmt) = p.next_stmt() {
-            match stmt {
-
-                Statement::Delay{dly} => {
-                    let trig_time = self.time + dly;
-                    self.timeheap.push(pid, trig_time);
-                    println!("*INFO* Procedure {} blocked on delay til: {}", 
-                            pid, trig_time);
-                    break;
-                },
-
-                Statement::AtChange{edges} => {
-                    for edge in edges {
-                        println!("*INFO* Process {} waits on {}", pid, edge);
-                        let e = self.waiting.entry(edge).or_insert_with( HashSet::new );
-                        e.insert(pid);
-                    }
-                    break;
-                },
+    fn is_net(&self, var: &str) -> bool {
+        self.variables.get(var).is_some_and(|v| v.net)
+    }
 
-                _ => {
-                    println!("*INFO* Loading: {}", stmt);
-                    self.q_active.push_front(stmt);
-                    c_stmt += 1;
+    // what the driver puts on the net, all z if nothing yet, which a
+    // bit or part select only changes some of
+    fn driven(&self, var: &str, driver: Option<(Driver, DriveStrength)>) -> Value {
+        match driver {
+            Some((d, _)) if self.is_net(var) => {
+                let contribution = self.drivers.get(var)
+                    .and_then(|drivers| drivers.iter().find(|c| c.0 == d));
+                match contribution {
+                    Some(c) => c.1.clone(),
+                    None => Value::z(self.var_width(var)),
                 }
-            }
-        }
-        if c_stmt == 0 {
-            println!("*INFO* Procedure {} event starved", pid);
+            },
+            _ => self.value_of(var),
         }
-        c_stmt
     }
 
-    // a value has changed, throw anythign sensive to this on
-    // the active queue
-    fn update_variable(&mut self, var: &str, value: Value) {
-
-        // transitions
-        let mut transitions: Vec<Edge> = vec![];
+    // a net takes what all its drivers make of it
+    fn store(&mut self, var: &str, value: Value, driver: Option<(Driver, DriveStrength)>) {
+        let (d, strength) = match driver {
+            Some(driver) if self.is_net(var) => driver,
+            _ => return self.update_variable(var, value),
+        };
+        let (kind, width) = match self.variables.get(var) {
+            Some(v) => (v.kind, v.width()),
+            None => (NetKind::Wire, 1),
+        };
+        let value = value.resize(width);
+        let mut resolved = match self.symtable.get(var) {
+            Some(v) => v.clone(),
+            None => self.initial_value(var),
+        };
+        let drivers = self.drivers.entry(var.to_string()).or_default();
+        // only the bits the driver changed can come out differently
+        let changed = match drivers.iter_mut().find(|c| c.0 == d) {
+            Some(c) => {
+                let changed = c.1.changed(&value);
+                c.1 = value;
+                changed
+            },
+            None => {
+                drivers.push( (d, value, strength) );
+                Some((0, width - 1))
+            },
+        };
+        if drivers.len() > 1 {
+            info!("*INFO* {} has {} drivers", var, drivers.len());
+        }
+        if let Some(bits) = changed {
+            net::resolve_bits(kind, &mut resolved, bits, drivers.iter().map(|&(_, ref v, s)| (v, s)));
+            self.update_variable(var, resolved);
+        }
+    }
 
-        // the 'something changed' trigger
-        transitions.push( Edge::Any(var.to_string()) );
+    // pin down any variable indices in an lvalue
+    fn resolve_lvalue(&mut self, id: Operand) -> Operand {
+        match id {
+            Operand::BitSelect(var, bit) => {
+                let index = match self.index(&bit) {
+                    Some(i) => Value::from(i),
+                    None => Value::x(1),
+                };
+                Operand::BitSelect(var, Box::new( Expression::Const(Operand::Literal(index)) ))
+            },
+            Operand::Concat(ops) => {
+                Operand::Concat( ops.into_iter().map(|op| self.resolve_lvalue(op)).collect() )
+            },
+            _ => id,
+        }
+    }
 
-        // is there an edge trigger in here too?
-        if let Some(old_value) = self.symtable.get(var) {
-            if (*old_value == 0) & (value != 0) {
-                // 0 -> something is a rising edge, triggers posedge blocks
-                transitions.push( Edge::Rise(var.to_string()) );
-            } else if (*old_value != 0) & (value == 0) {
-                // something -> 0 is a falling edge and triggers negedge blocks
-                transitions.push( Edge::Fall(var.to_string()) );
-            }
+    // everything an elaborated design has to simulate
+    pub fn load(&mut self, design: Design) {
+        for decl in &design.decls {
+            self.declare(decl);
+        }
+        for mut p in design.procs {
+            let scope = p.scope.clone();
+            self.check_widths(&scope, &mut |r| p.rewrite(r));
+            self.add_proc(p);
+        }
+        for mut ca in design.assigns {
+            let place = ca.to_string();
+            self.check_widths(&place, &mut |r| ca.rewrite(r));
+            self.add_assign(ca);
+        }
+        for gate in design.gates {
+            self.add_gate(gate);
+        }
+        for udp in design.udps {
+            self.add_udp(udp);
+        }
+        for mut sub in design.subroutines {
+            let name = sub.name.clone();
+            let stmts = Rc::make_mut(&mut sub.stmts);
+            self.check_widths(&name, &mut |r| for stmt in stmts.iter_mut() { stmt.rewrite(r) });
+            self.add_subroutine(sub);
         }
+    }
 
-        println!("*INFO* Transition: {}", var);
+    // a Value holds 64 bits, so a wider concatenation would lose some
+    fn check_widths(&mut self, place: &str, walk: &mut dyn FnMut(&dyn Rewrite)) {
+        let check = WidthCheck { eng: self, place, errors: RefCell::new(vec![]) };
+        walk(&check);
+        let errors = check.errors.into_inner();
+        self.errors.extend(errors);
+    }
 
-        // update the variable
-        self.symtable.insert(var.to_string(), value);
+    pub fn add_proc(&mut self, p: Procedure) {
+        self.procedures.push(p);
+    }
 
-        // now trigger procedures sensitive to this var
-        let mut pids_removed: Vec<ProcId> = vec![];
-        for transition in transitions {
-            if let Some(pid_set) = self.waiting.remove(&transition) {
-                // activate the procedure that was waiting on a change
-                for pid in pid_set {
-                    println!("*INFO* pulling from {}", pid);
-                    self.get_events_from_pid(pid);
-                    pids_removed.push(pid);
-                }
+    pub fn add_subroutine(&mut self, sub: Subroutine) {
+        if sub.automatic {
+            for decl in &sub.decls {
+                self.automatic.insert(decl.name.clone());
             }
         }
-        //self.scrub_waiting_list(pids_removed);
+        self.subroutines.insert(sub.name.clone(), Rc::new(sub));
     }
 
-    #[allow(dead_code)]
-    fn scrub_waiting_list(&mut self, pids: Vec<ProcId>) {
-        for pid in pids {
-            for values in self.waiting.values_mut() {
-                values.remove(&pid);
-            }
+    pub fn add_assign(&mut self, ca: ContinuousAssign) {
+        let aid = self.assigns.len();
+        for (var, bits) in self.reads(slice::from_ref(&ca.expr), slice::from_ref(&ca.id)) {
+            self.fanout.entry(var).or_default().push( (aid, bits) );
         }
+        self.assigns.push(ca);
+        self.tickets.push(0);
+        self.pending.push(None);
     }
 
-    //
-    // Display stuff
+    // an operand of a continuous assignment has changed
+    fn trigger_assign(&mut self, aid: AssignId) {
+        let dly = self.assign_delay(aid);
+        if dly == 0 {
+            self.q_active.push_front( Event::Update(aid) );
+            return;
+        }
+
+        // inertial delay: work out the value now, and supersede
+        // anything already on its way, unless it's the same value
+        let ca = self.assigns[aid].clone();
+        let value = self.evaluate_for(&ca.id, &ca.expr);
+        if self.pending[aid].as_ref() == Some(&value) {
+            return;
+        }
+        self.pending[aid] = Some(value.clone());
+        self.tickets[aid] += 1;
+        let ticket = self.tickets[aid];
+        info!("*INFO* {} scheduled for {}", ca, self.time + dly);
+        self.timeheap.push(Wakeup::Assign{aid, ticket, value}, self.time + dly);
+    }
+
+    // evaluate a continuous assignment and drive its target
+    fn update_assign(&mut self, aid: AssignId) {
+        *self.activity.updates.entry(aid).or_default() += 1;
+        let ca = self.assigns[aid].clone();
+        info!("*INFO* Evaluating: {}", ca);
+        if self.assign_delay(aid) > 0 {
+            // at time 0 the first value still has to wait out the delay
+            self.trigger_assign(aid);
+            return;
+        }
+        let value = self.evaluate_for(&ca.id, &ca.expr);
+        self.drive(ca.id, value, Some((Driver::Assign(aid), ca.strength)));
+    }
+
+
+    pub fn add_gate(&mut self, gate: Gate) {
+        let gid = self.gates.len();
+        let mut outputs: Vec<Operand> = vec![];
+        for output in gate.outputs() {
+            match output.lvalue() {
+                Some(id) => outputs.push(id),
+                None => self.errors.push(format!("{}: output {} can't be driven",
+                    gate, output)),
+            }
+        }
+        // "and (y[i], a, b)" moves when i does
+        for (var, bits) in self.reads(gate.inputs(), &outputs) {
+            self.gate_fanout.entry(var).or_default().push( (gid, bits) );
+        }
+        let delays: Vec<Time> = gate.delays.iter().map(|d| self.delay(d)).collect();
+        self.gates.push(GateInst {
+            kind: gate.kind,
+            outputs,
+            inputs: gate.inputs().to_vec(),
+            delays,
+            out: Logic::Z, // undriven, so the first evaluation always lands
+            ticket: 0,
+            gate,
+        });
+    }
+
+    // an input of a gate has changed: work out its output straight from
+    // the bits, without building any statements
+    fn update_gate(&mut self, gid: GateId) {
+        *self.activity.gates.entry(gid).or_default() += 1;
+        let exprs = mem::take(&mut self.gates[gid].inputs);
+        let inputs = self.bits(&exprs);
+        self.gates[gid].inputs = exprs;
+        let g = &mut self.gates[gid];
+        let out = g.kind.evaluate(&inputs);
+        if out == g.out {
+            return;
+        }
+        // inertial, like a continuous assignment: a change on its way is
+        // replaced by this one
+        g.out = out;
+        g.ticket += 1;
+        let dly = gate::delay(&g.delays, out);
+        if dly == 0 {
+            self.drive_gate(gid, out);
+        } else {
+            info!("*INFO* {} goes to {} at {}", g.gate, out, self.time + dly);
+            let ticket = g.ticket;
+            self.timeheap.push(Wakeup::Gate{gid, ticket, value: out}, self.time + dly);
+        }
+    }
+
+    // the bit on each of a primitive's inputs
+    fn bits(&mut self, exprs: &[Expression]) -> Vec<Logic> {
+        let mut bits: Vec<Logic> = Vec::with_capacity(exprs.len());
+        for expr in exprs {
+            let bit = match *expr {
+                Expression::Const(Operand::Identifier(ref var)) => self.value_of(var).bit(0),
+                _ => self.evaluate_self(expr).bit(0),
+            };
+            bits.push(bit);
+        }
+        bits
+    }
+
+    pub fn add_udp(&mut self, inst: PrimitiveInstance) {
+        let uid = self.udps.len();
+        let output = match inst.terminals[0].lvalue() {
+            Some(id) => id,
+            None => {
+                self.errors.push(format!("{}: output {} can't be driven",
+                    inst.name, inst.terminals[0]));
+                return;
+            },
+        };
+        for (var, bits) in self.reads(&inst.terminals[1..], slice::from_ref(&output)) {
+            self.udp_fanout.entry(var).or_default().push( (uid, bits) );
+        }
+        let delays: Vec<Time> = inst.delays.iter().map(|d| self.delay(d)).collect();
+        self.udps.push(UdpInst {
+            output,
+            inputs: inst.terminals[1..].to_vec(),
+            delays,
+            last: vec![Logic::X; inst.terminals.len() - 1],
+            state: Logic::Z, // never an output, so the first look-up lands
+            ticket: 0,
+            inst,
+        });
+    }
+
+    // look the output up again. A sequential primitive takes its inputs'
+    // changes one at a time, each an edge from where it was
+    fn update_udp(&mut self, uid: UdpId) {
+        *self.activity.udps.entry(uid).or_default() += 1;
+        let exprs = mem::take(&mut self.udps[uid].inputs);
+        let inputs = self.bits(&exprs);
+        self.udps[uid].inputs = exprs;
+
+        let u = &mut self.udps[uid];
+        let table = &u.inst.primitive.table;
+        let mut out = match u.state {
+            Logic::Z => u.inst.primitive.initial.unwrap_or(Logic::X),
+            state => state,
+        };
+        if table.sequential {
+            for (i, &bit) in inputs.iter().enumerate() {
+                // z goes in as x
+                let new = if bit == Logic::Z { Logic::X } else { bit };
+                let old = u.last[i];
+                if new != old {
+                    u.last[i] = new;
+                    out = table.evaluate(&u.last, Some((i, old)), out);
+                }
+            }
+        } else {
+            u.last = inputs;
+            out = table.evaluate(&u.last, None, out);
+        }
+        if out == u.state {
+            return;
+        }
+        u.state = out;
+        u.ticket += 1;
+        let dly = gate::delay(&u.delays, out);
+        if dly == 0 {
+            self.drive_udp(uid, out);
+        } else {
+            info!("*INFO* {} goes to {} at {}", u.inst, out, self.time + dly);
+            let ticket = u.ticket;
+            self.timeheap.push(Wakeup::Udp{uid, ticket, value: out}, self.time + dly);
+        }
+    }
+
+    fn drive_gate(&mut self, gid: GateId, value: Logic) {
+        let driver = (Driver::Gate(gid), self.gates[gid].gate.strength);
+        for i in 0..self.gates[gid].outputs.len() {
+            let id = self.gates[gid].outputs[i].clone();
+            self.drive(id, Value::from_logic(value), Some(driver));
+        }
+    }
+
+    fn drive_udp(&mut self, uid: UdpId, value: Logic) {
+        let id = self.udps[uid].output.clone();
+        self.drive(id, Value::from_logic(value), Some((Driver::Udp(uid), net::STRONG)));
+    }
+
+    fn assign_delay(&mut self, aid: AssignId) -> Time {
+        match self.assigns[aid].dly.clone() {
+            Some(dly) => self.delay(&dly),
+            None => 0,
+        }
+    }
+
+    // how long a delay expression says to wait, x or z counts as none
+    fn delay(&mut self, dly: &Expression) -> Time {
+        match self.evaluate_self(dly).to_u64() {
+            Some(n) => n as Time,
+            None => {
+                note!("*WARNING* delay #{} is unknown, using 0", dly.nested());
+                0
+            },
+        }
+    }
+
+    fn schedule_nba(&mut self, stmt: Statement) {
+        self.q_nba.push_front(stmt);
+    }
+
+    fn update_time(&mut self, time: Time) {
+        self.time = time;
+        self.activity.clear();
+        info!("*INFO* Time is now {}", self.time);
+    }
+
+    // Pump the time heap for whatever is due at the next time,
+    // and queue it up on the active queue. Returns why not, if
+    // there's nothing more to simulate.
+    fn get_events(&mut self) -> Option<RunResult> {
+        let (nexttime, wakeups) = self.timeheap.activate();
+        match nexttime {
+            Some(time) if self.max_time.is_some_and(|max_time| time > max_time) => {
+                note!("*INFO* Time limit reached, next event at {}", time);
+                Some(RunResult::TimeLimit)
+            },
+            Some(time) => {
+                self.update_time(time);
+                for wakeup in wakeups {
+                    match wakeup {
+                        Wakeup::Resume(pid) => {
+                            info!("*INFO* Activating procedure {}", pid);
+                            self.q_active.push_front( Event::Resume(pid) );
+                        },
+                        Wakeup::Assign{aid, ticket, value} => {
+                            if ticket != self.tickets[aid] {
+                                info!("*INFO* Update of {} was superseded", self.assigns[aid]);
+                                continue;
+                            }
+                            self.q_active.push_front( Event::Drive(aid, value) );
+                        },
+                        Wakeup::Nba(stmt) => self.schedule_nba(stmt),
+                        Wakeup::Gate{gid, ticket, value} => {
+                            if ticket == self.gates[gid].ticket {
+                                self.drive_gate(gid, value);
+                            }
+                        },
+                        Wakeup::Udp{uid, ticket, value} => {
+                            if ticket == self.udps[uid].ticket {
+                                self.drive_udp(uid, value);
+                            }
+                        },
+                    }
+                }
+                // even if everything was superseded, there may be more to come
+                None
+            },
+            None => {
+                info!("*INFO* Time starved");
+                Some(RunResult::EventStarved)
+            },
+        }
+    }
+
+    // Run a procedure until it blocks on a delay or an event control,
+    // or runs off the end
+    fn run_proc(&mut self, pid: ProcId) {
+        self.running.push(pid);
+        self.resume(pid);
+        self.running.pop();
+    }
+
+    fn resume(&mut self, pid: ProcId) {
+        info!("*INFO* Resuming procedure {} at statement {}",
+              pid, self.procedures[pid].counter);
+        let budget = self.max_deltas.map(|max_deltas| max_deltas.saturating_mul(STATEMENTS_PER_DELTA));
+        let mut steps = 0;
+        while let Some(stmt) = self.procedures[pid].next_stmt() {
+            if self.result.is_some() {
+                // stopped inside a function call
+                return;
+            }
+            steps += 1;
+            if budget.is_some_and(|budget| steps > budget) {
+                self.runaway(pid, budget.unwrap_or(0));
+                return;
+            }
+            info!("*INFO* P{}: {}", pid, stmt);
+            match stmt {
+
+                Statement::Delay{dly} => {
+                    let dly = self.delay(&dly);
+                    if dly == 0 {
+                        // #0 goes to the back of the current time, but still
+                        // ahead of the nonblocking assignment updates
+                        self.q_inactive.push_front( Event::Resume(pid) );
+                        info!("*INFO* Procedure {} inactive", pid);
+                        return;
+                    }
+                    let trig_time = self.time + dly;
+                    self.timeheap.push(Wakeup::Resume(pid), trig_time);
+                    info!("*INFO* Procedure {} blocked on delay til: {}", 
+                            pid, trig_time);
+                    return;
+                },
+
+                Statement::AtChange{edges} => {
+                    for edge in edges {
+                        info!("*INFO* Process {} waits on {}", pid, edge);
+                        let e = self.waiting.entry(edge).or_default();
+                        e.insert(pid);
+                    }
+                    return;
+                },
+
+                Statement::AtExpr{events} => {
+                    // woken by any operand, but only goes on if the value moves
+                    let mut watch: Vec<(EventExpr, Value)> = vec![];
+                    for event in events {
+                        for var in event.expr.get_identifiers() {
+                            info!("*INFO* Process {} waits on {}", pid, var);
+                            self.waiting.entry(Edge::Any(var)).or_default().insert(pid);
+                        }
+                        let value = self.evaluate_self(&event.expr);
+                        watch.push( (event, value) );
+                    }
+                    self.watches.insert(pid, watch);
+                    return;
+                },
+
+                Statement::Hold{id, expr} => {
+                    // the value and where it goes are fixed now
+                    let value = self.evaluate_for(&id, &expr);
+                    let id = self.resolve_lvalue(id);
+                    self.procedures[pid].held = Some((id, value));
+                },
+
+                Statement::Release => {
+                    if let Some((id, value)) = self.procedures[pid].held.take() {
+                        self.assign(id, value);
+                    }
+                },
+
+                Statement::NonBlockingAfter{id, expr, control} => {
+                    self.nonblocking_after(pid, id, expr, *control);
+                },
+
+                Statement::Wait{cond} => {
+                    if self.condition(&cond) == Logic::One {
+                        continue;
+                    }
+                    // look again whenever anything in it changes
+                    for var in cond.get_identifiers() {
+                        info!("*INFO* Process {} waits on {}", pid, var);
+                        self.waiting.entry(Edge::Any(var)).or_default().insert(pid);
+                    }
+                    self.procedures[pid].retry();
+                    return;
+                },
+
+                Statement::Trigger{event} => {
+                    if self.events.contains(&event) {
+                        info!("*INFO* Event {} triggered", event);
+                        self.wake(&Edge::Any(event));
+                    } else {
+                        note!("*WARNING* {} isn't declared as an event, not triggered", event);
+                    }
+                },
+
+                Statement::Jump{target} => {
+                    self.procedures[pid].jump(target);
+                },
+
+                Statement::Call{name, args} => {
+                    if self.procedures[pid].frames.len() >= MAX_CALL_DEPTH {
+                        note!("*WARNING* {} nested more than {} deep, not called",
+                              name, MAX_CALL_DEPTH);
+                        continue;
+                    }
+                    if let Some(frame) = self.enter(&name, &args, SubroutineKind::Task) {
+                        self.procedures[pid].frames.push(frame);
+                    }
+                },
+
+                Statement::Return => {
+                    if let Some(frame) = self.procedures[pid].frames.pop() {
+                        self.leave(frame);
+                    }
+                },
+
+                Statement::Fork{join, branches} => {
+                    let children = self.fork(pid, branches);
+                    if join != JoinKind::None && !children.is_empty() {
+                        info!("*INFO* Procedure {} blocked on {} of {:?}", pid, join, children);
+                        self.procedures[pid].joining = Some((join, children));
+                        return;
+                    }
+                },
+
+                Statement::DisableFork => {
+                    let children = mem::take(&mut self.procedures[pid].children);
+                    for child in children {
+                        self.disable(child);
+                    }
+                },
+
+                Statement::SystemTask{name, args, block} => {
+                    self.system_task(pid, &name, args, block);
+                    if self.result.is_some() {
+                        // $finish, no further
+                        return;
+                    }
+                },
+
+                Statement::BranchIfNot{cond, target} => {
+                    if self.condition(&cond) != Logic::One {
+                        self.procedures[pid].jump(target);
+                    }
+                },
+
+                Statement::Switch{kind, expr, arms, default} => {
+                    let target = self.switch(kind, &expr, &arms).unwrap_or(default);
+                    self.procedures[pid].jump(target);
+                },
+
+                Statement::RepeatStart{count} => {
+                    // an x or z count means no iterations at all
+                    let count = self.evaluate_self(&count).to_u64().unwrap_or(0);
+                    self.procedures[pid].repeats().push(count);
+                },
+
+                Statement::RepeatCheck{target} => {
+                    let p = &mut self.procedures[pid];
+                    match p.repeats().last_mut() {
+                        Some(n) if *n > 0 => {
+                            *n -= 1;
+                            continue;
+                        },
+                        _ => {},
+                    }
+                    p.repeats().pop();
+                    p.jump(target);
+                },
+
+                _ => {
+                    self.execute(stmt);
+                }
+            }
+        }
+        info!("*INFO* Procedure {} has finished", pid);
+        if self.procedures[pid].parent.is_some() {
+            self.end_thread(pid);
+        }
+    }
+
+    // start a thread for each branch of a fork, as children of `pid`.
+    // They go on the active queue, so they get going once it blocks
+    fn fork(&mut self, pid: ProcId, branches: Vec<Rc<Vec<Statement>>>) -> Vec<ProcId> {
+        let mut children: Vec<ProcId> = vec![];
+        for stmts in branches {
+            let cid = self.spawn(pid, stmts);
+            info!("*INFO* Procedure {} forks procedure {}", pid, cid);
+            children.push(cid);
+        }
+        self.procedures[pid].children.extend(&children);
+        children
+    }
+
+    // a new thread running `stmts`, started by `pid`
+    fn spawn(&mut self, pid: ProcId, stmts: Rc<Vec<Statement>>) -> ProcId {
+        let parent = &self.procedures[pid];
+        let mut frame = Frame::new(parent.current_scope(), stmts);
+        // inside an automatic task, the thread gets a copy of its
+        // variables as they are now
+        if let Some(outer) = parent.frames.last() {
+            frame.locals = outer.locals.clone();
+        }
+        let mut child = Procedure::new(ProcedureType::Fork);
+        child.scope = parent.scope.clone();
+        child.parent = Some(pid);
+        child.frames.push(frame);
+
+        let cid = match self.free.pop() {
+            Some(cid) => {
+                self.procedures[cid] = child;
+                cid
+            },
+            None => {
+                self.procedures.push(child);
+                self.procedures.len() - 1
+            },
+        };
+        self.q_active.push_front( Event::Resume(cid) );
+        cid
+    }
+
+    // "q <= #2 d" or "q <= @(posedge clk) d": d is worked out now, and
+    // the procedure carries on without waiting for the update
+    fn nonblocking_after(&mut self, pid: ProcId, id: Operand, expr: Expression, control: Statement) {
+        let value = Expression::Const( Operand::Literal(self.evaluate_for(&id, &expr)) );
+        let id = self.resolve_lvalue(id);
+        match control {
+            Statement::Delay{dly} => {
+                let update = Statement::BlockingAssign{id, expr: value};
+                let dly = self.delay(&dly);
+                if dly == 0 {
+                    self.schedule_nba(update);
+                } else {
+                    info!("*INFO* {} scheduled for {}", update, self.time + dly);
+                    self.timeheap.push(Wakeup::Nba(update), self.time + dly);
+                }
+            },
+            control => {
+                // a thread of its own waits for the event; it isn't one of
+                // fork's children, so disable fork leaves it be
+                let stmts = vec![ control, Statement::NonBlockingAssign{id, expr: value} ];
+                let cid = self.spawn(pid, Rc::new(stmts));
+                info!("*INFO* Procedure {} waits in procedure {} for its update", pid, cid);
+            },
+        }
+    }
+
+    // a forked thread has run off its end. Its parent may be waiting
+    // for it, and any threads it started are handed up to the parent
+    fn end_thread(&mut self, pid: ProcId) {
+        let parent = match self.procedures[pid].parent {
+            Some(parent) => parent,
+            None => return,
+        };
+        let orphans = mem::take(&mut self.procedures[pid].children);
+        for &orphan in &orphans {
+            self.procedures[orphan].parent = Some(parent);
+        }
+
+        let p = &mut self.procedures[parent];
+        p.children.retain(|&child| child != pid);
+        p.children.extend(orphans);
+        let joined = match p.joining {
+            Some((JoinKind::Any, ref children)) => children.contains(&pid),
+            Some((_, ref mut children)) => {
+                children.retain(|&child| child != pid);
+                children.is_empty()
+            },
+            None => false,
+        };
+        if joined {
+            info!("*INFO* Procedure {} joined", parent);
+            p.joining = None;
+            self.q_active.push_front( Event::Resume(parent) );
+        }
+
+        self.procedures[pid].finished = true;
+        self.free.push(pid);
+    }
+
+    // disable fork: stop a thread, and everything it started, wherever
+    // it's blocked. Its slot isn't reused, there may be a wakeup on the way
+    fn disable(&mut self, pid: ProcId) {
+        info!("*INFO* Procedure {} disabled", pid);
+        let children = mem::take(&mut self.procedures[pid].children);
+        for child in children {
+            self.disable(child);
+        }
+        let p = &mut self.procedures[pid];
+        p.finished = true;
+        p.frames.clear();
+        p.joining = None;
+        self.watches.remove(&pid);
+        self.scrub_waiting_list(&[pid]);
+    }
+
+    // start a task or function call: the inputs are worked out here in
+    // the caller, the outputs are copied back by leave()
+    fn enter(&mut self, name: &str, args: &[Expression], kind: SubroutineKind) -> Option<Frame> {
+        let sub = match self.subroutines.get(name) {
+            Some(sub) if sub.kind == kind => sub.clone(),
+            _ => {
+                note!("*WARNING* no {} called {}", kind, name);
+                return None;
+            },
+        };
+        info!("*INFO* Calling {}", name);
+        let mut frame = Frame::new(&sub.name, sub.stmts.clone());
+        let mut inputs: Vec<(String, Value)> = vec![];
+        for (port, arg) in sub.ports.iter().zip(args) {
+            let dir = sub.direction(port);
+            if dir != Some(DeclKind::Output) {
+                let value = self.evaluate_for(&Operand::Identifier(port.clone()), arg);
+                inputs.push( (port.clone(), value) );
+            }
+            if dir != Some(DeclKind::Input) {
+                if let Some(id) = arg.lvalue() {
+                    frame.outputs.push( (port.clone(), self.resolve_lvalue(id)) );
+                }
+            }
+        }
+        if sub.automatic {
+            // a fresh set of variables for every call
+            for decl in &sub.decls {
+                let value = self.initial_value(&decl.name);
+                frame.locals.insert(decl.name.clone(), value);
+            }
+            for (port, value) in inputs {
+                let value = self.fit(&port, value);
+                frame.locals.insert(port, value);
+            }
+        } else {
+            for (port, value) in inputs {
+                self.update_variable(&port, value);
+            }
+        }
+        Some(frame)
+    }
+
+    // the end of a call, hand the outputs back to the caller
+    fn leave(&mut self, frame: Frame) {
+        info!("*INFO* Returning from {}", frame.name);
+        let value_in = |eng: &Engine, var: &str| match frame.locals.get(var) {
+            Some(value) => value.clone(),
+            None => eng.value_of(var),
+        };
+        for (port, id) in &frame.outputs {
+            let value = value_in(self, port);
+            self.assign(id.clone(), value);
+        }
+        if let Some(result) = function_result(&frame.name) {
+            if self.subroutines.get(&frame.name).is_some_and(|s| s.kind == SubroutineKind::Function) {
+                self.returned = Some( value_in(self, &result) );
+            }
+        }
+    }
+
+    // the target of the first case item to match, if any
+    fn switch(&mut self, kind: CaseKind, expr: &Expression,
+              arms: &[(Vec<Expression>, usize)]) -> Option<usize>
+    {
+        // everything is sized to the widest of the lot
+        let mut width = self.expr_width(expr);
+        for (labels, _) in arms {
+            for label in labels {
+                width = width.max( self.expr_width(label) );
+            }
+        }
+        let value = self.evaluate(expr, width);
+        for &(ref labels, target) in arms {
+            for label in labels {
+                let label = self.evaluate(label, width);
+                let matched = match kind {
+                    CaseKind::Case => value.case_match(&label, false, false),
+                    CaseKind::Casez => value.case_match(&label, true, false),
+                    CaseKind::Casex => value.case_match(&label, true, true),
+                };
+                if matched {
+                    return Some(target);
+                }
+            }
+        }
+        None
+    }
+
+    // a value has changed, throw anythign sensive to this on
+    // the active queue
+    fn update_variable(&mut self, var: &str, value: Value) {
+
+        let value = self.fit(var, value);
+        if let Some(&pid) = self.running.last() {
+            // an automatic variable: nothing can wait on it
+            if let Some(local) = self.procedures[pid].local_mut(var) {
+                *local = value;
+                return;
+            }
+        }
+        let old_value = self.value_of(var);
+        if old_value == value {
+            info!("*INFO* No change: {} = {}", var, value);
+            return;
+        }
+
+        // transitions
+        let mut transitions: Vec<Edge> = vec![];
+
+        // the 'something changed' trigger
+        transitions.push( Edge::Any(var.to_string()) );
+
+        // is there an edge trigger in here too?
+        match edge(old_value.bit(0), value.bit(0)) {
+            Some(Sense::Rise) => transitions.push( Edge::Rise(var.to_string()) ),
+            Some(Sense::Fall) => transitions.push( Edge::Fall(var.to_string()) ),
+            _ => {},
+        }
+
+        info!("*INFO* Transition: {}", var);
+        *self.activity.toggles.entry(var.to_string()).or_default() += 1;
+        let changed = old_value.changed(&value);
+
+        // update the variable
+        self.symtable.insert(var.to_string(), value);
+
+        // now trigger procedures sensitive to this var
+        for transition in transitions {
+            self.wake(&transition);
+        }
+
+        self.fan_out(var, changed);
+    }
+
+    // trigger the continuous assignments, gates and primitives reading
+    // the bits of a variable that changed
+    fn fan_out(&mut self, var: &str, changed: Bits) {
+        let aids: Vec<AssignId> = match self.fanout.get(var) {
+            Some(aids) => aids.iter().filter(|r| overlap(r.1, changed)).map(|r| r.0).collect(),
+            None => vec![],
+        };
+        for aid in aids {
+            self.trigger_assign(aid);
+        }
+        if let Some(gids) = self.gate_fanout.get(var) {
+            for &(gid, bits) in gids {
+                if overlap(bits, changed) {
+                    self.q_active.push_front( Event::Gate(gid) );
+                }
+            }
+        }
+        if let Some(uids) = self.udp_fanout.get(var) {
+            for &(uid, bits) in uids {
+                if overlap(bits, changed) {
+                    self.q_active.push_front( Event::Udp(uid) );
+                }
+            }
+        }
+    }
+
+    // the variables `exprs` read, and `lvalues` through their indices,
+    // with the bits of each that matter
+    fn reads(&self, exprs: &[Expression], lvalues: &[Operand]) -> Vec<(String, Bits)> {
+        let reads = Reads { eng: self, vars: RefCell::new(HashMap::new()) };
+        for expr in exprs {
+            expr.clone().rewrite(&reads);
+        }
+        let mut vars = reads.vars.into_inner();
+        for var in lvalues.iter().flat_map(|id| id.get_index_identifiers()) {
+            vars.insert(var, None);
+        }
+        let mut vars: Vec<(String, Bits)> = vars.into_iter().collect();
+        vars.sort();
+        vars
+    }
+
+    // a value as the variable keeps it: sized to it, or converted to or
+    // from a real
+    fn fit(&self, var: &str, value: Value) -> Value {
+        let width = self.var_width(var);
+        let value = match (self.var_type(var), value.is_real()) {
+            (Type::Real, true) => value,
+            (Type::Real, false) => Value::real( value.to_real() ),
+            (_, true) => Value::from_real(value.to_real(), width),
+            _ => value.resize(width),
+        };
+        value.with_type( self.var_type(var) )
+    }
+
+    // activate the procedures waiting on a change or an event, they
+    // aren't waiting on anything else any more
+    fn wake(&mut self, transition: &Edge) {
+        if let Some(pid_set) = self.waiting.remove(transition) {
+            let mut pids: Vec<ProcId> = vec![];
+            for pid in pid_set {
+                if self.watches.contains_key(&pid) && !self.fired(pid) {
+                    // the expression came out the same, carry on waiting
+                    self.waiting.entry(transition.clone()).or_default().insert(pid);
+                } else {
+                    pids.push(pid);
+                }
+            }
+            self.scrub_waiting_list(&pids);
+            for pid in pids {
+                info!("*INFO* waking procedure {}", pid);
+                self.watches.remove(&pid);
+                self.q_active.push_front( Event::Resume(pid) );
+            }
+        }
+    }
+
+    // has an expression a procedure waits on changed the way it wants?
+    fn fired(&mut self, pid: ProcId) -> bool {
+        let mut watch = self.watches.remove(&pid).unwrap_or_default();
+        // seen from the waiting procedure, for its automatic variables
+        self.running.push(pid);
+        let mut fired = false;
+        for (event, last) in &mut watch {
+            let value = self.evaluate_self(&event.expr);
+            fired |= match event.sense {
+                Sense::Any => value != *last,
+                sense => edge(last.bit(0), value.bit(0)) == Some(sense),
+            };
+            *last = value;
+        }
+        self.running.pop();
+        self.watches.insert(pid, watch);
+        fired
+    }
+
+    fn scrub_waiting_list(&mut self, pids: &[ProcId]) {
+        for pid in pids {
+            for values in self.waiting.values_mut() {
+                values.remove(pid);
+            }
+        }
+        self.waiting.retain(|_, pids| !pids.is_empty());
+    }
+
+    //
+    // Display stuff
     //
     #[allow(dead_code)]
     pub fn show_proc(&self) {
@@ -334,14 +1871,34 @@ impl Engine {
             println!("\nProcedure {}", i);
             self.procedures[i].show();
         }
+        if !self.assigns.is_empty() {
+            println!("\nContinuous Assignments");
+            for (aid, ca) in self.assigns.iter().enumerate() {
+                println!(" {:3}: {}", aid, ca);
+            }
+        }
+        if !self.gates.is_empty() {
+            println!("\nGates");
+            for (gid, g) in self.gates.iter().enumerate() {
+                println!(" {:3}: {}", gid, g.gate);
+            }
+        }
+        if !self.udps.is_empty() {
+            println!("\nPrimitives");
+            for (uid, u) in self.udps.iter().enumerate() {
+                println!(" {:3}: {}", uid, u.inst);
+            }
+        }
     }
 
     #[allow(dead_code)]
     pub fn show_symtable(&self) {
         println!("\nSymbol Table");
         println!("--------------------------------------");
-        for (var, value) in &self.symtable {
-            println!(" {} = {}", var, value);
+        let mut vars: Vec<&String> = self.symtable.keys().collect();
+        vars.sort();
+        for var in vars {
+            println!(" {} = {}", var, self.symtable[var]);
         }
         println!("--------------------------------------\n");
     }
@@ -350,8 +1907,15 @@ impl Engine {
     pub fn show_queues(&self) {
         println!("\nActive Queue");
         println!("--------------------------------------");
-        for stmt in &self.q_active {
-            println!(" {}", stmt);
+        for event in &self.q_active {
+            println!(" {}", event);
+        }
+        println!("--------------------------------------\n");
+
+        println!("\nInactive Queue");
+        println!("--------------------------------------");
+        for event in &self.q_inactive {
+            println!(" {}", event);
         }
         println!("--------------------------------------\n");
 
@@ -361,6 +1925,76 @@ impl Engine {
             println!(" {}", stmt);
         }
         println!("--------------------------------------\n");
+
+        println!("\nPostponed Queue");
+        println!("--------------------------------------");
+        for (_, args) in &self.q_postponed {
+            let args_str: Vec<String> = args.iter().map(|a| format!("{}", a)).collect();
+            println!(" $strobe({})", args_str.join(", "));
+        }
+        println!("--------------------------------------\n");
+    }
+
+    // a procedure that won't wait never hands back to the scheduler, so
+    // it's stopped as if the time step wouldn't settle
+    fn runaway(&mut self, pid: ProcId, steps: usize) {
+        println!("*ERROR* Time {} didn't settle, a procedure ran {} statements without waiting:",
+                 self.time, steps);
+        let (place, first) = self.describe(pid);
+        println!("    {:3}: {}: {} ...", pid, place, first);
+        self.show_activity();
+        self.result = Some(RunResult::DeltaLimit);
+    }
+
+    // where a procedure is and how it starts, "ALWAYS in tb" and "c = ~c"
+    fn describe(&self, pid: ProcId) -> (String, String) {
+        let p = &self.procedures[pid];
+        // a forked thread's or a function's code is all in its frame
+        let stmts = match p.frames.first() {
+            Some(frame) if p.stmts.is_empty() => &frame.stmts[..],
+            _ => &p.stmts[..],
+        };
+        let first = match stmts.first() {
+            Some(stmt) => format!("{}", stmt),
+            None => String::new(),
+        };
+        (format!("{} in {}", p.kind, p.scope), first)
+    }
+
+    // who was busy in a time step that wouldn't settle - somewhere in
+    // here is a zero-delay loop. Printed even when quiet, it's why the
+    // run failed
+    pub fn show_activity(&self) {
+        println!("Most active in this time step, look for a zero-delay loop:");
+        println!("  Variables:");
+        for (var, n) in busiest(&self.activity.toggles) {
+            println!("    {:<24} changed {} times", var, n);
+        }
+        if !self.activity.wakes.is_empty() {
+            println!("  Procedures:");
+        }
+        for (pid, n) in busiest(&self.activity.wakes) {
+            let (place, first) = self.describe(pid);
+            println!("    {:3}: {}, woken {} times: {} ...", pid, place, n, first);
+        }
+        if !self.activity.updates.is_empty() {
+            println!("  Continuous assignments:");
+        }
+        for (aid, n) in busiest(&self.activity.updates) {
+            println!("    {:3}: {}, evaluated {} times", aid, self.assigns[aid], n);
+        }
+        if !self.activity.gates.is_empty() {
+            println!("  Gates:");
+        }
+        for (gid, n) in busiest(&self.activity.gates) {
+            println!("    {:3}: {}, evaluated {} times", gid, self.gates[gid].gate, n);
+        }
+        if !self.activity.udps.is_empty() {
+            println!("  Primitives:");
+        }
+        for (uid, n) in busiest(&self.activity.udps) {
+            println!("    {:3}: {}, evaluated {} times", uid, self.udps[uid].inst, n);
+        }
     }
 
     #[allow(dead_code)]
@@ -387,3 +2021,538 @@ impl Engine {
 
 }
 
+// collects the variables some code reads: a bit or part select with
+// constant bounds reads just those bits, anything else all of them
+struct Reads<'a> {
+    eng: &'a Engine,
+    vars: RefCell<HashMap<String, Bits>>,
+}
+
+impl<'a> Rewrite for Reads<'a> {
+
+    fn operand(&self, op: &mut Operand) {
+        let (var, bits) = match *op {
+            Operand::Identifier(ref var) => (var, None),
+            Operand::BitSelect(ref var, ref bit) => {
+                let offset = match **bit {
+                    Expression::Const(Operand::Literal(ref i)) if !self.eng.is_memory(var) => {
+                        i.to_u64().and_then(|i| self.eng.var_offset(var, i as usize))
+                    },
+                    _ => None,
+                };
+                (var, offset.map(|o| (o, o)))
+            },
+            Operand::PartSelect(ref var, ref msb, ref lsb) => {
+                let offsets = constant_bounds(msb, lsb).and_then(|(m, l)|
+                    self.eng.var_offset(var, m).and_then(|a| self.eng.var_offset(var, l).map(|b| (a, b))));
+                (var, offsets.map(|(a, b)| (a.min(b), a.max(b))))
+            },
+            _ => return,
+        };
+        let mut vars = self.vars.borrow_mut();
+        let all = vars.entry(var.clone()).or_insert(bits);
+        *all = match (*all, bits) {
+            (Some((lo, hi)), Some((other_lo, other_hi))) => Some((lo.min(other_lo), hi.max(other_hi))),
+            _ => None,
+        };
+    }
+}
+
+// finds the concatenations in some code that are wider than MAX_WIDTH
+struct WidthCheck<'a> {
+    eng: &'a Engine,
+    place: &'a str,
+    errors: RefCell<Vec<String>>,
+}
+
+impl<'a> WidthCheck<'a> {
+    fn check(&self, what: &dyn fmt::Display, width: usize) {
+        if width > MAX_WIDTH {
+            self.errors.borrow_mut().push(format!("{}: {} is {} bits wide, expressions wider than {} bits are not supported",
+                self.place, what, width, MAX_WIDTH));
+        }
+    }
+}
+
+impl<'a> Rewrite for WidthCheck<'a> {
+
+    // {a, b} = ... on the left
+    fn operand(&self, op: &mut Operand) {
+        if let Operand::Concat(ref ops) = *op {
+            if ops.iter().all(|op| self.eng.operand_width(op) <= MAX_WIDTH) {
+                self.check(op, self.eng.operand_width(op));
+            }
+        }
+    }
+
+    // only the innermost one that's too wide gets reported
+    fn expression(&self, expr: &mut Expression) {
+        let parts = match *expr {
+            Expression::Concat(ref exprs) => exprs.iter().collect(),
+            Expression::Replicate(_, ref a) => vec![&**a],
+            _ => return,
+        };
+        if parts.iter().all(|e| self.eng.expr_width(e) <= MAX_WIDTH) {
+            self.check(expr, self.eng.expr_width(expr));
+        }
+    }
+}
+
+impl Scope for Engine {
+
+    fn value_of(&self, var: &str) -> Value {
+        if let Some(&pid) = self.running.last() {
+            if let Some(value) = self.procedures[pid].local(var) {
+                return value.clone();
+            }
+        }
+        if let Some(n) = self.symtable.get(var) {
+            n.clone()
+        } else {
+            info!("*INFO* variable {} has no value yet, using x", var);
+            self.initial_value(var)
+        }
+    }
+
+    // undeclared variables are single-bit regs
+    fn var_width(&self, var: &str) -> usize {
+        match self.variable(var) {
+            Some(v) => v.width(),
+            None => 1,
+        }
+    }
+
+    fn var_offset(&self, var: &str, i: usize) -> Option<usize> {
+        match self.variable(var) {
+            Some(v) => v.offset(i),
+            None if i == 0 => Some(0),
+            None => None,
+        }
+    }
+
+    fn is_memory(&self, var: &str) -> bool {
+        self.variables.get(var).is_some_and(|v| v.array.is_some())
+    }
+
+    // words that were never written are x
+    fn word(&self, var: &str, addr: usize) -> Value {
+        if self.variables.get(var).is_some_and(|v| v.holds(addr)) {
+            self.value_of(&word_name(var, addr))
+        } else {
+            Value::x(self.var_width(var))
+        }
+    }
+
+    // functions have no waiting in them, so they run there and then,
+    // as a procedure of their own
+    fn function(&mut self, name: &str, args: &[Expression]) -> Value {
+        let width = self.function_width(name);
+        if self.running.len() >= MAX_CALL_DEPTH {
+            note!("*WARNING* {} nested more than {} deep, not called", name, MAX_CALL_DEPTH);
+            return Value::x(width);
+        }
+        let frame = match self.enter(name, args, SubroutineKind::Function) {
+            Some(frame) => frame,
+            None => return Value::x(width),
+        };
+        let mut p = Procedure::new(ProcedureType::Initial);
+        p.frames.push(frame);
+        let pid = self.procedures.len();
+        self.procedures.push(p);
+        self.run_proc(pid);
+        self.procedures.pop();
+        self.returned.take().unwrap_or_else(|| Value::x(width))
+    }
+
+    fn function_width(&self, name: &str) -> usize {
+        match function_result(name) {
+            Some(result) => self.var_width(&result),
+            None => 1,
+        }
+    }
+
+    fn var_type(&self, var: &str) -> Type {
+        self.variable(var).map_or(Type::Unsigned, |v| v.ty)
+    }
+
+    fn function_type(&self, name: &str) -> Type {
+        match function_result(name) {
+            Some(result) => self.var_type(&result),
+            None => Type::Unsigned,
+        }
+    }
+
+    fn system_function(&mut self, name: &str, _args: &[Expression]) -> Value {
+        match name {
+            "$time" => Value::new(self.time as u64, 64),
+            "$stime" => Value::new(self.time as u64, 32),
+            "$realtime" => Value::real(self.time as f64),
+            _ => {
+                note!("*WARNING* System function not implemented: {}", name);
+                Value::x(1)
+            },
+        }
+    }
+}
+
+// posedge or negedge, going from `old` to `new` (IEEE 1364 table 9-2)
+fn edge(old: Logic, new: Logic) -> Option<Sense> {
+    match (old, new) {
+        _ if old == new => None,
+        // 0 -> 1/x/z and x/z -> 1 are posedges
+        (Logic::Zero, _) | (_, Logic::One) => Some(Sense::Rise),
+        // 1 -> 0/x/z and x/z -> 0 are negedges
+        (Logic::One, _) | (_, Logic::Zero) => Some(Sense::Fall),
+        _ => None, // x <-> z is no edge at all
+    }
+}
+
+// a function hands back its value in the variable named after it,
+// "tb.f" in "tb.f.f"
+fn function_result(name: &str) -> Option<String> {
+    name.rsplit('.').next().map(|short| format!("{}.{}", name, short))
+}
+
+// has a $monitor argument changed, other than $time?
+fn changes(last: &[Arg], now: &[Arg], args: &[Argument]) -> bool {
+    for ((a, b), arg) in last.iter().zip(now).zip(args) {
+        if let Argument::Expr(Expression::SysCall(ref name, _)) = *arg {
+            if name == "$time" || name == "$stime" {
+                continue;
+            }
+        }
+        if a != b {
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use elaborate;
+    use output::Buffer;
+    use parser;
+
+    // parse and elaborate `src` into an engine ready to run, quietly and
+    // without a VCD file
+    fn engine(src: &str) -> Engine {
+        logging::set_verbosity(logging::QUIET);
+        let descriptions = parser::parse_str("test.v", src, &mut HashMap::new()).unwrap();
+        let mut eng = Engine::new();
+        eng.set_vcd_file(None);
+        eng.load( elaborate::elaborate(&descriptions, None).unwrap() );
+        eng.init().unwrap();
+        eng
+    }
+
+    // what running `src` prints
+    fn output(src: &str) -> String {
+        let mut eng = engine(src);
+        let buffer = Buffer::new();
+        eng.set_output( Box::new(buffer.clone()) );
+        eng.run();
+        buffer.contents()
+    }
+
+    // what init() makes of `src`
+    fn init(src: &str) -> Result<(), String> {
+        logging::set_verbosity(logging::QUIET);
+        let descriptions = parser::parse_str("test.v", src, &mut HashMap::new()).unwrap();
+        let mut eng = Engine::new();
+        eng.set_vcd_file(None);
+        eng.load( elaborate::elaborate(&descriptions, None)? );
+        eng.init()
+    }
+
+    fn value(eng: &Engine, var: &str) -> Option<u64> {
+        eng.symtable[var].to_u64()
+    }
+
+    #[test]
+    fn zero_delay_runs_before_nonblocking_updates() {
+        let mut eng = engine( include_str!("../examples/zero_delay.v") );
+        assert_eq!(eng.run(), RunResult::EventStarved);
+        assert_eq!(value(&eng, "zero_delay.early"), Some(1));
+        assert_eq!(value(&eng, "zero_delay.stale"), Some(0));
+        assert_eq!(value(&eng, "zero_delay.fresh"), Some(1));
+    }
+
+    // the same blocks the other way round: the regions, not the order
+    // the procedures were added in, decide what each one sees
+    #[test]
+    fn zero_delay_regions_dont_depend_on_order() {
+        let mut eng = engine("
+            module zero_delay;
+              reg a, n;
+              reg early, stale, fresh;
+              initial begin #0 stale = n; #1 fresh = n; end
+              initial begin n = 0; n <= 1; end
+              initial a = 1;
+              initial #0 early = a;
+            endmodule
+        ");
+        assert_eq!(eng.run(), RunResult::EventStarved);
+        assert_eq!(value(&eng, "zero_delay.early"), Some(1));
+        assert_eq!(value(&eng, "zero_delay.stale"), Some(0));
+        assert_eq!(value(&eng, "zero_delay.fresh"), Some(1));
+    }
+
+    #[test]
+    fn display_and_write_print_straight_away() {
+        let text = output(r#"
+            module t;
+              reg [7:0] a;
+              initial begin
+                a = 5;
+                $display("a=%0d|%d|%08b|%h|%0h", a, a, a, a, a);
+                $write("no newline ");
+                $write("then %s\n", "one");
+                $display(a, " left over");
+              end
+            endmodule
+        "#);
+        assert_eq!(text, "a=5|  5|00000101|05|5\nno newline then one\n  5 left over\n");
+    }
+
+    #[test]
+    fn strobe_prints_once_the_time_step_settles() {
+        let text = output(r#"
+            module t;
+              reg [3:0] a;
+              initial begin
+                a = 1;
+                $strobe("strobe %0t a=%b", $time, a);
+                $display("display a=%b", a);
+                a <= 2;
+                #3 a = 4;
+                $strobe("strobe %0t a=%0d", $time, a);
+              end
+            endmodule
+        "#);
+        assert_eq!(text, "display a=0001\nstrobe 0 a=0010\nstrobe 3 a=4\n");
+    }
+
+    #[test]
+    fn format_widths() {
+        let text = output(r#"
+            module t;
+              integer i;
+              real r;
+              initial begin
+                i = -42;
+                r = 3.14159;
+                $display("[%d] [%0d] [%6d] [%06d] [%-5d] [%-d]", i, i, 75, 75, 75, 75);
+                $display("[%b] [%0b] [%08b] [%o] [%0h] [%-6h]", 4'b0101, 4'b0101, 3'b101, 6'o17, 12'h0a0, 8'h0f);
+                $display("[%f] [%8.3f] [%.2f] [%-7.1f] [%e] [%g]", r, r, r, r, r, r);
+                $display("[%b] [%h] [%d]", 4'b1x0z, 8'hxz, 4'bxxxx);
+                $display("[%c%c] [%s] [%%] [%m]", "h", "i", "str");
+              end
+            endmodule
+        "#);
+        assert_eq!(text, "\
+            [        -42] [-42] [    75] [000075] [75   ] [75]\n\
+            [0101] [101] [00000101] [17] [a0] [f     ]\n\
+            [3.141590] [   3.142] [3.14] [3.1    ] [3.141590e+00] [3.14159]\n\
+            [1x0z] [xz] [ x]\n\
+            [hi] [str] [%] [t]\n");
+    }
+
+    // procedures that never wait don't get to hang the simulator
+    #[test]
+    fn zero_delay_loops_hit_the_delta_limit() {
+        for src in &["module t; reg c; initial c = 0; always c = ~c; endmodule",
+                     "module t; integer i; initial begin i = 0; forever i = i + 1; end endmodule"]
+        {
+            let mut eng = engine(src);
+            eng.set_max_deltas(Some(10));
+            assert_eq!(eng.run(), RunResult::DeltaLimit);
+        }
+    }
+
+    #[test]
+    fn undrivable_primitive_output_is_an_error() {
+        let result = init("
+            primitive inv(y, a); output y; input a; table 0 : 1; 1 : 0; endtable endprimitive
+            module t; reg a; inv u(1'b0, a); initial a = 0; endmodule
+        ");
+        assert_eq!(result, Err("t.u: output 0 can't be driven".to_string()));
+    }
+
+    #[test]
+    fn expressions_wider_than_a_value_are_errors() {
+        let too_wide = |body: &str| init(&format!("
+            module t; reg [63:0] a, b; reg [7:0] c; {} endmodule", body));
+        assert_eq!(too_wide("initial c = {64'h1, 64'h2} == 0;"),
+            Err("t: {1, 2} is 128 bits wide, expressions wider than 64 bits are not supported".to_string()));
+        assert_eq!(too_wide("initial c = {{2{a}}, b};"),
+            Err("t: {2{t.a}} is 128 bits wide, expressions wider than 64 bits are not supported".to_string()));
+        assert_eq!(too_wide("initial {a, c} = 0;"),
+            Err("t: {t.a, t.c} is 72 bits wide, expressions wider than 64 bits are not supported".to_string()));
+        assert_eq!(too_wide("wire [7:0] y; assign y = {a, c};"),
+            Err("assign t.y = {t.a, t.c}: {t.a, t.c} is 72 bits wide, expressions wider than 64 bits are not supported".to_string()));
+        assert_eq!(too_wide("initial c = {a[31:0], b[31:0]};"), Ok(()));
+    }
+
+    #[test]
+    fn inout_ports_share_the_net_strengths_and_all() {
+        let text = output(r#"
+            module pin(input low, inout line, output seen);
+              pullup (line);
+              bufif1 (line, 1'b0, low);
+              assign seen = line;
+            endmodule
+            module tb;
+              reg low, drive;
+              wire line, seen;
+              tri0 weak;
+              pin p (low, line, seen);
+              assign line = drive;
+              assign (weak0, weak1) weak = 1'b1;
+              initial begin
+                low = 0; drive = 0;
+                #1 $display("%b %b", line, seen);
+                low = 1; drive = 1'bz;
+                #1 $display("%b %b", line, seen);
+                low = 0;
+                #1 $display("%b %b %b", line, seen, weak);
+              end
+            endmodule
+        "#);
+        assert_eq!(text, "0 0\n0 0\n1 1 0\n");
+    }
+
+    #[test]
+    fn inout_port_on_part_of_a_net_is_an_error() {
+        let result = init("
+            module pin(inout line); endmodule
+            module tb; wire [1:0] bus; pin p (bus[0]); initial #1; endmodule
+        ");
+        assert_eq!(result,
+            Err("tb.p: inout port 'line' has to be connected to a whole net of its width, not tb.bus[0]".to_string()));
+    }
+
+    #[test]
+    fn readers_of_a_bus_hear_only_their_own_bits() {
+        let mut eng = engine(r#"
+            module tb;
+              reg in;
+              reg [1:0] i;
+              wire [7:0] w;
+              assign w[0] = in;
+              not g1 (w[1], w[0]);
+              not g2 (w[2], w[1]);
+              buf g3 (w[3], w[i]);
+              assign w[7:4] = {w[2:1], w[3], w[0]};
+              initial begin
+                in = 0; i = 0;
+                #1 in = 1;
+                #1 i = 2;
+              end
+            endmodule
+        "#);
+        assert_eq!(eng.gate_fanout["tb.w"], vec![(0, Some((0, 0))), (1, Some((1, 1))), (2, None)]);
+        assert_eq!(eng.gate_fanout["tb.i"], vec![(2, None)]);
+        assert_eq!(eng.fanout["tb.w"], vec![(1, Some((0, 3)))]);
+        eng.run();
+        assert_eq!(value(&eng, "tb.w"), Some(0b1011_1101));
+    }
+
+    #[test]
+    fn edges_follow_ieee() {
+        use value::Logic::{One, X, Z, Zero};
+        let rises = [(Zero, One), (Zero, X), (Zero, Z), (X, One), (Z, One)];
+        let falls = [(One, Zero), (One, X), (One, Z), (X, Zero), (Z, Zero)];
+        for &(old, new) in &rises {
+            assert_eq!(edge(old, new), Some(Sense::Rise), "{} -> {}", old, new);
+        }
+        for &(old, new) in &falls {
+            assert_eq!(edge(old, new), Some(Sense::Fall), "{} -> {}", old, new);
+        }
+        assert_eq!(edge(X, Z), None);
+        assert_eq!(edge(One, One), None);
+    }
+
+    #[test]
+    fn percent_m_includes_named_blocks() {
+        let text = output(r#"
+            module tb;
+              task t; begin : blk $display("%m"); end endtask
+              initial begin : main
+                $display("%m");
+                begin : inner $display("%m"); end
+                begin $display("%m"); end
+                t;
+              end
+            endmodule
+        "#);
+        assert_eq!(text, "tb.main\ntb.main.inner\ntb.main\ntb.t.blk\n");
+    }
+
+    #[test]
+    fn variables_with_initial_values_and_in_named_blocks() {
+        let text = output(r#"
+            module tb;
+              reg clk = 0;
+              integer i = 3, j;
+              initial begin : main
+                integer k;
+                k = i + 1;
+                begin : inner
+                  integer k;
+                  k = 10;
+                  $display("%m %0d", k);
+                end
+                $display("%m %0d %0d %b %b", k, tb.main.inner.k, clk, j[0]);
+              end
+            endmodule
+        "#);
+        assert_eq!(text, "tb.main.inner 10\ntb.main 4 10 0 x\n");
+    }
+
+    // b changing doesn't hold y up when a already decides it
+    #[test]
+    fn delayed_assign_is_only_superseded_by_a_new_value() {
+        let text = output(r#"
+            module t;
+              reg a, b;
+              wire y;
+              assign #5 y = a | b;
+              always @(y) $display("%0t y=%b", $time, y);
+              initial begin
+                a = 0; b = 0;
+                #20 a = 1;
+                #1 b = 1; #1 b = 0; #1 b = 1; #1 b = 0;
+              end
+            endmodule
+        "#);
+        assert_eq!(text, "5 y=0\n25 y=1\n");
+    }
+
+    // each turn of the loop reads the one before it, g[i-1].o
+    #[test]
+    fn generate_scope_index_is_folded() {
+        let text = output(r#"
+            module tb;
+              reg a;
+              genvar i;
+              for (i = 0; i < 4; i = i + 1) begin : g
+                wire o;
+                if (i == 0) begin : first
+                  assign o = a;
+                end else begin : rest
+                  assign o = ~g[i - 1].o;
+                end
+              end
+              initial begin
+                a = 1;
+                #1 $display("%b%b%b%b", g[0].o, g[1].o, g[2].o, g[3].o);
+              end
+            endmodule
+        "#);
+        assert_eq!(text, "1010\n");
+    }
+}
@@ -0,0 +1,580 @@
+//! Expression evaluation
+//!
+//! Anything that can look up variables gets Verilog expression
+//! evaluation for free by implementing `Scope`. Sizing follows IEEE 1364
+//! section 4.4: operands of arithmetic and bitwise operators are
+//! extended to the width of the context they appear in, while
+//! conditions, shift amounts, concatenations and the operands of
+//! relational and logical operators are sized by themselves.
+//!
+//! Signing follows section 4.5: an expression is signed only if all of
+//! its context-determined operands are, and real if any of them is, and
+//! the operands are extended or converted to match before anything is
+//! worked out.
+
+use procedure::*;
+use value::{Logic, Type};
+
+pub trait Scope {
+
+    fn value_of(&self, var: &str) -> Value;
+
+    fn var_width(&self, var: &str) -> usize;
+
+    // a bit index as written in the source, as an offset from the lsb
+    fn var_offset(&self, var: &str, i: usize) -> Option<usize>;
+
+    // "reg [7:0] mem [0:255]", whose bit selects pick a word
+    fn is_memory(&self, _var: &str) -> bool {
+        false
+    }
+
+    fn word(&self, var: &str, _addr: usize) -> Value {
+        Value::x(self.var_width(var))
+    }
+
+    // integer and reg signed variables are signed, and reals real
+    fn var_type(&self, _var: &str) -> Type {
+        Type::Unsigned
+    }
+
+    // $time and friends
+    fn system_function(&mut self, name: &str, _args: &[Expression]) -> Value {
+        note!("*WARNING* System function not implemented: {}", name);
+        Value::x(system_function_width(name))
+    }
+
+    // a function of the design's own
+    fn function(&mut self, name: &str, _args: &[Expression]) -> Value {
+        note!("*WARNING* Function not available here: {}", name);
+        Value::x(1)
+    }
+
+    fn function_width(&self, _name: &str) -> usize {
+        1
+    }
+
+    fn function_type(&self, _name: &str) -> Type {
+        Type::Unsigned
+    }
+
+    // evaluate an index expression, None if it's x or z
+    fn index(&mut self, expr: &Expression) -> Option<usize> {
+        let width = self.expr_width(expr);
+        self.evaluate(expr, width).to_u64().map(|n| n as usize)
+    }
+
+    fn lookup(&mut self, op: &Operand) -> Value {
+        match *op {
+            Operand::Literal(ref num) => num.clone(),
+            Operand::Identifier(ref id) => {
+                self.value_of(id).with_type(self.var_type(id))
+            },
+            Operand::BitSelect(ref id, ref addr) if self.is_memory(id) => {
+                let word = match self.index(addr) {
+                    Some(addr) => self.word(id, addr),
+                    None => Value::x(self.var_width(id)),
+                };
+                word.with_type(self.var_type(id))
+            },
+            Operand::BitSelect(ref id, ref bit) => {
+                let offset = self.index(bit).and_then(|i| self.var_offset(id, i));
+                match offset {
+                    Some(offset) => self.value_of(id).slice(offset, 1),
+                    None => Value::x(1),
+                }
+            },
+            Operand::PartSelect(ref id, ref msb, ref lsb) => {
+                let width = self.operand_width(op);
+                let offsets = constant_bounds(msb, lsb)
+                    .and_then(|(msb, lsb)| self.var_offset(id, msb).and_then(|a|
+                        self.var_offset(id, lsb).map(|b| (a, b))));
+                match offsets {
+                    Some((a, b)) => self.value_of(id).slice(a.min(b), width),
+                    None => Value::x(width),
+                }
+            },
+            Operand::Concat(ref ops) => {
+                let mut value: Option<Value> = None;
+                for op in ops {
+                    let v = self.lookup(op);
+                    value = match value {
+                        Some(high) => Some(high.concat(&v)),
+                        None => Some(v),
+                    };
+                }
+                value.unwrap_or_else(|| Value::x(1))
+            },
+        }
+    }
+
+    fn operand_width(&self, op: &Operand) -> usize {
+        match *op {
+            Operand::Literal(ref num) => num.width(),
+            Operand::Identifier(ref var) => self.var_width(var),
+            Operand::BitSelect(ref var, _) if self.is_memory(var) => self.var_width(var),
+            Operand::BitSelect(..) => 1,
+            Operand::PartSelect(_, ref msb, ref lsb) => match constant_bounds(msb, lsb) {
+                Some((msb, lsb)) => msb.max(lsb) - msb.min(lsb) + 1,
+                None => 1,
+            },
+            Operand::Concat(ref ops) => ops.iter().map(|op| self.operand_width(op)).sum(),
+        }
+    }
+
+    // the self-determined width of an expression
+    fn expr_width(&self, expr: &Expression) -> usize {
+        match *expr {
+            Expression::Const(ref op) => self.operand_width(op),
+            Expression::Unary(op, ref a) => match op {
+                UnaryOp::Plus | UnaryOp::Minus | UnaryOp::Not => self.expr_width(a),
+                _ => 1,
+            },
+            Expression::Binary(op, ref a, ref b) => match op {
+                BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div |
+                BinaryOp::Mod | BinaryOp::And | BinaryOp::Or | BinaryOp::Xor |
+                BinaryOp::Xnor => self.expr_width(a).max(self.expr_width(b)),
+                BinaryOp::Pow | BinaryOp::Shl | BinaryOp::Shr |
+                BinaryOp::AShl | BinaryOp::AShr => self.expr_width(a),
+                _ => 1,
+            },
+            Expression::Ternary(_, ref a, ref b) => {
+                self.expr_width(a).max(self.expr_width(b))
+            },
+            Expression::Concat(ref exprs) => {
+                exprs.iter().map(|e| self.expr_width(e)).sum()
+            },
+            Expression::Replicate(ref n, ref a) => {
+                // the count has to be a constant, so anything goes here
+                let count = match **n {
+                    Expression::Const(Operand::Literal(ref num)) => num.to_u64().unwrap_or(1),
+                    _ => 1,
+                };
+                count as usize * self.expr_width(a)
+            },
+            Expression::SysCall(ref name, ref args) => match (name.as_str(), args.first()) {
+                ("$signed", Some(a)) | ("$unsigned", Some(a)) => self.expr_width(a),
+                _ => system_function_width(name),
+            },
+            Expression::Call(ref name, _) => self.function_width(name),
+        }
+    }
+
+    fn operand_type(&self, op: &Operand) -> Type {
+        match *op {
+            Operand::Literal(ref num) => num.ty(),
+            Operand::Identifier(ref var) => self.var_type(var),
+            Operand::BitSelect(ref var, _) if self.is_memory(var) => self.var_type(var),
+            _ => Type::Unsigned,
+        }
+    }
+
+    // signed, unsigned or real, which the operands decide and the
+    // context never does
+    fn expr_type(&self, expr: &Expression) -> Type {
+        match *expr {
+            Expression::Const(ref op) => self.operand_type(op),
+            Expression::Unary(op, ref a) => match op {
+                UnaryOp::Plus | UnaryOp::Minus | UnaryOp::Not => self.expr_type(a),
+                _ => Type::Unsigned,
+            },
+            Expression::Binary(op, ref a, ref b) => match op {
+                BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div |
+                BinaryOp::Mod | BinaryOp::And | BinaryOp::Or | BinaryOp::Xor |
+                BinaryOp::Xnor => combine(self.expr_type(a), self.expr_type(b)),
+                // the right operand is self-determined, unless it's real
+                BinaryOp::Pow => match self.expr_type(b) {
+                    Type::Real => Type::Real,
+                    _ => self.expr_type(a),
+                },
+                BinaryOp::Shl | BinaryOp::Shr | BinaryOp::AShl | BinaryOp::AShr => {
+                    self.expr_type(a)
+                },
+                _ => Type::Unsigned,
+            },
+            Expression::Ternary(_, ref a, ref b) => {
+                combine(self.expr_type(a), self.expr_type(b))
+            },
+            Expression::Concat(_) | Expression::Replicate(..) => Type::Unsigned,
+            Expression::SysCall(ref name, _) => system_function_type(name),
+            Expression::Call(ref name, _) => self.function_type(name),
+        }
+    }
+
+    // is a condition true? Reals are if they aren't 0
+    fn condition(&mut self, expr: &Expression) -> Logic {
+        if self.expr_type(expr) == Type::Real {
+            if self.evaluate_real(expr) != 0.0 { Logic::One } else { Logic::Zero }
+        } else {
+            self.evaluate_self(expr).truth()
+        }
+    }
+
+    // what to assign to an lvalue: a real if it's a real variable,
+    // otherwise sized to fit
+    fn evaluate_for(&mut self, lvalue: &Operand, expr: &Expression) -> Value {
+        if self.operand_type(lvalue) == Type::Real {
+            Value::real( self.evaluate_real(expr) )
+        } else {
+            let width = self.operand_width(lvalue);
+            self.evaluate(expr, width)
+        }
+    }
+
+    // evaluate in a context at least `width` bits wide; a real
+    // expression is rounded to an integer
+    fn evaluate(&mut self, expr: &Expression, width: usize) -> Value {
+        match self.expr_type(expr) {
+            Type::Real => {
+                let width = width.max( self.expr_width(expr) );
+                Value::from_real(self.evaluate_real(expr), width).with_type(Type::Signed)
+            },
+            ty => self.evaluate_in(expr, width, ty),
+        }
+    }
+
+    // evaluate as part of an expression of type `ty`, which all the
+    // context-determined operands are converted to
+    fn evaluate_in(&mut self, expr: &Expression, width: usize, ty: Type) -> Value {
+        let width = width.max( self.expr_width(expr) );
+        let value = match *expr {
+            Expression::Const(ref op) => {
+                self.lookup(op).with_type(ty).extend(width)
+            },
+
+            Expression::Unary(op, ref a) => {
+                match op {
+                    UnaryOp::Plus => self.evaluate_in(a, width, ty),
+                    UnaryOp::Minus => self.evaluate_in(a, width, ty).neg(),
+                    UnaryOp::Not => self.evaluate_in(a, width, ty).not(),
+                    _ => {
+                        let a = self.evaluate_self(a);
+                        let bit = match op {
+                            UnaryOp::LogNot => a.log_not(),
+                            UnaryOp::RedAnd => a.reduce_and(),
+                            UnaryOp::RedNand => a.reduce_and().not(),
+                            UnaryOp::RedOr => a.reduce_or(),
+                            UnaryOp::RedNor => a.reduce_or().not(),
+                            UnaryOp::RedXor => a.reduce_xor(),
+                            _ => a.reduce_xor().not(),
+                        };
+                        bit.resize(width)
+                    },
+                }
+            },
+
+            Expression::Binary(op, ref a, ref b) => {
+                match op {
+                    BinaryOp::LogAnd | BinaryOp::LogOr => {
+                        let a = Value::from_logic( self.condition(a) );
+                        let b = Value::from_logic( self.condition(b) );
+                        let bit = if op == BinaryOp::LogAnd {
+                            a.log_and(&b)
+                        } else {
+                            a.log_or(&b)
+                        };
+                        bit.resize(width)
+                    },
+                    BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge |
+                    BinaryOp::Eq | BinaryOp::Ne | BinaryOp::CaseEq | BinaryOp::CaseNe
+                        if combine(self.expr_type(a), self.expr_type(b)) == Type::Real =>
+                    {
+                        let a = self.evaluate_real(a);
+                        let b = self.evaluate_real(b);
+                        let bit = match op {
+                            BinaryOp::Lt => a < b,
+                            BinaryOp::Le => a <= b,
+                            BinaryOp::Gt => a > b,
+                            BinaryOp::Ge => a >= b,
+                            BinaryOp::Eq | BinaryOp::CaseEq => a == b,
+                            _ => a != b,
+                        };
+                        Value::from_bool(bit).resize(width)
+                    },
+                    BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge |
+                    BinaryOp::Eq | BinaryOp::Ne | BinaryOp::CaseEq | BinaryOp::CaseNe => {
+                        // operands are sized and signed to each other, not the context
+                        let w = self.expr_width(a).max( self.expr_width(b) );
+                        let t = combine(self.expr_type(a), self.expr_type(b));
+                        let a = self.evaluate_in(a, w, t);
+                        let b = self.evaluate_in(b, w, t);
+                        let bit = match op {
+                            BinaryOp::Lt => a.lt(&b),
+                            BinaryOp::Le => a.le(&b),
+                            BinaryOp::Gt => a.gt(&b),
+                            BinaryOp::Ge => a.ge(&b),
+                            BinaryOp::Eq => a.eq(&b),
+                            BinaryOp::Ne => a.ne(&b),
+                            BinaryOp::CaseEq => a.case_eq(&b),
+                            _ => a.case_ne(&b),
+                        };
+                        bit.resize(width)
+                    },
+                    BinaryOp::Shl | BinaryOp::Shr | BinaryOp::AShl | BinaryOp::AShr => {
+                        let a = self.evaluate_in(a, width, ty);
+                        let b = self.evaluate_self(b);
+                        match op {
+                            BinaryOp::Shl | BinaryOp::AShl => a.shl(&b),
+                            BinaryOp::AShr => a.ashr(&b),
+                            _ => a.shr(&b),
+                        }
+                    },
+                    BinaryOp::Pow => {
+                        let a = self.evaluate_in(a, width, ty);
+                        let b = self.evaluate_self(b);
+                        a.pow(&b)
+                    },
+                    _ => {
+                        let a = self.evaluate_in(a, width, ty);
+                        let b = self.evaluate_in(b, width, ty);
+                        match op {
+                            BinaryOp::Add => a.add(&b),
+                            BinaryOp::Sub => a.sub(&b),
+                            BinaryOp::Mul => a.mul(&b),
+                            BinaryOp::Div => a.div(&b),
+                            BinaryOp::Mod => a.rem(&b),
+                            BinaryOp::And => a.and(&b),
+                            BinaryOp::Or => a.or(&b),
+                            BinaryOp::Xor => a.xor(&b),
+                            _ => a.xnor(&b),
+                        }
+                    },
+                }
+            },
+
+            Expression::Ternary(ref c, ref a, ref b) => {
+                match self.condition(c) {
+                    Logic::One => self.evaluate_in(a, width, ty),
+                    Logic::Zero => self.evaluate_in(b, width, ty),
+                    _ => {
+                        // unknown condition: keep the bits both sides agree on
+                        let a = self.evaluate_in(a, width, ty);
+                        a.merge( &self.evaluate_in(b, width, ty) )
+                    },
+                }
+            },
+
+            Expression::Concat(ref exprs) => {
+                let mut value: Option<Value> = None;
+                for e in exprs {
+                    let v = self.evaluate_self(e);
+                    value = match value {
+                        Some(high) => Some(high.concat(&v)),
+                        None => Some(v),
+                    };
+                }
+                value.unwrap_or_else(|| Value::x(1)).resize(width)
+            },
+
+            Expression::Replicate(ref n, ref a) => {
+                let count = self.evaluate_self(n).to_u64().unwrap_or(0);
+                let v = self.evaluate_self(a);
+                let mut value = v.clone();
+                for _ in 1..count {
+                    value = value.concat(&v);
+                }
+                value.resize(width)
+            },
+
+            Expression::SysCall(ref name, ref args) => match (name.as_str(), args.first()) {
+                ("$signed", Some(a)) | ("$unsigned", Some(a)) => {
+                    self.evaluate_self(a).with_type(ty).extend(width)
+                },
+                ("$rtoi", Some(a)) => {
+                    let n = self.evaluate_real(a).trunc();
+                    Value::from_real(n, 32).with_type(ty).extend(width)
+                },
+                _ => self.system_function(name, args).resize(width),
+            },
+
+            Expression::Call(ref name, ref args) => {
+                self.function(name, args).with_type(ty).extend(width)
+            },
+        };
+        value.with_type(ty)
+    }
+
+    // evaluate as a real number, with anything that isn't one converted
+    fn evaluate_real(&mut self, expr: &Expression) -> f64 {
+        match *expr {
+            Expression::Const(ref op) => self.lookup(op).to_real(),
+            Expression::Unary(UnaryOp::Plus, ref a) => self.evaluate_real(a),
+            Expression::Unary(UnaryOp::Minus, ref a) => -self.evaluate_real(a),
+            Expression::Binary(op, ref a, ref b) if is_arithmetic(op) => {
+                let a = self.evaluate_real(a);
+                let b = self.evaluate_real(b);
+                match op {
+                    BinaryOp::Add => a + b,
+                    BinaryOp::Sub => a - b,
+                    BinaryOp::Mul => a * b,
+                    BinaryOp::Div => a / b,
+                    BinaryOp::Mod => a % b,
+                    _ => a.powf(b),
+                }
+            },
+            Expression::Ternary(ref c, ref a, ref b) => {
+                match self.condition(c) {
+                    Logic::One => self.evaluate_real(a),
+                    Logic::Zero => self.evaluate_real(b),
+                    _ => 0.0,
+                }
+            },
+            Expression::SysCall(ref name, ref args) => match (name.as_str(), args.first()) {
+                ("$itor", Some(a)) => self.evaluate_self(a).to_real(),
+                _ => self.system_function(name, args).to_real(),
+            },
+            Expression::Call(ref name, ref args) => {
+                let ty = self.function_type(name);
+                self.function(name, args).with_type(ty).to_real()
+            },
+            _ if self.expr_type(expr) == Type::Real => {
+                note!("*WARNING* {} can't be done on a real", expr);
+                0.0
+            },
+            _ => self.evaluate_self(expr).to_real(),
+        }
+    }
+
+    // evaluate at the expression's own width
+    fn evaluate_self(&mut self, expr: &Expression) -> Value {
+        let width = self.expr_width(expr);
+        self.evaluate(expr, width)
+    }
+}
+
+pub fn system_function_width(name: &str) -> usize {
+    match name {
+        "$time" | "$realtime" | "$itor" => 64,
+        "$stime" | "$rtoi" => 32,
+        _ => 1,
+    }
+}
+
+fn system_function_type(name: &str) -> Type {
+    match name {
+        "$signed" | "$rtoi" => Type::Signed,
+        "$realtime" | "$itor" => Type::Real,
+        _ => Type::Unsigned,
+    }
+}
+
+// an operator's type from its operands': real if either is, signed
+// only if both are
+fn combine(a: Type, b: Type) -> Type {
+    match (a, b) {
+        (Type::Real, _) | (_, Type::Real) => Type::Real,
+        (Type::Signed, Type::Signed) => Type::Signed,
+        _ => Type::Unsigned,
+    }
+}
+
+fn is_arithmetic(op: BinaryOp) -> bool {
+    matches!(op, BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div |
+        BinaryOp::Mod | BinaryOp::Pow)
+}
+
+// the bounds of a part select, which elaboration has folded to numbers
+pub fn constant_bounds(msb: &Expression, lsb: &Expression) -> Option<(usize, usize)> {
+    match (msb, lsb) {
+        (&Expression::Const(Operand::Literal(ref msb)),
+         &Expression::Const(Operand::Literal(ref lsb))) => {
+            match (msb.to_u64(), lsb.to_u64()) {
+                (Some(msb), Some(lsb)) => Some((msb as usize, lsb as usize)),
+                _ => None,
+            }
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use parser;
+
+    // variables numbered from bit 0, as wide as their values
+    struct Vars(HashMap<&'static str, Value>);
+
+    impl Scope for Vars {
+
+        fn value_of(&self, var: &str) -> Value {
+            self.0[var].clone()
+        }
+
+        fn var_width(&self, var: &str) -> usize {
+            self.0[var].width()
+        }
+
+        fn var_offset(&self, var: &str, i: usize) -> Option<usize> {
+            if i < self.var_width(var) { Some(i) } else { None }
+        }
+
+        fn var_type(&self, var: &str) -> Type {
+            self.0[var].ty()
+        }
+    }
+
+    fn vars() -> Vars {
+        let mut vars = HashMap::new();
+        vars.insert("a", Value::new(0xf, 4));
+        vars.insert("b", Value::new(1, 4));
+        vars.insert("s", Value::new(0xf, 4).with_type(Type::Signed));
+        vars.insert("k", Value::parse(Some(4), 'b', "1x01").unwrap());
+        Vars(vars)
+    }
+
+    // `src` worked out in a context `width` bits wide
+    fn eval(src: &str, width: usize) -> String {
+        let expr = parser::parse_expression(src).unwrap();
+        vars().evaluate(&expr, width).to_bin_string()
+    }
+
+    fn width(src: &str) -> usize {
+        vars().expr_width( &parser::parse_expression(src).unwrap() )
+    }
+
+    #[test]
+    fn operands_take_the_width_of_the_context() {
+        assert_eq!(eval("a + b", 4), "0000");
+        assert_eq!(eval("a + b", 5), "10000");
+        assert_eq!(eval("a + b == 5'd16", 1), "1");
+        assert_eq!(eval("{a + b}", 5), "00000");
+        assert_eq!(eval("1'b1 << 3", 4), "1000");
+        assert_eq!(eval("~b", 6), "111110");
+    }
+
+    #[test]
+    fn self_determined_widths() {
+        assert_eq!(width("a + 6'd1"), 6);
+        assert_eq!(width("a == b"), 1);
+        assert_eq!(width("{a, b[0], a[2:1]}"), 7);
+        assert_eq!(width("{3{a}}"), 12);
+        assert_eq!(width("a << 8'd1"), 4);
+        assert_eq!(width("c ? a : 6'd0"), 6);
+        assert_eq!(width("$time"), 64);
+    }
+
+    #[test]
+    fn signed_only_when_every_operand_is() {
+        assert_eq!(eval("s + 4'sd1", 8), "00000000");
+        assert_eq!(eval("s + 4'd1", 8), "00010000");
+        assert_eq!(eval("$unsigned(s) < 0", 1), "0");
+        assert_eq!(eval("s < 0", 1), "1");
+        assert_eq!(eval("$signed(a) >>> 1", 4), "1111");
+        assert_eq!(eval("1 + 0.5", 4).trim_start_matches('0'), "10");
+        assert_eq!(vars().evaluate_real(&parser::parse_expression("a / 2.0").unwrap()), 7.5);
+    }
+
+    #[test]
+    fn unknown_bits_and_selects() {
+        assert_eq!(eval("k[2] ? 4'b1010 : 4'b1000", 4), "10x0");
+        assert_eq!(eval("k & 4'b0011", 4), "0001");
+        assert_eq!(eval("k + 4'd1", 4), "xxxx");
+        assert_eq!(eval("k[3:2]", 2), "1x");
+        assert_eq!(eval("a[7]", 1), "x");
+        assert_eq!(eval("k === 4'b1x01", 1), "1");
+        assert_eq!(eval("k == 4'b1x01", 1), "x");
+    }
+}
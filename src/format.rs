@@ -0,0 +1,297 @@
+//! Formatted output for the printing system tasks
+//!
+//! `$display("a=%b y=%h", a, y)`: a string argument is a format string
+//! whose specifiers use up the values after it; values left over are
+//! printed in decimal.
+//!
+//! Specifiers are `%b`, `%o`, `%h`/`%x`, `%d`, `%t`, `%s`, `%c`, `%m` and
+//! `%%`, and `%f`, `%e` and `%g` for reals. Values are printed as wide
+//! as their widest value, so columns line up. A field width changes
+//! that: `%0d` and `%0h` print just the digits needed, `%6d` pads with
+//! spaces, `%-6d` pads on the right and `%08b` with zeros, and `%8.3f`
+//! has three decimal places.
+
+use std::char;
+
+use value::{Value, Logic, Type};
+
+// %t prints this wide, like the default $timeformat
+const TIME_WIDTH: usize = 20;
+
+// decimal places for %f and %e, and significant digits for %g
+const PRECISION: usize = 6;
+
+// an argument once it's been evaluated
+#[derive(PartialEq, Debug, Clone)]
+pub enum Arg {
+    Str(String),
+    Val(Value),
+}
+
+// `scope` is what %m prints
+pub fn render(args: &[Arg], scope: &str) -> String {
+    let mut out = String::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            Arg::Val(ref value) if value.is_real() => {
+                out.push_str( &value.to_real().to_string() );
+            },
+            Arg::Val(ref value) => {
+                out.push_str( &radix(value, 'd') );
+            },
+            Arg::Str(ref fmt) => {
+                let mut chars = fmt.chars().peekable();
+                while let Some(c) = chars.next() {
+                    if c != '%' {
+                        out.push(c);
+                        continue;
+                    }
+                    let mut field = String::new();
+                    if chars.peek() == Some(&'-') {
+                        field.push('-');
+                        chars.next();
+                    }
+                    while let Some(&d) = chars.peek() {
+                        if !d.is_ascii_digit() && d != '.' {
+                            break;
+                        }
+                        field.push(d);
+                        chars.next();
+                    }
+                    let spec = match chars.next() {
+                        Some(spec) => spec.to_ascii_lowercase(),
+                        None => break,
+                    };
+                    let text = match spec {
+                        '%' => "%".to_string(),
+                        'm' => scope.to_string(),
+                        _ => match args.next() {
+                            Some(Arg::Val(ref value)) if "feg".contains(spec) => {
+                                let precision = field.split('.').nth(1)
+                                    .and_then(|p| p.parse().ok())
+                                    .unwrap_or(PRECISION);
+                                real(value.to_real(), spec, precision)
+                            },
+                            Some(Arg::Val(ref value)) => radix(value, spec),
+                            Some(Arg::Str(ref s)) => s.clone(),
+                            None => {
+                                note!("*WARNING* not enough arguments for '%{}'", spec);
+                                continue;
+                            },
+                        },
+                    };
+                    out.push_str( &pad(text, spec, &field) );
+                }
+            },
+        }
+    }
+    out
+}
+
+// apply a field width: "" leaves it be, "0" trims, "6" and "06" pad on
+// the left and "-6" on the right
+fn pad(text: String, spec: char, field: &str) -> String {
+    if field.is_empty() || spec == '%' || spec == 'm' {
+        return text;
+    }
+    let text = match spec {
+        'd' | 't' => text.trim_start().to_string(),
+        'b' | 'o' | 'h' | 'x' => {
+            let trimmed = text.trim_start_matches('0');
+            if trimmed.is_empty() {
+                "0".to_string()
+            } else {
+                trimmed.to_string()
+            }
+        },
+        _ => text,
+    };
+    let left = field.starts_with('-');
+    let field = field.trim_start_matches('-');
+    let width: usize = field.split('.').next().unwrap_or("").parse().unwrap_or(0);
+    if text.len() >= width {
+        text
+    } else if left {
+        format!("{:<1$}", text, width)
+    } else if field.starts_with('0') {
+        format!("{}{}", "0".repeat(width - text.len()), text)
+    } else {
+        format!("{:>1$}", text, width)
+    }
+}
+
+// a value in the radix the specifier asks for
+pub fn radix(value: &Value, spec: char) -> String {
+    if value.is_real() {
+        // rounded to an integer first
+        let n = Value::from_real(value.to_real(), 64).with_type(Type::Signed);
+        return radix(&n, spec);
+    }
+    match spec {
+        'b' => value.to_bin_string(),
+        'o' => digits(value, 3),
+        'h' | 'x' => digits(value, 4),
+        't' => format!("{:>1$}", decimal(value), TIME_WIDTH),
+        's' => string(value),
+        'c' => string( &value.slice(0, 8) ),
+        'd' => {
+            // room for the most negative number if it's signed
+            let width = if value.is_signed() {
+                (1u64 << (value.width() - 1)).to_string().len() + 1
+            } else {
+                Value::new(!0, value.width()).to_u64()
+                    .map_or(1, |max| max.to_string().len())
+            };
+            format!("{:>1$}", decimal(value), width)
+        },
+        _ => {
+            note!("*WARNING* unknown format specifier '%{}'", spec);
+            decimal(value)
+        },
+    }
+}
+
+fn decimal(value: &Value) -> String {
+    match (value.to_u64(), value.to_i64()) {
+        (_, Some(n)) if value.is_signed() => n.to_string(),
+        (Some(n), _) => n.to_string(),
+        _ => unknown(value, 0, value.width()).to_string(),
+    }
+}
+
+// C's %f, %e and %g
+fn real(n: f64, spec: char, precision: usize) -> String {
+    match spec {
+        'f' => format!("{:.*}", precision, n),
+        'e' => exponent(n, precision),
+        _ => {
+            // %e for very big and very small numbers, %f otherwise, and
+            // no zeros on the end either way
+            let digits = precision.max(1);
+            let exp = if n == 0.0 { 0 } else { n.abs().log10().floor() as i32 };
+            let text = if exp < -4 || exp >= digits as i32 {
+                exponent(n, digits - 1)
+            } else {
+                format!("{:.*}", (digits as i32 - 1 - exp) as usize, n)
+            };
+            let (mantissa, exp) = text.split_at( text.find('e').unwrap_or(text.len()) );
+            let mantissa = if mantissa.contains('.') {
+                mantissa.trim_end_matches('0').trim_end_matches('.')
+            } else {
+                mantissa
+            };
+            format!("{}{}", mantissa, exp)
+        },
+    }
+}
+
+// 1.500000e+02, where Rust would write 1.5e2
+fn exponent(n: f64, precision: usize) -> String {
+    let text = format!("{:.*e}", precision, n);
+    let (mantissa, exp) = text.split_at( text.find('e').unwrap_or(text.len()) );
+    let exp: i32 = exp.get(1..).and_then(|e| e.parse().ok()).unwrap_or(0);
+    format!("{}e{}{:02}", mantissa, if exp < 0 { '-' } else { '+' }, exp.abs())
+}
+
+// octal or hex digits, most significant first
+fn digits(value: &Value, bits: usize) -> String {
+    let mut s = String::new();
+    let mut lsb = 0;
+    while lsb < value.width() {
+        let w = bits.min(value.width() - lsb);
+        let digit = match value.slice(lsb, w).to_u64() {
+            Some(n) => char::from_digit(n as u32, 16).unwrap(),
+            None => unknown(value, lsb, w),
+        };
+        s.insert(0, digit);
+        lsb += bits;
+    }
+    s
+}
+
+// eight bits to a character, leading zero bytes aren't printed
+fn string(value: &Value) -> String {
+    let mut s = String::new();
+    let mut lsb = 0;
+    while lsb < value.width() {
+        let w = 8.min(value.width() - lsb);
+        match value.slice(lsb, w).to_u64() {
+            Some(0) => {},
+            Some(n) => s.insert(0, n as u8 as char),
+            None => s.insert(0, '?'),
+        }
+        lsb += 8;
+    }
+    s
+}
+
+// a digit with x or z bits in it: lower case if they all are
+fn unknown(value: &Value, lsb: usize, width: usize) -> char {
+    let bits: Vec<Logic> = (lsb..lsb + width).map(|i| value.bit(i)).collect();
+    if bits.iter().all(|b| *b == Logic::X) {
+        'x'
+    } else if bits.contains(&Logic::X) {
+        'X'
+    } else if bits.iter().all(|b| *b == Logic::Z) {
+        'z'
+    } else {
+        'Z'
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn show(fmt: &str, values: &[Value]) -> String {
+        let mut args = vec![Arg::Str(fmt.to_string())];
+        args.extend(values.iter().cloned().map(Arg::Val));
+        render(&args, "tb.u")
+    }
+
+    fn bits(digits: &str) -> Value {
+        Value::parse(Some(digits.len()), 'b', digits).unwrap()
+    }
+
+    #[test]
+    fn radixes_are_as_wide_as_the_widest_value() {
+        let v = Value::new(10, 8);
+        assert_eq!(show("%b %o %h %d|", &[v.clone(), v.clone(), v.clone(), v]),
+            "00001010 012 0a  10|");
+        assert_eq!(show("%d", &[Value::new(3, 4).with_type(Type::Signed)]), " 3");
+        assert_eq!(show("%d", &[Value::new(0xf, 4).with_type(Type::Signed)]), "-1");
+        assert_eq!(show("%t|", &[Value::new(5, 64)]), format!("{:>20}|", 5));
+    }
+
+    #[test]
+    fn field_widths() {
+        let v = Value::new(10, 8);
+        assert_eq!(show("[%0d] [%0h] [%0b]", &[v.clone(), v.clone(), v.clone()]), "[10] [a] [1010]");
+        assert_eq!(show("[%5d] [%-5d] [%05b]", &[v.clone(), v.clone(), Value::new(3, 8)]),
+            "[   10] [10   ] [00011]");
+        assert_eq!(show("[%0b]", &[Value::new(0, 8)]), "[0]");
+        assert_eq!(show("[%8.3f] [%e] [%g] [%g]",
+            &[Value::real(1.23456), Value::real(150.0), Value::real(0.5), Value::real(1e-6)]),
+            "[   1.235] [1.500000e+02] [0.5] [1e-06]");
+    }
+
+    #[test]
+    fn unknown_digits() {
+        assert_eq!(show("%h", &[bits("xxxx0101")]), "x5");
+        assert_eq!(show("%h", &[bits("x1010101")]), "X5");
+        assert_eq!(show("%o", &[bits("zzz000")]), "z0");
+        assert_eq!(show("%h", &[bits("z1010101")]), "Z5");
+        assert_eq!(show("%d", &[bits("x000")]), " X");
+        assert_eq!(show("%d", &[Value::x(8)]), "  x");
+    }
+
+    #[test]
+    fn strings_scopes_and_leftovers() {
+        let hi = Value::new(0x4869, 16);
+        assert_eq!(show("%s %c %m 100%%", &[hi, Value::new(0x41, 8)]), "Hi A tb.u 100%");
+        assert_eq!(render(&[Arg::Val(Value::new(7, 4)), Arg::Str(" and ".to_string()),
+            Arg::Val(Value::real(1.5))], ""), " 7 and 1.5");
+        assert_eq!(show("%d %d", &[Value::new(1, 1)]), "1 ");
+    }
+}
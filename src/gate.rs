@@ -0,0 +1,181 @@
+//! Gate primitives
+//!
+//! `and`, `or`, `bufif1` and the rest, built into the language. Every
+//! terminal of a gate is a single bit, so gates are worked out a Logic
+//! at a time here rather than going through the expression evaluator.
+
+use std::fmt;
+
+use net::{self, DriveStrength};
+use procedure::Time;
+use value::Logic;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum GateKind {
+    And,
+    Nand,
+    Or,
+    Nor,
+    Xor,
+    Xnor,
+    Buf,
+    Not,
+    Bufif0,
+    Bufif1,
+    Notif0,
+    Notif1,
+    Pullup,
+    Pulldown,
+}
+
+const KEYWORDS: [(&str, GateKind); 14] = [
+    ("and", GateKind::And), ("nand", GateKind::Nand),
+    ("or", GateKind::Or), ("nor", GateKind::Nor),
+    ("xor", GateKind::Xor), ("xnor", GateKind::Xnor),
+    ("buf", GateKind::Buf), ("not", GateKind::Not),
+    ("bufif0", GateKind::Bufif0), ("bufif1", GateKind::Bufif1),
+    ("notif0", GateKind::Notif0), ("notif1", GateKind::Notif1),
+    ("pullup", GateKind::Pullup), ("pulldown", GateKind::Pulldown),
+];
+
+impl fmt::Display for GateKind {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match KEYWORDS.iter().find(|&&(_, kind)| kind == *self) {
+            Some(&(word, _)) => write!(f, "{}", word),
+            None => write!(f, "?"),
+        }
+    }
+}
+
+impl GateKind {
+
+    pub fn from_keyword(word: &str) -> Option<GateKind> {
+        KEYWORDS.iter().find(|&&(w, _)| w == word).map(|&(_, kind)| kind)
+    }
+
+    // buf and not drive any number of outputs from their last terminal,
+    // a pullup's only terminal is its output, and everything else has one
+    // output and then its inputs
+    pub fn outputs(self, terminals: usize) -> usize {
+        match self {
+            GateKind::Buf | GateKind::Not => terminals.saturating_sub(1),
+            GateKind::Pullup | GateKind::Pulldown => terminals,
+            _ => 1,
+        }
+    }
+
+    // how many terminals it can have, outputs included
+    pub fn terminals(self) -> (usize, Option<usize>) {
+        match self {
+            GateKind::Bufif0 | GateKind::Bufif1 |
+            GateKind::Notif0 | GateKind::Notif1 => (3, Some(3)),
+            GateKind::Pullup | GateKind::Pulldown => (1, Some(1)),
+            _ => (2, None),
+        }
+    }
+
+    // how hard it drives when not told otherwise
+    pub fn strength(self) -> DriveStrength {
+        match self {
+            GateKind::Pullup | GateKind::Pulldown => net::PULL,
+            _ => net::STRONG,
+        }
+    }
+
+    // the output for these inputs; z in is as good as x
+    pub fn evaluate(self, inputs: &[Logic]) -> Logic {
+        match self {
+            GateKind::And => and(inputs),
+            GateKind::Nand => not(and(inputs)),
+            GateKind::Or => or(inputs),
+            GateKind::Nor => not(or(inputs)),
+            GateKind::Xor => xor(inputs),
+            GateKind::Xnor => not(xor(inputs)),
+            GateKind::Buf => buf(inputs[0]),
+            GateKind::Not => not(inputs[0]),
+            GateKind::Bufif0 => tristate(buf(inputs[0]), inputs[1], Logic::Zero),
+            GateKind::Bufif1 => tristate(buf(inputs[0]), inputs[1], Logic::One),
+            GateKind::Notif0 => tristate(not(inputs[0]), inputs[1], Logic::Zero),
+            GateKind::Notif1 => tristate(not(inputs[0]), inputs[1], Logic::One),
+            GateKind::Pullup => Logic::One,
+            GateKind::Pulldown => Logic::Zero,
+        }
+    }
+}
+
+fn buf(a: Logic) -> Logic {
+    match a {
+        Logic::Z => Logic::X,
+        _ => a,
+    }
+}
+
+fn not(a: Logic) -> Logic {
+    match a {
+        Logic::Zero => Logic::One,
+        Logic::One => Logic::Zero,
+        _ => Logic::X,
+    }
+}
+
+// a known 0 anywhere wins
+fn and(inputs: &[Logic]) -> Logic {
+    if inputs.contains(&Logic::Zero) {
+        Logic::Zero
+    } else if inputs.iter().all(|&a| a == Logic::One) {
+        Logic::One
+    } else {
+        Logic::X
+    }
+}
+
+// a known 1 anywhere wins
+fn or(inputs: &[Logic]) -> Logic {
+    if inputs.contains(&Logic::One) {
+        Logic::One
+    } else if inputs.iter().all(|&a| a == Logic::Zero) {
+        Logic::Zero
+    } else {
+        Logic::X
+    }
+}
+
+fn xor(inputs: &[Logic]) -> Logic {
+    let mut out = Logic::Zero;
+    for &a in inputs {
+        out = match (out, a) {
+            (Logic::Zero, Logic::Zero) | (Logic::One, Logic::One) => Logic::Zero,
+            (Logic::Zero, Logic::One) | (Logic::One, Logic::Zero) => Logic::One,
+            _ => return Logic::X,
+        };
+    }
+    out
+}
+
+// drives `data` when the control is `on`, lets go otherwise. Not
+// knowing the control gives x, there are no strengths to be weak with
+fn tristate(data: Logic, control: Logic, on: Logic) -> Logic {
+    match control {
+        _ if control == on => data,
+        Logic::Zero | Logic::One => Logic::Z,
+        _ => Logic::X,
+    }
+}
+
+// #(rise, fall, turn-off): how long the output takes to get to `to`.
+// One delay does for all three, with two the turn-off is the smaller,
+// and getting to x takes the smallest of the lot
+pub fn delay(delays: &[Time], to: Logic) -> Time {
+    let (rise, fall, off) = match *delays {
+        [] => (0, 0, 0),
+        [d] => (d, d, d),
+        [rise, fall] => (rise, fall, rise.min(fall)),
+        [rise, fall, off, ..] => (rise, fall, off),
+    };
+    match to {
+        Logic::One => rise,
+        Logic::Zero => fall,
+        Logic::Z => off,
+        Logic::X => rise.min(fall).min(off),
+    }
+}
@@ -0,0 +1,583 @@
+//! Verilog lexer
+//!
+//! Chops a source file into tokens, remembering the line and column
+//! each one started at so the parser can report errors sensibly.
+
+use std::fmt;
+use std::collections::HashMap;
+
+use parser::ParseError;
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Token {
+    Ident(String),
+    SysIdent(String),                 // $display, $finish etc.
+    Number{size: Option<usize>, base: char, digits: String, signed: bool},
+    Real(f64),                        // 1.5, 2e-3
+    Str(String),
+    Punct(&'static str),
+    TableRow(String),                 // "01?:0:1", a row of a primitive's table
+    Eof,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Ident(ref s) => write!(f, "'{}'", s),
+            Token::SysIdent(ref s) => write!(f, "'{}'", s),
+            Token::Number{ref size, ref base, ref digits, ..} => {
+                if let Some(size) = *size {
+                    write!(f, "'{}'{}{}'", size, base, digits)
+                } else {
+                    write!(f, "'{}'", digits)
+                }
+            },
+            Token::Real(n) => write!(f, "'{}'", n),
+            Token::Str(ref s) => write!(f, "\"{}\"", s),
+            Token::Punct(p) => write!(f, "'{}'", p),
+            Token::TableRow(ref s) => write!(f, "table row '{}'", s),
+            Token::Eof => write!(f, "end of file"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Spanned {
+    pub tok: Token,
+    pub line: usize,
+    pub col: usize,
+}
+
+// longest first, so the greedy match picks "<=" over "<"
+const PUNCTS: [&str; 46] = [
+    "===", "!==", "<<<", ">>>",
+    "<=", ">=", "==", "!=", "&&", "||", "~&", "~|", "~^", "^~",
+    "<<", ">>", "->", "**", "+:", "-:",
+    "(", ")", "[", "]", "{", "}", ";", ",", ".", ":", "#", "@", "=",
+    "<", ">", "+", "-", "*", "/", "%", "&", "|", "^", "~", "!", "?",
+];
+
+// directives which don't change the meaning of the design for us
+const IGNORED_DIRECTIVES: [&str; 5] = [
+    "timescale", "default_nettype", "resetall", "celldefine", "endcelldefine",
+];
+
+// macros expanding into macros expanding into...
+const MAX_MACRO_DEPTH: usize = 32;
+
+// `ifdef nesting
+struct Conditional {
+    active: bool,   // are we emitting tokens in this branch
+    taken: bool,    // has any branch of this `ifdef been taken yet
+}
+
+pub struct Lexer<'a> {
+    file: String,
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    col: usize,
+    defines: &'a mut HashMap<String, String>,
+    conds: Vec<Conditional>,
+    depth: usize,
+}
+
+impl<'a> Lexer<'a> {
+
+    pub fn new(file: &str, src: &str, defines: &'a mut HashMap<String, String>)
+        -> Lexer<'a>
+    {
+        Lexer {
+            file: file.to_string(),
+            chars: src.chars().collect(),
+            pos: 0,
+            line: 1,
+            col: 1,
+            defines,
+            conds: vec![],
+            depth: 0,
+        }
+    }
+
+    fn active(&self) -> bool {
+        self.conds.iter().all(|c| c.active)
+    }
+
+    fn peek(&self, ahead: usize) -> Option<char> {
+        self.chars.get(self.pos + ahead).cloned()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek(0);
+        if let Some(c) = c {
+            self.pos += 1;
+            if c == '\n' {
+                self.line += 1;
+                self.col = 1;
+            } else {
+                self.col += 1;
+            }
+        }
+        c
+    }
+
+    fn error(&self, line: usize, col: usize, msg: &str) -> ParseError {
+        ParseError {
+            file: self.file.clone(),
+            line,
+            col,
+            msg: msg.to_string(),
+        }
+    }
+
+    pub fn tokenize(&mut self) -> Result<Vec<Spanned>, ParseError> {
+        let mut toks: Vec<Spanned> = vec![];
+        loop {
+            self.skip_whitespace_and_comments()?;
+            let line = self.line;
+            let col = self.col;
+            let c = match self.peek(0) {
+                Some(c) => c,
+                None => {
+                    if !self.conds.is_empty() {
+                        return Err(self.error(line, col, "missing `endif"));
+                    }
+                    toks.push( Spanned{ tok: Token::Eof, line, col } );
+                    break;
+                }
+            };
+
+            if c == '`' {
+                self.bump();
+                self.directive(line, col, &mut toks)?;
+                continue;
+            }
+
+            if !self.active() {
+                // skipped by `ifdef, but strings may hold a backtick
+                if c == '"' {
+                    self.string(line, col)?;
+                } else {
+                    self.bump();
+                }
+                continue;
+            }
+
+            let tok = if c.is_ascii_alphabetic() || c == '_' {
+                let word = self.take_word();
+                if word == "table" {
+                    toks.push( Spanned{ tok: Token::Ident(word), line, col } );
+                    self.table(&mut toks)?;
+                    continue;
+                }
+                Token::Ident(word)
+            } else if c == '$' {
+                self.bump();
+                Token::SysIdent( format!("${}", self.take_word()) )
+            } else if c.is_ascii_digit() || c == '\'' {
+                self.number(line, col)?
+            } else if c == '"' {
+                self.string(line, col)?
+            } else {
+                self.punct(line, col)?
+            };
+            toks.push( Spanned{ tok, line, col } );
+        }
+        Ok(toks)
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), ParseError> {
+        loop {
+            match (self.peek(0), self.peek(1)) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.bump();
+                },
+                (Some('/'), Some('/')) => {
+                    while let Some(c) = self.peek(0) {
+                        if c == '\n' {
+                            break;
+                        }
+                        self.bump();
+                    }
+                },
+                (Some('/'), Some('*')) => {
+                    let line = self.line;
+                    let col = self.col;
+                    self.bump();
+                    self.bump();
+                    loop {
+                        match (self.peek(0), self.peek(1)) {
+                            (Some('*'), Some('/')) => {
+                                self.bump();
+                                self.bump();
+                                break;
+                            },
+                            (Some(_), _) => {
+                                self.bump();
+                            },
+                            (None, _) => {
+                                return Err(self.error(line, col,
+                                    "unterminated block comment"));
+                            },
+                        }
+                    }
+                },
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    // the rows of a primitive's table up to endtable, which is left for
+    // the parser. Symbols like "0x" or "(?0)" aren't tokens anywhere
+    // else, so each row is one token with the whitespace taken out
+    fn table(&mut self, toks: &mut Vec<Spanned>) -> Result<(), ParseError> {
+        loop {
+            self.skip_whitespace_and_comments()?;
+            let line = self.line;
+            let col = self.col;
+            let rest: String = self.chars[self.pos..].iter().take(9).collect();
+            if rest.starts_with("endtable") &&
+                !rest[8..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+            {
+                return Ok(());
+            }
+            let mut row = String::new();
+            loop {
+                self.skip_whitespace_and_comments()?;
+                match self.bump() {
+                    Some(';') => break,
+                    Some(c) => row.push(c),
+                    None => return Err(self.error(line, col, "missing endtable")),
+                }
+            }
+            toks.push( Spanned{ tok: Token::TableRow(row), line, col } );
+        }
+    }
+
+    fn take_word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(c) = self.peek(0) {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '$' {
+                word.push(c);
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        word
+    }
+
+    fn take_digits(&mut self) -> String {
+        let mut digits = String::new();
+        while let Some(c) = self.peek(0) {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '?' {
+                if c != '_' {
+                    digits.push(c);
+                }
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        digits
+    }
+
+    // 12, 'b1010, 8'hFF, 4 'sb10_01 or 1.5e3. Plain decimal numbers are
+    // signed, based ones only with an s
+    fn number(&mut self, line: usize, col: usize) -> Result<Token, ParseError> {
+        let mut size: Option<usize> = None;
+
+        if self.peek(0) != Some('\'') {
+            let digits = self.take_digits();
+            // a size may be separated from the base by whitespace
+            let mut ahead = 0;
+            while let Some(c) = self.peek(ahead) {
+                if c == ' ' || c == '\t' {
+                    ahead += 1;
+                } else {
+                    break;
+                }
+            }
+            if self.peek(ahead) != Some('\'') {
+                let fraction = self.peek(0) == Some('.') && self.peek(1).is_some_and(|c| c.is_ascii_digit());
+                if fraction || digits.contains(['e', 'E']) {
+                    return self.real(digits, line, col);
+                }
+                if !digits.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(self.error(line, col,
+                        &format!("malformed number '{}'", digits)));
+                }
+                return Ok(Token::Number{size: None, base: 'd', digits, signed: true});
+            }
+            for _ in 0..ahead {
+                self.bump();
+            }
+            size = match digits.parse::<usize>() {
+                Ok(0) | Err(_) => {
+                    return Err(self.error(line, col,
+                        &format!("bad number size '{}'", digits)));
+                },
+                Ok(n) => Some(n),
+            };
+        }
+
+        // the tick
+        self.bump();
+        let signed = matches!(self.peek(0), Some('s') | Some('S'));
+        if signed {
+            self.bump();
+        }
+        let base = match self.bump() {
+            Some(c) if "bBoOdDhH".contains(c) => c.to_ascii_lowercase(),
+            _ => return Err(self.error(line, col, "expected number base after '")),
+        };
+        while let Some(' ') | Some('\t') = self.peek(0) {
+            self.bump();
+        }
+        let digits = self.take_digits();
+        if digits.is_empty() {
+            return Err(self.error(line, col, "missing digits in based number"));
+        }
+        Ok(Token::Number{size, base, digits: digits.to_lowercase(), signed})
+    }
+
+    // the rest of a real number after its integer digits, which may
+    // have run on into an exponent already
+    fn real(&mut self, mut text: String, line: usize, col: usize) -> Result<Token, ParseError> {
+        if self.peek(0) == Some('.') {
+            self.bump();
+            text.push('.');
+            text += &self.take_digits();
+        }
+        if text.ends_with(['e', 'E']) {
+            if let Some(sign) = self.peek(0).filter(|&c| c == '+' || c == '-') {
+                self.bump();
+                text.push(sign);
+                text += &self.take_digits();
+            }
+        }
+        match text.parse::<f64>() {
+            Ok(n) => Ok(Token::Real(n)),
+            Err(_) => Err(self.error(line, col, &format!("malformed real number '{}'", text))),
+        }
+    }
+
+    fn string(&mut self, line: usize, col: usize) -> Result<Token, ParseError> {
+        self.bump();
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => break,
+                Some('\\') => {
+                    match self.bump() {
+                        Some('n') => s.push('\n'),
+                        Some('t') => s.push('\t'),
+                        Some(c) => s.push(c),
+                        None => break,
+                    }
+                },
+                Some('\n') | None => {
+                    return Err(self.error(line, col, "unterminated string"));
+                },
+                Some(c) => s.push(c),
+            }
+        }
+        Ok(Token::Str(s))
+    }
+
+    // rest of the line, following backslash continuations
+    fn take_line(&mut self) -> String {
+        let mut text = String::new();
+        while let Some(c) = self.peek(0) {
+            if c == '\n' {
+                break;
+            } else if c == '\\' && self.peek(1) == Some('\n') {
+                self.bump();
+                text.push(' ');
+            } else if c == '/' && self.peek(1) == Some('/') {
+                while let Some(c) = self.peek(0) {
+                    if c == '\n' {
+                        break;
+                    }
+                    self.bump();
+                }
+                break;
+            } else {
+                text.push(c);
+            }
+            self.bump();
+        }
+        text.trim().to_string()
+    }
+
+    fn directive_name(&mut self, line: usize, col: usize, directive: &str)
+        -> Result<String, ParseError>
+    {
+        while let Some(' ') | Some('\t') = self.peek(0) {
+            self.bump();
+        }
+        let name = self.take_word();
+        if name.is_empty() {
+            return Err(self.error(line, col,
+                &format!("expected a macro name after `{}", directive)));
+        }
+        Ok(name)
+    }
+
+    fn directive(&mut self, line: usize, col: usize, toks: &mut Vec<Spanned>)
+        -> Result<(), ParseError>
+    {
+        let name = self.take_word();
+        match name.as_str() {
+            "ifdef" | "ifndef" => {
+                let macro_name = self.directive_name(line, col, &name)?;
+                let defined = self.defines.contains_key(&macro_name);
+                let active = defined == (name == "ifdef");
+                self.conds.push( Conditional{ active, taken: active } );
+            },
+            "elsif" => {
+                let macro_name = self.directive_name(line, col, &name)?;
+                let defined = self.defines.contains_key(&macro_name);
+                match self.conds.last_mut() {
+                    Some(cond) => {
+                        cond.active = !cond.taken && defined;
+                        cond.taken |= cond.active;
+                    },
+                    None => return Err(self.error(line, col, "`elsif without `ifdef")),
+                }
+            },
+            "else" => {
+                match self.conds.last_mut() {
+                    Some(cond) => {
+                        cond.active = !cond.taken;
+                        cond.taken = true;
+                    },
+                    None => return Err(self.error(line, col, "`else without `ifdef")),
+                }
+            },
+            "endif" => {
+                if self.conds.pop().is_none() {
+                    return Err(self.error(line, col, "`endif without `ifdef"));
+                }
+            },
+            _ if !self.active() => {
+                // everything else is skipped along with the code around it
+            },
+            "define" => {
+                let macro_name = self.directive_name(line, col, &name)?;
+                if self.peek(0) == Some('(') {
+                    return Err(self.error(line, col,
+                        "macros with arguments are not supported"));
+                }
+                let body = self.take_line();
+                self.defines.insert(macro_name, body);
+            },
+            "undef" => {
+                let macro_name = self.directive_name(line, col, &name)?;
+                self.defines.remove(&macro_name);
+            },
+            _ if IGNORED_DIRECTIVES.contains(&name.as_str()) => {
+                self.take_line();
+            },
+            _ => {
+                let body = match self.defines.get(&name) {
+                    Some(body) => body.clone(),
+                    None => {
+                        return Err(self.error(line, col,
+                            &format!("undefined macro or unsupported directive `{}", name)));
+                    },
+                };
+                if self.depth >= MAX_MACRO_DEPTH {
+                    return Err(self.error(line, col,
+                        &format!("macro `{} expands recursively", name)));
+                }
+                // expanded tokens all point back at the macro usage
+                let mut sub = Lexer::new(&self.file, &body, &mut *self.defines);
+                sub.depth = self.depth + 1;
+                for mut tok in sub.tokenize()? {
+                    if tok.tok != Token::Eof {
+                        tok.line = line;
+                        tok.col = col;
+                        toks.push(tok);
+                    }
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn punct(&mut self, line: usize, col: usize) -> Result<Token, ParseError> {
+        for p in PUNCTS.iter() {
+            let matched = p.chars().enumerate()
+                .all(|(i, c)| self.peek(i) == Some(c));
+            if matched {
+                for _ in 0..p.len() {
+                    self.bump();
+                }
+                return Ok(Token::Punct(p));
+            }
+        }
+        let c = self.peek(0).unwrap_or(' ');
+        Err(self.error(line, col, &format!("unexpected character '{}'", c)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(src: &str) -> Vec<Token> {
+        let mut defines = HashMap::new();
+        let toks = Lexer::new("t.v", src, &mut defines).tokenize().unwrap();
+        toks.into_iter().map(|s| s.tok).filter(|t| *t != Token::Eof).collect()
+    }
+
+    fn error(src: &str) -> String {
+        let mut defines = HashMap::new();
+        Lexer::new("t.v", src, &mut defines).tokenize().unwrap_err().to_string()
+    }
+
+    fn number(size: Option<usize>, base: char, digits: &str, signed: bool) -> Token {
+        Token::Number{size, base, digits: digits.to_string(), signed}
+    }
+
+    #[test]
+    fn sized_and_based_numbers() {
+        assert_eq!(tokens("8'hFF 4 'sb10_01 'o17 12 16'd x"), vec![
+            number(Some(8), 'h', "ff", false),
+            number(Some(4), 'b', "1001", true),
+            number(None, 'o', "17", false),
+            number(None, 'd', "12", true),
+            number(Some(16), 'd', "x", false),
+        ]);
+        assert_eq!(tokens("4'bx1z? 1.5e3 2e-3"), vec![
+            number(Some(4), 'b', "x1z?", false),
+            Token::Real(1500.0),
+            Token::Real(0.002),
+        ]);
+    }
+
+    #[test]
+    fn punctuation_is_greedy() {
+        assert_eq!(tokens("a<=b<<<2"), vec![
+            Token::Ident("a".to_string()), Token::Punct("<="), Token::Ident("b".to_string()),
+            Token::Punct("<<<"), number(None, 'd', "2", true),
+        ]);
+    }
+
+    #[test]
+    fn macros_and_conditionals() {
+        let src = "`define W 8\n`ifdef W\n`W\n`else\nno\n`endif\n";
+        assert_eq!(tokens(src), vec![number(None, 'd', "8", true)]);
+    }
+
+    #[test]
+    fn errors_point_at_line_and_column() {
+        assert_eq!(error("a = 4'q1;"), "t.v:1:5: expected number base after '");
+        assert_eq!(error("a\n  0'b1"), "t.v:2:3: bad number size '0'");
+        assert_eq!(error("x;\n  $display(\"oops\n"), "t.v:2:12: unterminated string");
+        assert_eq!(error("/* never\nends"), "t.v:1:1: unterminated block comment");
+        assert_eq!(error("`ifdef A\n"), "t.v:2:1: missing `endif");
+        assert_eq!(error("a\n\\"), "t.v:2:1: unexpected character '\\'");
+    }
+}
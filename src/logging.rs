@@ -0,0 +1,46 @@
+//! Verbosity-gated printing
+//!
+//! The simulator reports on everything it does, which is the point of
+//! it, but not every run wants the full scheduler trace.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const QUIET: usize = 0;   // errors only
+pub const NORMAL: usize = 1;  // warnings and milestones
+pub const TRACE: usize = 2;   // what the scheduler is doing
+pub const DEBUG: usize = 3;   // plus queue and table dumps
+
+static VERBOSITY: AtomicUsize = AtomicUsize::new(NORMAL);
+
+pub fn set_verbosity(level: usize) {
+    VERBOSITY.store(level, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> usize {
+    VERBOSITY.load(Ordering::Relaxed)
+}
+
+// warnings and milestones
+macro_rules! note {
+    ($($arg:tt)*) => {
+        if $crate::logging::verbosity() >= $crate::logging::NORMAL {
+            println!($($arg)*);
+        }
+    }
+}
+
+// the scheduler trace
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::logging::verbosity() >= $crate::logging::TRACE {
+            println!($($arg)*);
+        }
+    }
+}
+
+// gate for the table dumps
+macro_rules! debugging {
+    () => {
+        $crate::logging::verbosity() >= $crate::logging::DEBUG
+    }
+}
@@ -1,41 +1,104 @@
-
+#[macro_use]
+mod logging;
 mod procedure;
+mod value;
+mod eval;
+mod format;
+mod output;
 mod engine;
 mod test_procs;
 mod timeheap;
 mod vcd;
+mod lexer;
+mod parser;
+mod design;
+mod elaborate;
+mod gate;
+mod udp;
+mod net;
+mod memfile;
+mod options;
+
+use std::env;
+use std::process;
 
 use test_procs::*;
-use engine::Engine;
+use engine::{Engine, RunResult};
+use options::Options;
 
 fn main() {
-    println!("********************************************");
-    println!("***     Tiny-Verilog-Simulator           ***");
-    println!("***        (c) CrapCorp 2017             ***");
-    println!("*** Patent Pending, All rights reserved  ***");
-    println!("********************************************");
+    let mut opts = match Options::parse(env::args().skip(1)) {
+        Ok(opts) => opts,
+        Err(why) => {
+            println!("*ERROR* {}", why);
+            println!("{}", options::USAGE);
+            process::exit(2);
+        }
+    };
+    if opts.help {
+        println!("{}", options::USAGE);
+        return;
+    }
+    logging::set_verbosity(opts.verbosity);
+
+    note!("********************************************");
+    note!("***     Tiny-Verilog-Simulator           ***");
+    note!("***        (c) CrapCorp 2017             ***");
+    note!("*** Patent Pending, All rights reserved  ***");
+    note!("********************************************");
 
-    println!("*INFO* Initialising");
+    note!("*INFO* Initialising");
     let mut eng = Engine::new();
+    eng.set_vcd_file(opts.vcd_file.clone());
+    eng.set_dump_memories(opts.dump_memories);
+    eng.set_max_time(opts.max_time);
+    eng.set_max_deltas(opts.max_deltas);
 
     // build something to simulate
-    println!("*INFO* Building design");
-    eng.add_proc( build_clock(5, 100) );
-    eng.add_proc( build_bitstream("a", 0xF0F0F0F0, 32, 7, 0) );
-    eng.add_proc( build_bitstream("b", 0x34AE4210, 32, 11, 0) );
-    eng.add_proc( build_bitstream("c", 0x834ead51, 32, 13, 200) );
-    eng.add_proc( build_and("y", "a", "b") );
-    eng.add_proc( build_flop("y", "ff1_out"));
-    eng.add_proc( build_flop("ff1_out", "ff2_out"));
-    eng.add_proc( build_flop("ff2_out", "ff3_out"));
-    eng.add_proc( build_flop("ff_div_inv", "ff_div"));
-    eng.add_proc( build_inverter("ff_div_inv", "ff_div") );
+    note!("*INFO* Building design");
+    if opts.files.is_empty() {
+        eng.add_proc( build_clock(5, 100) );
+        eng.add_proc( build_bitstream("a", 0xF0F0F0F0, 32, 7, 0) );
+        eng.add_proc( build_bitstream("b", 0x34AE4210, 32, 11, 0) );
+        eng.add_proc( build_bitstream("c", 0x834ead51, 32, 13, 200) );
+        eng.add_assign( build_and("y", "a", "b") );
+        eng.add_proc( build_flop("y", "ff1_out"));
+        eng.add_proc( build_flop("ff1_out", "ff2_out"));
+        eng.add_proc( build_flop("ff2_out", "ff3_out"));
+        eng.add_proc( build_reset("ff_div", 0) );
+        eng.add_proc( build_flop("ff_div_inv", "ff_div"));
+        eng.add_assign( build_inverter("ff_div_inv", "ff_div") );
+    } else if let Err(why) = load_design(&mut eng, &mut opts) {
+        println!("*ERROR* {}", why);
+        process::exit(1);
+    }
 
+    if debugging!() {
+        eng.show_proc();
+    }
+    if let Err(why) = eng.init() {
+        println!("*ERROR* {}", why);
+        process::exit(1);
+    }
 
+    if eng.run() == RunResult::DeltaLimit {
+        process::exit(1);
+    }
+}
 
-    eng.show_proc();
-    eng.init();
+// parse the source files, flatten the hierarchy and hand the
+// design to the engine
+fn load_design(eng: &mut Engine, opts: &mut Options) -> Result<(), String> {
+    let mut descriptions = vec![];
+    for filename in &opts.files {
+        note!("*INFO* Reading {}", filename);
+        match parser::parse_file(filename, &mut opts.defines) {
+            Ok(mut d) => descriptions.append(&mut d),
+            Err(why) => return Err(format!("{}", why)),
+        }
+    }
 
-    eng.run();
+    let design = elaborate::elaborate(&descriptions, opts.top.as_deref())?;
+    eng.load(design);
+    Ok(())
 }
-
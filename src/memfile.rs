@@ -0,0 +1,114 @@
+//! Memory image files
+//!
+//! What `$readmemh` and `$readmemb` load into a memory: one word after
+//! another in hex or binary, separated by white space, with `//` and
+//! `/* */` comments, `_` to break up long words and `@addr` (always in
+//! hex) to carry on loading from another address.
+
+use value::Value;
+
+pub enum Item {
+    Address(usize),
+    Word(Value),
+}
+
+// the words and addresses in `text`, each word `width` bits of `base`
+// ('h' or 'b') digits
+pub fn parse(text: &str, base: char, width: usize) -> Result<Vec<Item>, String> {
+    let mut items = vec![];
+    for (line, token) in tokens(text)? {
+        let item = if let Some(addr) = token.strip_prefix('@') {
+            match usize::from_str_radix(&addr.replace('_', ""), 16) {
+                Ok(addr) => Item::Address(addr),
+                Err(_) => return Err(format!("line {}: bad address '{}'", line, token)),
+            }
+        } else {
+            let digits = token.replace('_', "").to_lowercase();
+            match Value::parse(Some(width), base, &digits) {
+                Some(word) if !digits.is_empty() => Item::Word(word),
+                _ => return Err(format!("line {}: '{}' isn't a {} word", line, token,
+                    if base == 'h' { "hex" } else { "binary" })),
+            }
+        };
+        items.push(item);
+    }
+    Ok(items)
+}
+
+// everything that isn't white space or a comment, with its line number
+fn tokens(text: &str) -> Result<Vec<(usize, String)>, String> {
+    let mut tokens = vec![];
+    let mut token = String::new();
+    let mut line = 1;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let comment = c == '/' && (chars.peek() == Some(&'/') || chars.peek() == Some(&'*'));
+        if (c.is_whitespace() || comment) && !token.is_empty() {
+            tokens.push( (line, token.clone()) );
+            token.clear();
+        }
+        if comment {
+            if chars.next() == Some('/') {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+            } else {
+                let start = line;
+                let mut last = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if last == '*' => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            last = c;
+                        },
+                        None => return Err(format!("line {}: comment never ends", start)),
+                    }
+                }
+            }
+        } else if c == '\n' {
+            line += 1;
+        } else if !c.is_whitespace() {
+            token.push(c);
+        }
+    }
+    if !token.is_empty() {
+        tokens.push( (line, token) );
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "@10" for an address, the bits for a word
+    fn load(text: &str, base: char, width: usize) -> Result<Vec<String>, String> {
+        let items = parse(text, base, width)?;
+        Ok(items.iter().map(|item| match *item {
+            Item::Address(addr) => format!("@{:x}", addr),
+            Item::Word(ref word) => word.to_bin_string(),
+        }).collect())
+    }
+
+    #[test]
+    fn words_addresses_and_comments() {
+        let text = "// header\n0a 1_F /* two\nlines */ x5\n@1_0 zz // done";
+        assert_eq!(load(text, 'h', 8).unwrap(),
+            vec!["00001010", "00011111", "xxxx0101", "@10", "zzzzzzzz"]);
+        assert_eq!(load("1010 0x1z\n@3 1", 'b', 4).unwrap(),
+            vec!["1010", "0x1z", "@3", "0001"]);
+        assert_eq!(load("  \n// nothing\n", 'h', 8).unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn errors_give_the_line() {
+        assert_eq!(load("00\n0g", 'h', 8), Err("line 2: '0g' isn't a hex word".to_string()));
+        assert_eq!(load("0\n\n12", 'b', 4), Err("line 3: '12' isn't a binary word".to_string()));
+        assert_eq!(load("@zz", 'h', 8), Err("line 1: bad address '@zz'".to_string()));
+        assert_eq!(load("00\n/* open\n\n", 'h', 8), Err("line 2: comment never ends".to_string()));
+        assert_eq!(load("_", 'h', 8), Err("line 1: '_' isn't a hex word".to_string()));
+    }
+}
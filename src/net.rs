@@ -0,0 +1,253 @@
+//! Nets and drive strengths
+//!
+//! A net can have any number of drivers: continuous assignments, gates
+//! and primitives. Each drives every bit with a strength for 0 and one
+//! for 1, and the net's value is worked out from all of them by the
+//! IEEE 1364 rules: the strongest driver wins, drivers of the same
+//! strength that disagree give x, unless it's a wand or wor net where
+//! they're and-ed or or-ed together. Nobody driving leaves z. A tri0 or
+//! tri1 net is one more driver itself, of pull strength, and a supply0
+//! or supply1 net one of supply strength.
+
+use std::fmt;
+
+use value::{Logic, Value};
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum NetKind {
+    Wire,
+    Tri,
+    Wand,
+    Wor,
+    Tri0,
+    Tri1,
+    Supply0,
+    Supply1,
+}
+
+const KEYWORDS: [(&str, NetKind); 8] = [
+    ("wire", NetKind::Wire), ("tri", NetKind::Tri),
+    ("wand", NetKind::Wand), ("wor", NetKind::Wor),
+    ("tri0", NetKind::Tri0), ("tri1", NetKind::Tri1),
+    ("supply0", NetKind::Supply0), ("supply1", NetKind::Supply1),
+];
+
+impl fmt::Display for NetKind {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match KEYWORDS.iter().find(|&&(_, kind)| kind == *self) {
+            Some(&(word, _)) => write!(f, "{}", word),
+            None => write!(f, "?"),
+        }
+    }
+}
+
+impl NetKind {
+
+    pub fn from_keyword(word: &str) -> Option<NetKind> {
+        KEYWORDS.iter().find(|&&(w, _)| w == word).map(|&(_, kind)| kind)
+    }
+
+    // what the net drives itself, whatever else is driving it
+    fn driver(self) -> Option<(Logic, Strength)> {
+        match self {
+            NetKind::Tri0 => Some((Logic::Zero, Strength::Pull)),
+            NetKind::Tri1 => Some((Logic::One, Strength::Pull)),
+            NetKind::Supply0 => Some((Logic::Zero, Strength::Supply)),
+            NetKind::Supply1 => Some((Logic::One, Strength::Supply)),
+            _ => None,
+        }
+    }
+}
+
+// how hard a driver pushes, weakest first
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+pub enum Strength {
+    HighZ,
+    Weak,
+    Pull,
+    Strong,
+    Supply,
+}
+
+const STRENGTHS: [(&str, Strength); 5] = [
+    ("highz", Strength::HighZ), ("weak", Strength::Weak), ("pull", Strength::Pull),
+    ("strong", Strength::Strong), ("supply", Strength::Supply),
+];
+
+impl fmt::Display for Strength {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match STRENGTHS.iter().find(|&&(_, s)| s == *self) {
+            Some(&(word, _)) => write!(f, "{}", word),
+            None => write!(f, "?"),
+        }
+    }
+}
+
+// "(strong0, weak1)": the strength of a driver's 0s and of its 1s
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct DriveStrength {
+    pub zero: Strength,
+    pub one: Strength,
+}
+
+pub const STRONG: DriveStrength = DriveStrength { zero: Strength::Strong, one: Strength::Strong };
+pub const PULL: DriveStrength = DriveStrength { zero: Strength::Pull, one: Strength::Pull };
+
+impl Default for DriveStrength {
+    fn default() -> DriveStrength {
+        STRONG
+    }
+}
+
+impl fmt::Display for DriveStrength {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({}0, {}1)", self.zero, self.one)
+    }
+}
+
+impl DriveStrength {
+
+    // "pull0" is (Pull, false) and "weak1" (Weak, true)
+    pub fn from_keyword(word: &str) -> Option<(Strength, bool)> {
+        let (name, one) = if let Some(name) = word.strip_suffix('0') {
+            (name, false)
+        } else if let Some(name) = word.strip_suffix('1') {
+            (name, true)
+        } else {
+            return None;
+        };
+        STRENGTHS.iter().find(|&&(w, _)| w == name).map(|&(_, s)| (s, one))
+    }
+
+    // how hard `bit` is driven; an x is as strong as the stronger of the two
+    fn of(self, bit: Logic) -> Strength {
+        match bit {
+            Logic::Zero => self.zero,
+            Logic::One => self.one,
+            Logic::X => self.zero.max(self.one),
+            Logic::Z => Strength::HighZ,
+        }
+    }
+}
+
+// what a net of this kind is with these drivers on it
+pub fn resolve<'a, I>(kind: NetKind, width: usize, drivers: I) -> Value
+    where I: Iterator<Item = (&'a Value, DriveStrength)> + Clone
+{
+    let mut value = Value::z(width);
+    resolve_bits(kind, &mut value, (0, width - 1), drivers);
+    value
+}
+
+// work out bits `lo` to `hi` of a net again, when a driver has changed
+// only those
+pub fn resolve_bits<'a, I>(kind: NetKind, value: &mut Value, (lo, hi): (usize, usize), drivers: I)
+    where I: Iterator<Item = (&'a Value, DriveStrength)> + Clone
+{
+    for i in lo..=hi {
+        let bits = drivers.clone().map(|(v, s)| {
+            let bit = v.bit(i);
+            (bit, s.of(bit))
+        });
+        value.set_bit(i, resolve_bit(kind, bits));
+    }
+}
+
+fn resolve_bit<I: Iterator<Item = (Logic, Strength)>>(kind: NetKind, bits: I) -> Logic {
+    let mut best = (Logic::Z, Strength::HighZ);
+    for (bit, strength) in bits.chain(kind.driver()) {
+        if strength == Strength::HighZ {
+            continue;
+        }
+        if strength > best.1 {
+            best = (bit, strength);
+        } else if strength == best.1 {
+            best.0 = match kind {
+                NetKind::Wand => wired_and(best.0, bit),
+                NetKind::Wor => wired_or(best.0, bit),
+                _ if best.0 == bit => bit,
+                _ => Logic::X,
+            };
+        }
+    }
+    best.0
+}
+
+fn wired_and(a: Logic, b: Logic) -> Logic {
+    match (a, b) {
+        (Logic::Zero, _) | (_, Logic::Zero) => Logic::Zero,
+        (Logic::One, Logic::One) => Logic::One,
+        _ => Logic::X,
+    }
+}
+
+fn wired_or(a: Logic, b: Logic) -> Logic {
+    match (a, b) {
+        (Logic::One, _) | (_, Logic::One) => Logic::One,
+        (Logic::Zero, Logic::Zero) => Logic::Zero,
+        _ => Logic::X,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use value::Logic::{One, X, Z, Zero};
+    use self::Strength::{HighZ, Pull, Strong, Supply, Weak};
+
+    fn bit(kind: NetKind, drivers: &[(Logic, Strength)]) -> Logic {
+        resolve_bit(kind, drivers.iter().cloned())
+    }
+
+    #[test]
+    fn strongest_driver_wins() {
+        assert_eq!(bit(NetKind::Wire, &[]), Z);
+        assert_eq!(bit(NetKind::Wire, &[(One, Weak), (Zero, Pull)]), Zero);
+        assert_eq!(bit(NetKind::Wire, &[(Zero, Strong), (One, Supply)]), One);
+        assert_eq!(bit(NetKind::Wire, &[(One, Strong), (Z, HighZ)]), One);
+        assert_eq!(bit(NetKind::Wire, &[(X, Pull), (One, Strong)]), One);
+    }
+
+    #[test]
+    fn same_strength_disagreeing_is_x() {
+        assert_eq!(bit(NetKind::Wire, &[(One, Strong), (One, Strong)]), One);
+        assert_eq!(bit(NetKind::Wire, &[(One, Strong), (Zero, Strong)]), X);
+        assert_eq!(bit(NetKind::Tri, &[(Zero, Weak), (One, Weak), (One, Pull)]), One);
+    }
+
+    #[test]
+    fn wired_nets_combine_the_same_strength() {
+        assert_eq!(bit(NetKind::Wand, &[(One, Strong), (Zero, Strong)]), Zero);
+        assert_eq!(bit(NetKind::Wand, &[(One, Strong), (One, Strong)]), One);
+        assert_eq!(bit(NetKind::Wor, &[(One, Strong), (Zero, Strong)]), One);
+        assert_eq!(bit(NetKind::Wor, &[(X, Strong), (Zero, Strong)]), X);
+        assert_eq!(bit(NetKind::Wor, &[(One, Pull), (Zero, Strong)]), Zero);
+    }
+
+    #[test]
+    fn tri0_and_tri1_pull() {
+        assert_eq!(bit(NetKind::Tri0, &[]), Zero);
+        assert_eq!(bit(NetKind::Tri1, &[(Z, HighZ)]), One);
+        assert_eq!(bit(NetKind::Tri0, &[(One, Weak)]), Zero);
+        assert_eq!(bit(NetKind::Tri0, &[(One, Pull)]), X);
+        assert_eq!(bit(NetKind::Tri1, &[(Zero, Strong)]), Zero);
+    }
+
+    #[test]
+    fn supply_nets_resolve_against_supply_drivers() {
+        assert_eq!(bit(NetKind::Supply0, &[]), Zero);
+        assert_eq!(bit(NetKind::Supply1, &[(Zero, Strong)]), One);
+        assert_eq!(bit(NetKind::Supply0, &[(One, Supply)]), X);
+        assert_eq!(bit(NetKind::Supply1, &[(One, Supply)]), One);
+    }
+
+    #[test]
+    fn resolves_each_bit() {
+        let a = Value::parse(Some(4), 'b', "10zz").unwrap();
+        let b = Value::parse(Some(4), 'b', "z01z").unwrap();
+        let weak = DriveStrength { zero: Weak, one: Weak };
+        let value = resolve(NetKind::Tri1, 4, vec![(&a, STRONG), (&b, weak)].into_iter());
+        assert_eq!(value, Value::parse(Some(4), 'b', "1011").unwrap());
+    }
+}
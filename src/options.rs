@@ -0,0 +1,139 @@
+//! Command-line options for the simulator driver
+
+use std::collections::HashMap;
+
+use procedure::Time;
+use engine::DEFAULT_MAX_DELTAS;
+use logging;
+
+pub const USAGE: &str = "Usage: tv2 [options] <files...>
+
+Simulate the Verilog design in <files...>. With no files, simulate the
+built-in demo design.
+
+Options:
+  -t, --top <module>       top-level module (default: any not instantiated)
+  -o, --vcd <file>         VCD waveform file (default: waves.vcd)
+  -n, --no-vcd             don't write a VCD file
+      --dump-memories      put every word of every memory in the VCD file
+  -m, --max-time <time>    stop simulating after this time
+  -d, --max-deltas <n>     give up on a time step after this many delta
+                           cycles (default: 10000)
+  -v, --verbose <level>    0 = errors only, 1 = warnings (default),
+                           2 = scheduler trace, 3 = queue and table dumps
+  -q, --quiet              same as --verbose 0
+  -D <name>[=<value>]      `define a macro
+  +define+<name>[=<value>][+<name>...]
+                           `define macros, Verilog-XL style
+  -h, --help               show this message";
+
+pub struct Options {
+    pub files: Vec<String>,
+    pub top: Option<String>,
+    pub vcd_file: Option<String>,
+    pub dump_memories: bool,
+    pub max_time: Option<Time>,
+    pub max_deltas: Option<usize>,
+    pub verbosity: usize,
+    pub defines: HashMap<String, String>,
+    pub help: bool,
+}
+
+impl Options {
+
+    pub fn new() -> Options {
+        Options {
+            files: vec![],
+            top: None,
+            vcd_file: Some("waves.vcd".to_string()),
+            dump_memories: false,
+            max_time: None,
+            max_deltas: Some(DEFAULT_MAX_DELTAS),
+            verbosity: logging::NORMAL,
+            defines: HashMap::new(),
+            help: false,
+        }
+    }
+
+    pub fn parse<I: Iterator<Item=String>>(mut args: I) -> Result<Options, String> {
+        let mut opts = Options::new();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => {
+                    opts.help = true;
+                },
+                "-t" | "--top" => {
+                    opts.top = Some( value_of(&arg, args.next())? );
+                },
+                "-o" | "--vcd" => {
+                    opts.vcd_file = Some( value_of(&arg, args.next())? );
+                },
+                "-n" | "--no-vcd" => {
+                    opts.vcd_file = None;
+                },
+                "--dump-memories" => {
+                    opts.dump_memories = true;
+                },
+                "-m" | "--max-time" => {
+                    let value = value_of(&arg, args.next())?;
+                    opts.max_time = Some( number_of(&arg, &value)? );
+                },
+                "-d" | "--max-deltas" => {
+                    let value = value_of(&arg, args.next())?;
+                    opts.max_deltas = Some( number_of(&arg, &value)? );
+                },
+                "-v" | "--verbose" => {
+                    let value = value_of(&arg, args.next())?;
+                    opts.verbosity = number_of(&arg, &value)?;
+                },
+                "-q" | "--quiet" => {
+                    opts.verbosity = logging::QUIET;
+                },
+                "-D" => {
+                    let value = value_of(&arg, args.next())?;
+                    opts.define(&value);
+                },
+                _ if arg.starts_with("+define+") => {
+                    for def in arg["+define+".len()..].split('+') {
+                        if !def.is_empty() {
+                            opts.define(def);
+                        }
+                    }
+                },
+                _ if arg.starts_with("-D") => {
+                    opts.define(&arg[2..]);
+                },
+                _ if arg.starts_with('-') || arg.starts_with('+') => {
+                    return Err(format!("unknown option '{}'", arg));
+                },
+                _ => {
+                    opts.files.push(arg);
+                },
+            }
+        }
+        Ok(opts)
+    }
+
+    // NAME or NAME=VALUE
+    fn define(&mut self, def: &str) {
+        let mut parts = def.splitn(2, '=');
+        let name = parts.next().unwrap_or("").to_string();
+        let value = parts.next().unwrap_or("").to_string();
+        self.defines.insert(name, value);
+    }
+}
+
+fn value_of(option: &str, value: Option<String>) -> Result<String, String> {
+    match value {
+        Some(value) => Ok(value),
+        None => Err(format!("option '{}' needs a value", option)),
+    }
+}
+
+fn number_of(option: &str, value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(n) => Ok(n),
+        Err(_) => Err(format!("option '{}' expects a number, not '{}'", option, value)),
+    }
+}
@@ -0,0 +1,50 @@
+//! Where the printing system tasks' text goes
+//!
+//! Normally straight to stdout, but the tests hand the engine a
+//! `Buffer` instead, to capture it.
+
+#[cfg(test)]
+use std::cell::RefCell;
+use std::io::{self, Write};
+#[cfg(test)]
+use std::rc::Rc;
+
+pub trait Sink {
+    fn write_str(&mut self, text: &str);
+}
+
+pub struct Stdout;
+
+impl Sink for Stdout {
+    fn write_str(&mut self, text: &str) {
+        print!("{}", text);
+        // $write without a newline should still show up
+        io::stdout().flush().ok();
+    }
+}
+
+// collects everything written to it, clones share the same text
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct Buffer {
+    text: Rc<RefCell<String>>,
+}
+
+#[cfg(test)]
+impl Buffer {
+
+    pub fn new() -> Buffer {
+        Buffer::default()
+    }
+
+    pub fn contents(&self) -> String {
+        self.text.borrow().clone()
+    }
+}
+
+#[cfg(test)]
+impl Sink for Buffer {
+    fn write_str(&mut self, text: &str) {
+        self.text.borrow_mut().push_str(text);
+    }
+}
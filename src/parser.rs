@@ -0,0 +1,1773 @@
+//! Recursive-descent parser for a subset of Verilog-2001
+//!
+//! Builds `Procedure`s directly out of the token stream, so what comes
+//! out the far end is exactly what `Engine::add_proc()` consumes.
+
+use std::fmt;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::rc::Rc;
+
+use lexer::{Lexer, Spanned, Token};
+use design::*;
+use gate::GateKind;
+use net::{self, DriveStrength, NetKind, Strength};
+use procedure::*;
+use udp;
+use value::{Type, MAX_WIDTH};
+
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub file: String,
+    pub line: usize,
+    pub col: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        // a file that can't be read has no line to point at
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file, self.msg)
+        } else {
+            write!(f, "{}:{}:{}: {}", self.file, self.line, self.col, self.msg)
+        }
+    }
+}
+
+// `define macros carry over from one file to the next, as in a
+// Verilog compilation unit
+pub fn parse_file(filename: &str, defines: &mut HashMap<String, String>)
+    -> Result<Vec<Description>, ParseError>
+{
+    let mut src = String::new();
+    let result = File::open(filename)
+        .and_then(|mut file| file.read_to_string(&mut src));
+    if let Err(why) = result {
+        return Err(ParseError {
+            file: filename.to_string(),
+            line: 0,
+            col: 0,
+            msg: why.to_string(),
+        });
+    }
+    parse_str(filename, &src, defines)
+}
+
+pub fn parse_str(filename: &str, src: &str, defines: &mut HashMap<String, String>)
+    -> Result<Vec<Description>, ParseError>
+{
+    let toks = Lexer::new(filename, src, defines).tokenize()?;
+    Parser::new(filename, toks).source_text()
+}
+
+// an expression on its own, like the index of a generate scope kept in
+// a name, "g[i - 1].o", until elaboration knows what i is
+pub fn parse_expression(src: &str) -> Result<Expression, ParseError> {
+    let toks = Lexer::new("", src, &mut HashMap::new()).tokenize()?;
+    let mut parser = Parser::new("", toks);
+    let expr = parser.expression()?;
+    if *parser.peek() != Token::Eof {
+        return Err(parser.unexpected("the end of the expression"));
+    }
+    Ok(expr)
+}
+
+struct Parser {
+    toks: Vec<Spanned>,
+    pos: usize,
+    file: String,
+    blocks: Vec<String>, // the named blocks the statement is inside
+    block_decls: Vec<Declaration>, // and what's been declared in them
+}
+
+impl Parser {
+
+    fn new(filename: &str, toks: Vec<Spanned>) -> Parser {
+        Parser {
+            toks,
+            pos: 0,
+            file: filename.to_string(),
+            blocks: vec![],
+            block_decls: vec![],
+        }
+    }
+
+    //
+    // Token stream helpers
+    //
+    fn peek(&self) -> &Token {
+        &self.toks[self.pos].tok
+    }
+
+    // the token after this one
+    fn peek_next(&self) -> &Token {
+        let next = (self.pos + 1).min(self.toks.len() - 1);
+        &self.toks[next].tok
+    }
+
+    fn advance(&mut self) -> Token {
+        let tok = self.toks[self.pos].tok.clone();
+        if self.pos + 1 < self.toks.len() {
+            self.pos += 1;
+        }
+        tok
+    }
+
+    fn error(&self, msg: &str) -> ParseError {
+        let here = &self.toks[self.pos];
+        ParseError {
+            file: self.file.clone(),
+            line: here.line,
+            col: here.col,
+            msg: msg.to_string(),
+        }
+    }
+
+    fn unexpected(&self, wanted: &str) -> ParseError {
+        self.error(&format!("expected {}, found {}", wanted, self.peek()))
+    }
+
+    fn is_punct(&self, p: &str) -> bool {
+        match *self.peek() {
+            Token::Punct(q) => q == p,
+            _ => false,
+        }
+    }
+
+    fn is_keyword(&self, kw: &str) -> bool {
+        match *self.peek() {
+            Token::Ident(ref s) => s == kw,
+            _ => false,
+        }
+    }
+
+    fn eat_punct(&mut self, p: &str) -> bool {
+        if self.is_punct(p) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_keyword(&mut self, kw: &str) -> bool {
+        if self.is_keyword(kw) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_punct(&mut self, p: &str) -> Result<(), ParseError> {
+        if self.eat_punct(p) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("'{}'", p)))
+        }
+    }
+
+    fn expect_keyword(&mut self, kw: &str) -> Result<(), ParseError> {
+        if self.eat_keyword(kw) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("'{}'", kw)))
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String, ParseError> {
+        let name = match *self.peek() {
+            Token::Ident(ref s) if !is_reserved(s) => s.clone(),
+            _ => return Err(self.unexpected("an identifier")),
+        };
+        self.advance();
+        Ok(name)
+    }
+
+    fn number(&mut self) -> Result<Value, ParseError> {
+        let value = match *self.peek() {
+            Token::Number{ref size, base, ref digits, signed} => {
+                let value = self.number_value(*size, base, digits)?;
+                if signed { value.with_type(Type::Signed) } else { value }
+            },
+            Token::Real(n) => Value::real(n),
+            _ => return Err(self.unexpected("a number")),
+        };
+        self.advance();
+        Ok(value)
+    }
+
+    fn number_value(&self, size: Option<usize>, base: char, digits: &str)
+        -> Result<Value, ParseError>
+    {
+        if size.unwrap_or(0) > MAX_WIDTH {
+            return Err(self.error(&format!("numbers wider than {} bits are not supported",
+                MAX_WIDTH)));
+        }
+        match Value::parse(size, base, digits) {
+            Some(value) => Ok(value),
+            None => Err(self.error(&format!("malformed number '{}'", digits))),
+        }
+    }
+
+    // #10, #DELAY or #(DELAY + 1)
+    fn delay(&mut self) -> Result<Expression, ParseError> {
+        if self.is_punct("(") {
+            self.condition()
+        } else if let Token::Number{..} | Token::Real(_) = *self.peek() {
+            Ok( Expression::Const(Operand::Literal(self.number()?)) )
+        } else {
+            Ok( Expression::identifier(&self.identifier()?) )
+        }
+    }
+
+    //
+    // Design units
+    //
+    fn source_text(&mut self) -> Result<Vec<Description>, ParseError> {
+        let mut descriptions: Vec<Description> = vec![];
+        while *self.peek() != Token::Eof {
+            if self.is_keyword("module") {
+                descriptions.push( Description::Module(self.module()?) );
+            } else if self.is_keyword("primitive") {
+                descriptions.push( Description::Primitive(self.primitive()?) );
+            } else {
+                return Err(self.unexpected("'module' or 'primitive'"));
+            }
+        }
+        Ok(descriptions)
+    }
+
+    fn module(&mut self) -> Result<Module, ParseError> {
+        self.expect_keyword("module")?;
+        let mut m = Module {
+            name: self.identifier()?,
+            ports: vec![],
+            items: vec![],
+        };
+
+        if self.eat_punct("#") {
+            // module m #(parameter WIDTH = 4, DEPTH = 16) (...);
+            self.expect_punct("(")?;
+            loop {
+                self.eat_keyword("parameter");
+                let param = self.parameter_assignment(false)?;
+                m.items.push( ModuleItem::Param(param) );
+                if !self.eat_punct(",") {
+                    break;
+                }
+            }
+            self.expect_punct(")")?;
+        }
+        if self.eat_punct("(") && !self.eat_punct(")") {
+            self.port_list(&mut m)?;
+            self.expect_punct(")")?;
+        }
+        self.expect_punct(";")?;
+
+        while !self.eat_keyword("endmodule") {
+            self.module_item(&mut m)?;
+        }
+        Ok(m)
+    }
+
+    // "primitive dff (q, clk, d); output q; reg q; input clk, d;
+    // initial q = 0; table ... endtable endprimitive", where a reg output
+    // makes it sequential. The ports may be declared in the list instead
+    fn primitive(&mut self) -> Result<Primitive, ParseError> {
+        self.expect_keyword("primitive")?;
+        // a module to collect the ports and declarations in
+        let mut m = Module {
+            name: self.identifier()?,
+            ports: vec![],
+            items: vec![],
+        };
+        self.expect_punct("(")?;
+        self.port_list(&mut m)?;
+        self.expect_punct(")")?;
+        self.expect_punct(";")?;
+
+        let mut initial: Option<(String, Value)> = None;
+        while !self.is_keyword("table") {
+            if let Some(dir) = self.direction() {
+                self.advance();
+                if dir == DeclKind::Output && self.eat_keyword("reg") {
+                    self.declaration(&mut m, &[dir, DeclKind::Reg])?;
+                } else {
+                    self.declaration(&mut m, &[dir])?;
+                }
+            } else if self.eat_keyword("reg") {
+                self.declaration(&mut m, &[DeclKind::Reg])?;
+            } else if self.eat_keyword("initial") {
+                let name = self.identifier()?;
+                self.expect_punct("=")?;
+                initial = Some( (name, self.number()?) );
+                self.expect_punct(";")?;
+            } else {
+                return Err(self.unexpected("a port declaration, 'initial' or 'table'"));
+            }
+        }
+
+        if let Some(decl) = m.decls().iter().find(|d| d.range.is_some()) {
+            return Err(self.error(&format!("{}: primitive ports are one bit, {} isn't",
+                m.name, decl.name)));
+        }
+        for (i, port) in m.ports.iter().enumerate() {
+            match m.direction(port) {
+                Some(DeclKind::Output) if i == 0 => {},
+                Some(DeclKind::Input) if i > 0 => {},
+                _ if i == 0 => return Err(self.error(&format!(
+                    "{}: a primitive's first port is its output", m.name))),
+                _ => return Err(self.error(&format!(
+                    "{}: {} should be an input", m.name, port))),
+            }
+        }
+        if m.ports.len() < 2 {
+            return Err(self.error(&format!("{}: a primitive needs an input", m.name)));
+        }
+        let output = m.ports[0].clone();
+        let sequential = m.decls().iter().any(|d| d.kind == DeclKind::Reg && d.name == output);
+        let initial = match initial {
+            None => None,
+            Some((ref name, _)) if *name != output || !sequential => {
+                return Err(self.error(&format!(
+                    "{}: only the output of a sequential primitive has an initial value", m.name)));
+            },
+            Some((_, value)) => Some(value.bit(0)),
+        };
+
+        self.expect_keyword("table")?;
+        let mut rows: Vec<udp::Row> = vec![];
+        while let Token::TableRow(ref text) = *self.peek() {
+            let row = udp::Row::parse(text, m.ports.len() - 1, sequential)
+                .map_err(|why| self.error(&why))?;
+            rows.push(row);
+            self.advance();
+        }
+        self.expect_keyword("endtable")?;
+        self.expect_keyword("endprimitive")?;
+        Ok(Primitive {
+            name: m.name,
+            ports: m.ports,
+            initial,
+            table: udp::Table { sequential, rows },
+        })
+    }
+
+    // either a list of names, or ANSI-style "input wire signed [3:0] a, b"
+    fn port_list(&mut self, m: &mut Module) -> Result<(), ParseError> {
+        let mut kind: Option<DeclKind> = None;
+        let mut range: Option<(Expression, Expression)> = None;
+        let mut signed = false;
+        let mut var: Option<DeclKind> = None;
+        let mut net: Option<NetKind> = None;
+        loop {
+            if let Some(dir) = self.direction() {
+                self.advance();
+                kind = Some(dir);
+                var = if dir == DeclKind::Output { self.eat_variable_kind() } else { None };
+                net = if var.is_some() { None } else { self.net_kind() };
+                if net.is_some() {
+                    self.advance();
+                }
+                signed = self.eat_keyword("signed");
+                range = self.range()?;
+            }
+            let name = self.identifier()?;
+            if let Some(kind) = kind {
+                m.items.push( ModuleItem::Decl(Declaration {
+                    kind,
+                    name: name.clone(),
+                    range: range.clone(),
+                    signed,
+                    array: None,
+                }));
+            }
+            // "output wand y": the port's net type
+            if let Some(net) = net {
+                m.items.push( ModuleItem::Decl(Declaration {
+                    kind: DeclKind::Net(net),
+                    name: name.clone(),
+                    range: range.clone(),
+                    signed,
+                    array: None,
+                }));
+            }
+            // output reg or output integer: the port is a variable too
+            if let Some(var) = var {
+                m.items.push( ModuleItem::Decl(Declaration {
+                    kind: var,
+                    name: name.clone(),
+                    range: range.clone(),
+                    signed,
+                    array: None,
+                }));
+            }
+            m.ports.push(name);
+            if !self.eat_punct(",") {
+                return Ok(());
+            }
+        }
+    }
+
+    // reg, integer, time, real or realtime
+    fn eat_variable_kind(&mut self) -> Option<DeclKind> {
+        let kind = match *self.peek() {
+            Token::Ident(ref s) => match s.as_str() {
+                "reg" => DeclKind::Reg,
+                "integer" => DeclKind::Integer,
+                "time" => DeclKind::Time,
+                "real" | "realtime" => DeclKind::Real,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        self.advance();
+        Some(kind)
+    }
+
+    fn direction(&self) -> Option<DeclKind> {
+        match *self.peek() {
+            Token::Ident(ref s) if s == "input" => Some(DeclKind::Input),
+            Token::Ident(ref s) if s == "output" => Some(DeclKind::Output),
+            Token::Ident(ref s) if s == "inout" => Some(DeclKind::Inout),
+            _ => None,
+        }
+    }
+
+    // [msb:lsb]
+    fn range(&mut self) -> Result<Option<(Expression, Expression)>, ParseError> {
+        if !self.eat_punct("[") {
+            return Ok(None);
+        }
+        let msb = self.expression()?;
+        self.expect_punct(":")?;
+        let lsb = self.expression()?;
+        self.expect_punct("]")?;
+        Ok(Some((msb, lsb)))
+    }
+
+    fn module_item(&mut self, m: &mut Module) -> Result<(), ParseError> {
+        if let Some(dir) = self.direction() {
+            self.advance();
+            if dir == DeclKind::Output {
+                // "output reg q;" declares the port and the variable at once
+                if let Some(var) = self.eat_variable_kind() {
+                    return self.declaration(m, &[dir, var]);
+                }
+            }
+            if let Some(net) = self.net_kind() {
+                self.advance();
+                return self.declaration(m, &[dir, DeclKind::Net(net)]);
+            }
+            return self.declaration(m, &[dir]);
+        }
+
+        if let Some(var) = self.eat_variable_kind() {
+            self.declaration(m, &[var])
+
+        } else if let Some(net) = self.net_kind() {
+            self.advance();
+            self.net_declaration(m, net)
+
+        } else if self.eat_keyword("event") {
+            self.declaration(m, &[DeclKind::Event])
+
+        } else if self.eat_keyword("assign") {
+            let strength = self.drive_strength(net::STRONG)?;
+            let dly = if self.eat_punct("#") {
+                Some(self.delay()?)
+            } else {
+                None
+            };
+            loop {
+                let id = self.lvalue()?;
+                self.expect_punct("=")?;
+                let expr = self.expression()?;
+                let mut ca = ContinuousAssign::new(id, expr, dly.clone());
+                ca.strength = strength;
+                m.items.push( ModuleItem::Assign(ca) );
+                if !self.eat_punct(",") {
+                    break;
+                }
+            }
+            self.expect_punct(";")
+
+        } else if self.is_keyword("parameter") || self.is_keyword("localparam") {
+            let local = self.is_keyword("localparam");
+            self.advance();
+            loop {
+                let param = self.parameter_assignment(local)?;
+                m.items.push( ModuleItem::Param(param) );
+                if !self.eat_punct(",") {
+                    break;
+                }
+            }
+            self.expect_punct(";")
+
+        } else if self.eat_keyword("defparam") {
+            loop {
+                let mut path = self.identifier()?;
+                while self.eat_punct(".") {
+                    path = format!("{}.{}", path, self.identifier()?);
+                }
+                self.expect_punct("=")?;
+                let value = self.expression()?;
+                m.items.push( ModuleItem::Defparam(Defparam { path, value }) );
+                if !self.eat_punct(",") {
+                    break;
+                }
+            }
+            self.expect_punct(";")
+
+        } else if self.eat_keyword("initial") {
+            let stmts = self.statement()?;
+            for decl in self.block_decls.drain(..) {
+                m.items.push( ModuleItem::Decl(decl) );
+            }
+            m.items.push( ModuleItem::Proc( build_proc(ProcedureType::Initial, &m.name, stmts) ));
+            Ok(())
+
+        } else if self.eat_keyword("always") {
+            let stmts = self.statement()?;
+            for decl in self.block_decls.drain(..) {
+                m.items.push( ModuleItem::Decl(decl) );
+            }
+            m.items.push( ModuleItem::Proc( build_proc(ProcedureType::Always, &m.name, stmts) ));
+            Ok(())
+
+        } else if self.eat_keyword("task") {
+            let task = self.subroutine(SubroutineKind::Task)?;
+            m.items.push( ModuleItem::Subroutine(task) );
+            Ok(())
+
+        } else if self.eat_keyword("function") {
+            let function = self.subroutine(SubroutineKind::Function)?;
+            m.items.push( ModuleItem::Subroutine(function) );
+            Ok(())
+
+        } else if self.eat_keyword("generate") {
+            // generate regions are optional, and make no difference
+            while !self.eat_keyword("endgenerate") {
+                if *self.peek() == Token::Eof {
+                    return Err(self.unexpected("'endgenerate'"));
+                }
+                self.module_item(m)?;
+            }
+            Ok(())
+
+        } else if self.eat_keyword("genvar") {
+            loop {
+                let var = self.identifier()?;
+                m.items.push( ModuleItem::Genvar(var) );
+                if !self.eat_punct(",") {
+                    break;
+                }
+            }
+            self.expect_punct(";")
+
+        } else if self.eat_keyword("for") {
+            let gen = self.generate_for(m)?;
+            m.items.push( ModuleItem::Generate(gen) );
+            Ok(())
+
+        } else if self.eat_keyword("if") {
+            let cond = self.condition()?;
+            let then_block = self.generate_block(m)?;
+            let else_block = if self.eat_keyword("else") {
+                Some( self.generate_block(m)? )
+            } else {
+                None
+            };
+            m.items.push( ModuleItem::Generate(Generate::If{cond, then_block, else_block}) );
+            Ok(())
+
+        } else if self.eat_keyword("case") {
+            let gen = self.generate_case(m)?;
+            m.items.push( ModuleItem::Generate(gen) );
+            Ok(())
+
+        } else if let Some(kind) = self.gate_kind() {
+            self.advance();
+            self.gates(m, kind)
+
+        } else if let Token::Ident(_) = *self.peek() {
+            self.instantiation(m)
+
+        } else {
+            Err(self.unexpected("a module item"))
+        }
+    }
+
+    // for (i = 0; i < N; i = i + 1) begin : name ... end
+    fn generate_for(&mut self, m: &Module) -> Result<Generate, ParseError> {
+        self.expect_punct("(")?;
+        let var = self.identifier()?;
+        self.expect_punct("=")?;
+        let init = self.expression()?;
+        self.expect_punct(";")?;
+        let cond = self.expression()?;
+        self.expect_punct(";")?;
+        let step_var = self.identifier()?;
+        if step_var != var {
+            return Err(self.error(&format!("generate loop over {} steps {}", var, step_var)));
+        }
+        self.expect_punct("=")?;
+        let step = self.expression()?;
+        self.expect_punct(")")?;
+        if !self.is_keyword("begin") {
+            return Err(self.unexpected("a named begin-end block"));
+        }
+        let block = self.generate_block(m)?;
+        if block.name.is_none() {
+            return Err(self.error("a generate loop's block needs a name, begin : name"));
+        }
+        Ok(Generate::For{var, init, cond, step, block})
+    }
+
+    fn generate_case(&mut self, m: &Module) -> Result<Generate, ParseError> {
+        let expr = self.condition()?;
+        let mut arms: Vec<(Vec<Expression>, GenBlock)> = vec![];
+        let mut default: Option<GenBlock> = None;
+        while !self.eat_keyword("endcase") {
+            if self.eat_keyword("default") {
+                if default.is_some() {
+                    return Err(self.error("more than one default in generate case"));
+                }
+                self.eat_punct(":");
+                default = Some( self.generate_block(m)? );
+                continue;
+            }
+            let mut labels: Vec<Expression> = vec![];
+            loop {
+                labels.push( self.expression()? );
+                if !self.eat_punct(",") {
+                    break;
+                }
+            }
+            self.expect_punct(":")?;
+            arms.push( (labels, self.generate_block(m)?) );
+        }
+        Ok(Generate::Case{expr, arms, default})
+    }
+
+    // a begin-end block of module items, or just the one item
+    fn generate_block(&mut self, m: &Module) -> Result<GenBlock, ParseError> {
+        let mut block = Module {
+            name: m.name.clone(),
+            ports: vec![],
+            items: vec![],
+        };
+        let mut name: Option<String> = None;
+        if self.eat_keyword("begin") {
+            if self.eat_punct(":") {
+                name = Some( self.identifier()? );
+            }
+            while !self.eat_keyword("end") {
+                if *self.peek() == Token::Eof {
+                    return Err(self.unexpected("'end'"));
+                }
+                self.module_item(&mut block)?;
+            }
+        } else {
+            self.module_item(&mut block)?;
+        }
+        Ok(GenBlock { name, items: block.items })
+    }
+
+    // everything after "task" or "function", up to the end
+    fn subroutine(&mut self, kind: SubroutineKind) -> Result<Subroutine, ParseError> {
+        let automatic = self.eat_keyword("automatic");
+        // "function integer f" or "function signed [7:0] f"
+        let mut result = DeclKind::Reg;
+        let mut signed = false;
+        let mut range: Option<(Expression, Expression)> = None;
+        if kind == SubroutineKind::Function {
+            result = self.eat_variable_kind().unwrap_or(DeclKind::Reg);
+            if result.width().is_none() {
+                signed = self.eat_keyword("signed");
+                range = self.range()?;
+            }
+        }
+        let mut sub = Subroutine {
+            kind,
+            name: self.identifier()?,
+            automatic,
+            ports: vec![],
+            decls: vec![],
+            stmts: Rc::new(vec![]),
+        };
+        if kind == SubroutineKind::Function {
+            // the value is returned in a variable named after the function
+            sub.decls.push( Declaration {
+                kind: result, name: sub.name.clone(), range, signed, array: None,
+            });
+        }
+
+        // "task t (input [3:0] a, output b);"
+        if self.eat_punct("(") && !self.eat_punct(")") {
+            loop {
+                let dir = match self.direction() {
+                    Some(dir) => dir,
+                    None => return Err(self.unexpected("input, output or inout")),
+                };
+                self.advance();
+                self.subroutine_declaration(&mut sub, dir, true)?;
+                if !self.eat_punct(",") {
+                    break;
+                }
+            }
+            self.expect_punct(")")?;
+        }
+        self.expect_punct(";")?;
+
+        let end = if kind == SubroutineKind::Task { "endtask" } else { "endfunction" };
+        let mut body: Vec<Statement> = vec![];
+        while !self.eat_keyword(end) {
+            if *self.peek() == Token::Eof {
+                return Err(self.unexpected(&format!("'{}'", end)));
+            }
+            if let Some(dir) = self.direction() {
+                self.advance();
+                self.subroutine_declaration(&mut sub, dir, false)?;
+                self.expect_punct(";")?;
+            } else if let Some(var) = self.eat_variable_kind() {
+                self.subroutine_declaration(&mut sub, var, false)?;
+                self.expect_punct(";")?;
+            } else {
+                body.append( &mut self.statement()? );
+            }
+        }
+        sub.decls.append(&mut self.block_decls);
+        sub.stmts = Rc::new( lower_body(body) );
+        Ok(sub)
+    }
+
+    // "signed [7:0] a, b" of a task or function; in a list of arguments a
+    // comma can also start the next direction
+    fn subroutine_declaration(&mut self, sub: &mut Subroutine, kind: DeclKind, in_list: bool)
+        -> Result<(), ParseError>
+    {
+        // "input integer n" is a port and an integer
+        let mut var = None;
+        if kind.is_direction() {
+            var = self.eat_variable_kind().filter(|&v| v != DeclKind::Reg);
+        }
+        let fixed = var.unwrap_or(kind).width().is_some();
+        let signed = !fixed && self.eat_keyword("signed");
+        let range = if fixed { None } else { self.range()? };
+        loop {
+            let name = self.identifier()?;
+            if kind.is_direction() {
+                sub.ports.push(name.clone());
+            }
+            sub.decls.push( Declaration { kind, name: name.clone(), range: range.clone(), signed, array: None } );
+            if let Some(var) = var {
+                sub.decls.push( Declaration { kind: var, name, range: None, signed, array: None } );
+            }
+            if !self.is_punct(",") {
+                return Ok(());
+            }
+            if in_list {
+                // ", output y" is the next lot
+                if let Token::Ident(ref s) = *self.peek_next() {
+                    if s == "input" || s == "output" || s == "inout" {
+                        return Ok(());
+                    }
+                }
+            }
+            self.advance();
+        }
+    }
+
+    // "[7:0] WIDTH = 8", after the parameter keyword
+    fn parameter_assignment(&mut self, local: bool) -> Result<Parameter, ParseError> {
+        let range = self.range()?;
+        let name = self.identifier()?;
+        self.expect_punct("=")?;
+        let value = self.expression()?;
+        Ok(Parameter { name, range, value, local })
+    }
+
+    fn gate_kind(&self) -> Option<GateKind> {
+        match *self.peek() {
+            Token::Ident(ref s) => GateKind::from_keyword(s),
+            _ => None,
+        }
+    }
+
+    // "and (weak0, weak1) #(1, 2) g1 (y, a, b), (z, c, d);", the instance
+    // names are optional
+    fn gates(&mut self, m: &mut Module, kind: GateKind) -> Result<(), ParseError> {
+        let strength = self.drive_strength(kind.strength())?;
+        let mut delays: Vec<Expression> = vec![];
+        if self.eat_punct("#") {
+            if self.eat_punct("(") {
+                loop {
+                    delays.push( self.expression()? );
+                    if !self.eat_punct(",") {
+                        break;
+                    }
+                }
+                self.expect_punct(")")?;
+                if delays.len() > 3 {
+                    return Err(self.error("a gate has at most 3 delays: rise, fall and turn-off"));
+                }
+            } else {
+                delays.push( self.delay()? );
+            }
+        }
+        loop {
+            let name = match *self.peek() {
+                Token::Ident(_) => Some(self.identifier()?),
+                _ => None,
+            };
+            let terminals = self.call_arguments()?;
+            match kind.terminals() {
+                (min, Some(max)) if terminals.len() < min || terminals.len() > max => {
+                    let plural = if max == 1 { "" } else { "s" };
+                    return Err(self.error(&format!("{} takes {} terminal{}", kind, max, plural)));
+                },
+                (min, _) if terminals.len() < min => {
+                    return Err(self.error(&format!("{} takes at least {} terminals", kind, min)));
+                },
+                _ => {},
+            }
+            let gate = Gate { kind, strength, name, delays: delays.clone(), terminals };
+            if let Some(output) = gate.outputs().iter().find(|t| t.lvalue().is_none()) {
+                return Err(self.error(&format!("{} output {} can't be driven", kind, output)));
+            }
+            m.items.push( ModuleItem::Gate(gate) );
+            if !self.eat_punct(",") {
+                break;
+            }
+        }
+        self.expect_punct(";")
+    }
+
+    // "flop u1 (.d(a), .q(b)), u2 (b, c);" or "counter #(.WIDTH(8)) u3 (...);"
+    fn instantiation(&mut self, m: &mut Module) -> Result<(), ParseError> {
+        let module = self.identifier()?;
+        let parameters = if !self.eat_punct("#") {
+            Connections::Positional(vec![])
+        } else if self.eat_punct("(") {
+            let parameters = self.connections()?;
+            self.expect_punct(")")?;
+            parameters
+        } else {
+            // "dff #3 u1 (...)"
+            Connections::Positional(vec![ Some(self.delay()?) ])
+        };
+        loop {
+            let name = self.identifier()?;
+            self.expect_punct("(")?;
+            let connections = self.connections()?;
+            self.expect_punct(")")?;
+            m.items.push( ModuleItem::Instance(Instance {
+                module: module.clone(),
+                name,
+                parameters: parameters.clone(),
+                connections,
+            }));
+            if !self.eat_punct(",") {
+                break;
+            }
+        }
+        self.expect_punct(";")
+    }
+
+    // the inside of an instance's port list, up to the closing bracket
+    fn connections(&mut self) -> Result<Connections, ParseError> {
+        if self.is_punct(")") {
+            return Ok(Connections::Positional(vec![]));
+        }
+        if !self.is_punct(".") {
+            let mut exprs: Vec<Option<Expression>> = vec![];
+            loop {
+                if self.is_punct(",") || self.is_punct(")") {
+                    exprs.push(None);
+                } else {
+                    exprs.push( Some(self.expression()?) );
+                }
+                if !self.eat_punct(",") {
+                    break;
+                }
+            }
+            return Ok(Connections::Positional(exprs));
+        }
+
+        let mut ports: Vec<(String, Option<Expression>)> = vec![];
+        loop {
+            self.expect_punct(".")?;
+            let port = self.identifier()?;
+            if ports.iter().any(|p| p.0 == port) {
+                return Err(self.error(&format!("port '{}' is connected twice", port)));
+            }
+            self.expect_punct("(")?;
+            let expr = if self.is_punct(")") {
+                None
+            } else {
+                Some(self.expression()?)
+            };
+            self.expect_punct(")")?;
+            ports.push( (port, expr) );
+            if !self.eat_punct(",") {
+                break;
+            }
+        }
+        Ok(Connections::Named(ports))
+    }
+
+    // "begin : main integer k; ..." declares variables of the block,
+    // "main.k", which are set aside for the module or task it's in
+    fn block_declarations(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut locals: Vec<String> = vec![];
+        let path = self.blocks.join(".");
+        while let Some(kind) = self.eat_variable_kind() {
+            let (decls, _) = self.declarators(&[kind])?;
+            for mut decl in decls {
+                locals.push(decl.name.clone());
+                decl.name = format!("{}.{}", path, decl.name);
+                self.block_decls.push(decl);
+            }
+        }
+        Ok(locals)
+    }
+
+    // "reg clk = 0;" starts clk off at time 0, as an initial block would
+    fn declaration(&mut self, m: &mut Module, kinds: &[DeclKind]) -> Result<(), ParseError> {
+        let (decls, inits) = self.declarators(kinds)?;
+        for decl in decls {
+            m.items.push( ModuleItem::Decl(decl) );
+        }
+        if !inits.is_empty() {
+            m.items.push( ModuleItem::Proc( build_proc(ProcedureType::Initial, &m.name, inits) ));
+        }
+        Ok(())
+    }
+
+    // "signed [7:0] a, b = 1, mem [0:3];" after the kind: the declarations
+    // of each name, and the assignments that give variables their
+    // initial values
+    fn declarators(&mut self, kinds: &[DeclKind])
+        -> Result<(Vec<Declaration>, Vec<Statement>), ParseError>
+    {
+        let signed = self.eat_keyword("signed");
+        let range = self.range()?;
+        if let Some(&kind) = kinds.iter().find(|k| k.width().is_some()) {
+            if signed || range.is_some() {
+                return Err(self.error(&format!("{} variables can't have a range or be signed", kind)));
+            }
+        }
+        let mut decls: Vec<Declaration> = vec![];
+        let mut inits: Vec<Statement> = vec![];
+        loop {
+            let name = self.identifier()?;
+            // "reg [7:0] mem [0:255]" is a memory of 256 bytes
+            let array = self.range()?;
+            if array.is_some() && !(kinds.len() == 1 && kinds[0].is_variable()) {
+                return Err(self.error(&format!("{}: only a variable can be a memory", name)));
+            }
+            if self.eat_punct("=") {
+                if !self.blocks.is_empty() {
+                    return Err(self.error("a block's variables can't have initial values"));
+                }
+                if array.is_some() || !kinds.iter().any(|k| k.is_variable()) {
+                    return Err(self.error(&format!("{}: only a variable can have an initial value",
+                        name)));
+                }
+                let expr = self.expression()?;
+                inits.push( Statement::BlockingAssign{id: Operand::Identifier(name.clone()), expr} );
+            }
+            for kind in kinds {
+                decls.push( Declaration {
+                    kind: *kind,
+                    name: name.clone(),
+                    range: range.clone(),
+                    signed,
+                    array: array.clone(),
+                });
+            }
+            if !self.eat_punct(",") {
+                break;
+            }
+        }
+        self.expect_punct(";")?;
+        Ok((decls, inits))
+    }
+
+    // "wand [7:0] y, z;" or "wire (weak0, weak1) y = a & b;", which is
+    // shorthand for a continuous assignment
+    fn net_declaration(&mut self, m: &mut Module, kind: NetKind) -> Result<(), ParseError> {
+        let strength = self.drive_strength(net::STRONG)?;
+        let signed = self.eat_keyword("signed");
+        let range = self.range()?;
+        loop {
+            let name = self.identifier()?;
+            m.items.push( ModuleItem::Decl(Declaration {
+                kind: DeclKind::Net(kind),
+                name: name.clone(),
+                range: range.clone(),
+                signed,
+                array: None,
+            }));
+            if self.eat_punct("=") {
+                let expr = self.expression()?;
+                let mut ca = ContinuousAssign::new(Operand::Identifier(name), expr, None);
+                ca.strength = strength;
+                m.items.push( ModuleItem::Assign(ca) );
+            }
+            if !self.eat_punct(",") {
+                break;
+            }
+        }
+        self.expect_punct(";")
+    }
+
+    fn net_kind(&self) -> Option<NetKind> {
+        match *self.peek() {
+            Token::Ident(ref s) => NetKind::from_keyword(s),
+            _ => None,
+        }
+    }
+
+    // "(strong0, weak1)", or "(weak1)" for a pullup, if there is one;
+    // "and (y, a, b)" starts with a bracket too
+    fn drive_strength(&mut self, default: DriveStrength) -> Result<DriveStrength, ParseError> {
+        let is_strength = |tok: &Token| match *tok {
+            Token::Ident(ref s) => DriveStrength::from_keyword(s).is_some(),
+            _ => false,
+        };
+        if !self.is_punct("(") || !is_strength(self.peek_next()) {
+            return Ok(default);
+        }
+        self.advance();
+        let mut strength = default;
+        let mut given = (false, false);
+        loop {
+            let (level, one) = match *self.peek() {
+                Token::Ident(ref s) => DriveStrength::from_keyword(s),
+                _ => None,
+            }.ok_or_else(|| self.unexpected("a drive strength"))?;
+            if one {
+                if given.1 {
+                    return Err(self.error("two strengths for 1"));
+                }
+                strength.one = level;
+                given.1 = true;
+            } else {
+                if given.0 {
+                    return Err(self.error("two strengths for 0"));
+                }
+                strength.zero = level;
+                given.0 = true;
+            }
+            self.advance();
+            if !self.eat_punct(",") {
+                break;
+            }
+        }
+        self.expect_punct(")")?;
+        if strength.zero == Strength::HighZ && strength.one == Strength::HighZ {
+            return Err(self.error("(highz0, highz1) doesn't drive anything"));
+        }
+        Ok(strength)
+    }
+
+    //
+    // Statements
+    //
+    // Procedures are flat lists of statements, so a statement may expand
+    // into several (a begin-end block, or a delay in front of an assignment)
+    fn statement(&mut self) -> Result<Vec<Statement>, ParseError> {
+        let mut stmts: Vec<Statement> = vec![];
+
+        if self.eat_keyword("begin") {
+            let named = self.eat_punct(":");
+            let mut locals: Vec<String> = vec![];
+            if named {
+                let name = self.identifier()?;
+                self.blocks.push(name);
+                locals = self.block_declarations()?;
+            }
+            while !self.eat_keyword("end") {
+                if *self.peek() == Token::Eof {
+                    return Err(self.unexpected("'end'"));
+                }
+                stmts.append( &mut self.statement()? );
+            }
+            if named {
+                // "k" in here is "main.k"
+                let path = self.blocks.join(".");
+                let rename = |var: &str| {
+                    let first = var.split(&['.', '['][..]).next().unwrap_or(var);
+                    if locals.iter().any(|local| local == first) {
+                        format!("{}.{}", path, var)
+                    } else {
+                        var.to_string()
+                    }
+                };
+                for stmt in &mut stmts {
+                    stmt.rename_apart(&rename, &|name: &str| name.to_string());
+                }
+                self.blocks.pop();
+            }
+
+        } else if self.eat_punct("#") {
+            let dly = self.delay()?;
+            stmts.push( Statement::Delay{dly} );
+            stmts.append( &mut self.statement()? );
+
+        } else if self.eat_punct("@") {
+            if self.eat_implicit_sensitivity()? {
+                // @* waits on whatever the statement after it reads
+                let mut body = self.statement()?;
+                stmts.push( Statement::AtChange{edges: implicit_sensitivity(&body)} );
+                stmts.append( &mut body );
+            } else {
+                stmts.push( self.event_control()? );
+                stmts.append( &mut self.statement()? );
+            }
+
+        } else if self.eat_punct("->") {
+            let event = match self.select()? {
+                Operand::Identifier(name) => name,
+                _ => return Err(self.error("an event can't be indexed")),
+            };
+            self.expect_punct(";")?;
+            stmts.push( Statement::Trigger{event} );
+
+        } else if self.eat_keyword("wait") {
+            let cond = self.condition()?;
+            stmts.push( Statement::Wait{cond} );
+            stmts.append( &mut self.statement()? );
+
+        } else if self.eat_punct(";") {
+            // null statement
+
+        } else if let Token::SysIdent(ref name) = *self.peek() {
+            let name = name.clone();
+            self.advance();
+            let args = self.task_arguments()?;
+            self.expect_punct(";")?;
+            let block = if self.blocks.is_empty() {
+                None
+            } else {
+                Some(self.blocks.join("."))
+            };
+            stmts.push( Statement::SystemTask{name, args, block} );
+
+        } else if self.eat_keyword("if") {
+            let cond = self.condition()?;
+            let then_stmts = self.statement()?;
+            let else_stmts = if self.eat_keyword("else") {
+                self.statement()?
+            } else {
+                vec![]
+            };
+            stmts.push( Statement::If{cond, then_stmts, else_stmts} );
+
+        } else if self.is_keyword("case") || self.is_keyword("casez") ||
+                  self.is_keyword("casex") {
+            stmts.push( self.case_statement()? );
+
+        } else if self.eat_keyword("for") {
+            self.expect_punct("(")?;
+            let init = self.assignment()?;
+            self.expect_punct(";")?;
+            let cond = self.expression()?;
+            self.expect_punct(";")?;
+            let step = self.assignment()?;
+            self.expect_punct(")")?;
+            let body = self.statement()?;
+            stmts.push( Statement::For{
+                init: Box::new(init),
+                cond,
+                step: Box::new(step),
+                body,
+            });
+
+        } else if self.eat_keyword("while") {
+            let cond = self.condition()?;
+            let body = self.statement()?;
+            stmts.push( Statement::While{cond, body} );
+
+        } else if self.eat_keyword("repeat") {
+            let count = self.condition()?;
+            let body = self.statement()?;
+            stmts.push( Statement::Repeat{count, body} );
+
+        } else if self.eat_keyword("forever") {
+            let body = self.statement()?;
+            stmts.push( Statement::Forever{body} );
+
+        } else if self.eat_keyword("fork") {
+            if self.eat_punct(":") {
+                self.identifier()?;
+            }
+            // every statement in here is a thread of its own
+            let mut branches: Vec<Rc<Vec<Statement>>> = vec![];
+            let join = loop {
+                if self.eat_keyword("join") {
+                    break JoinKind::All;
+                } else if self.eat_keyword("join_any") {
+                    break JoinKind::Any;
+                } else if self.eat_keyword("join_none") {
+                    break JoinKind::None;
+                } else if *self.peek() == Token::Eof {
+                    return Err(self.unexpected("'join'"));
+                }
+                branches.push( Rc::new(lower_body(self.statement()?)) );
+            };
+            stmts.push( Statement::Fork{join, branches} );
+
+        } else if self.eat_keyword("disable") {
+            // disabling named blocks isn't supported
+            self.expect_keyword("fork")?;
+            self.expect_punct(";")?;
+            stmts.push( Statement::DisableFork );
+
+        } else if self.is_task_call() {
+            let name = self.identifier()?;
+            let args = if self.is_punct("(") {
+                self.call_arguments()?
+            } else {
+                vec![]
+            };
+            self.expect_punct(";")?;
+            stmts.push( Statement::Call{name, args} );
+
+        } else {
+            stmts.push( self.assignment()? );
+            self.expect_punct(";")?;
+        }
+        Ok(stmts)
+    }
+
+    // "check(a, b);" or "reset;", rather than an assignment
+    fn is_task_call(&self) -> bool {
+        match (self.peek(), self.peek_next()) {
+            (Token::Ident(s), Token::Punct(p)) => !is_reserved(s) && (*p == "(" || *p == ";"),
+            _ => false,
+        }
+    }
+
+    // (a, b + 1) after a task or function name
+    fn call_arguments(&mut self) -> Result<Vec<Expression>, ParseError> {
+        let mut args: Vec<Expression> = vec![];
+        self.expect_punct("(")?;
+        if self.eat_punct(")") {
+            return Ok(args);
+        }
+        loop {
+            args.push( self.expression()? );
+            if !self.eat_punct(",") {
+                break;
+            }
+        }
+        self.expect_punct(")")?;
+        Ok(args)
+    }
+
+    // "a = b" or "a <= b", without the semicolon so for loops can use it
+    fn assignment(&mut self) -> Result<Statement, ParseError> {
+        let id = self.lvalue()?;
+        if self.eat_punct("=") {
+            let control = self.intra_assignment_control()?;
+            let expr = self.expression()?;
+            Ok(match control {
+                Some(control) => Statement::BlockingAfter{id, expr, control: Box::new(control)},
+                None => Statement::BlockingAssign{id, expr},
+            })
+        } else if self.eat_punct("<=") {
+            let control = self.intra_assignment_control()?;
+            let expr = self.expression()?;
+            Ok(match control {
+                Some(control) => Statement::NonBlockingAfter{id, expr, control: Box::new(control)},
+                None => Statement::NonBlockingAssign{id, expr},
+            })
+        } else {
+            Err(self.unexpected("'=' or '<='"))
+        }
+    }
+
+    // the "#3" or "@(posedge clk)" in "a = #3 b"
+    fn intra_assignment_control(&mut self) -> Result<Option<Statement>, ParseError> {
+        if self.eat_punct("#") {
+            Ok(Some( Statement::Delay{dly: self.delay()?} ))
+        } else if self.eat_punct("@") {
+            Ok(Some( self.event_control()? ))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // ("a=%b", a), or nothing at all
+    fn task_arguments(&mut self) -> Result<Vec<Argument>, ParseError> {
+        let mut args: Vec<Argument> = vec![];
+        if !self.eat_punct("(") {
+            return Ok(args);
+        }
+        if self.eat_punct(")") {
+            return Ok(args);
+        }
+        loop {
+            let string = match *self.peek() {
+                Token::Str(ref s) => Some(s.clone()),
+                _ => None,
+            };
+            match string {
+                Some(s) => {
+                    self.advance();
+                    args.push( Argument::Str(s) );
+                },
+                None => args.push( Argument::Expr(self.expression()?) ),
+            }
+            if !self.eat_punct(",") {
+                break;
+            }
+        }
+        self.expect_punct(")")?;
+        Ok(args)
+    }
+
+    // (expr)
+    fn condition(&mut self) -> Result<Expression, ParseError> {
+        self.expect_punct("(")?;
+        let cond = self.expression()?;
+        self.expect_punct(")")?;
+        Ok(cond)
+    }
+
+    fn case_statement(&mut self) -> Result<Statement, ParseError> {
+        let kind = if self.eat_keyword("casez") {
+            CaseKind::Casez
+        } else if self.eat_keyword("casex") {
+            CaseKind::Casex
+        } else {
+            self.expect_keyword("case")?;
+            CaseKind::Case
+        };
+        let expr = self.condition()?;
+        let mut items: Vec<CaseItem> = vec![];
+        let mut default: Option<Vec<Statement>> = None;
+
+        while !self.eat_keyword("endcase") {
+            if self.eat_keyword("default") {
+                if default.is_some() {
+                    return Err(self.error("more than one default in case statement"));
+                }
+                self.eat_punct(":");
+                default = Some( self.statement()? );
+                continue;
+            }
+            let mut labels: Vec<Expression> = vec![];
+            loop {
+                labels.push( self.expression()? );
+                if !self.eat_punct(",") {
+                    break;
+                }
+            }
+            self.expect_punct(":")?;
+            let stmts = self.statement()?;
+            items.push( CaseItem{labels, stmts} );
+        }
+        Ok(Statement::Case{kind, expr, items, default: default.unwrap_or_default()})
+    }
+
+    // @(posedge clk or negedge rst_n), @(a, b) or @a
+    // "*" or "(*)" after an @
+    fn eat_implicit_sensitivity(&mut self) -> Result<bool, ParseError> {
+        if self.eat_punct("*") {
+            return Ok(true);
+        }
+        if self.is_punct("(") && *self.peek_next() == Token::Punct("*") {
+            self.advance();
+            self.advance();
+            self.expect_punct(")")?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    // @(posedge clk or negedge rst_n), or @(a & b or c[3]); when it's all
+    // plain variables the engine can wait on those directly
+    fn event_control(&mut self) -> Result<Statement, ParseError> {
+        let mut events: Vec<EventExpr> = vec![];
+        if !self.eat_punct("(") {
+            let expr = Expression::Const(self.select()?);
+            events.push( EventExpr{sense: Sense::Any, expr} );
+        } else {
+            loop {
+                let sense = if self.eat_keyword("posedge") {
+                    Sense::Rise
+                } else if self.eat_keyword("negedge") {
+                    Sense::Fall
+                } else {
+                    Sense::Any
+                };
+                let expr = self.expression()?;
+                events.push( EventExpr{sense, expr} );
+                if !self.eat_keyword("or") && !self.eat_punct(",") {
+                    break;
+                }
+            }
+            self.expect_punct(")")?;
+        }
+
+        let mut edges: Vec<Edge> = vec![];
+        for event in &events {
+            let var = match event.expr {
+                Expression::Const(Operand::Identifier(ref var)) => var.clone(),
+                _ => return Ok( Statement::AtExpr{events} ),
+            };
+            edges.push( match event.sense {
+                Sense::Any => Edge::Any(var),
+                Sense::Rise => Edge::Rise(var),
+                Sense::Fall => Edge::Fall(var),
+            });
+        }
+        Ok( Statement::AtChange{edges} )
+    }
+
+    //
+    // Expressions
+    //
+    // Precedence climbing over the binary operators, loosest first:
+    //   ?:  ||  &&  |  ^ ~^  &  == != === !==  < <= > >=
+    //   << >> <<< >>>  + -  * / %  **  unary
+    fn expression(&mut self) -> Result<Expression, ParseError> {
+        let cond = self.binary_expression(0)?;
+        if !self.eat_punct("?") {
+            return Ok(cond);
+        }
+        let a = self.expression()?;
+        self.expect_punct(":")?;
+        let b = self.expression()?;
+        Ok(Expression::ternary(cond, a, b))
+    }
+
+    fn binary_op(&self) -> Option<(BinaryOp, usize)> {
+        let p = match *self.peek() {
+            Token::Punct(p) => p,
+            _ => return None,
+        };
+        let op = match p {
+            "||" => (BinaryOp::LogOr, 0),
+            "&&" => (BinaryOp::LogAnd, 1),
+            "|" => (BinaryOp::Or, 2),
+            "^" => (BinaryOp::Xor, 3),
+            "~^" | "^~" => (BinaryOp::Xnor, 3),
+            "&" => (BinaryOp::And, 4),
+            "==" => (BinaryOp::Eq, 5),
+            "!=" => (BinaryOp::Ne, 5),
+            "===" => (BinaryOp::CaseEq, 5),
+            "!==" => (BinaryOp::CaseNe, 5),
+            "<" => (BinaryOp::Lt, 6),
+            "<=" => (BinaryOp::Le, 6),
+            ">" => (BinaryOp::Gt, 6),
+            ">=" => (BinaryOp::Ge, 6),
+            "<<" => (BinaryOp::Shl, 7),
+            ">>" => (BinaryOp::Shr, 7),
+            "<<<" => (BinaryOp::AShl, 7),
+            ">>>" => (BinaryOp::AShr, 7),
+            "+" => (BinaryOp::Add, 8),
+            "-" => (BinaryOp::Sub, 8),
+            "*" => (BinaryOp::Mul, 9),
+            "/" => (BinaryOp::Div, 9),
+            "%" => (BinaryOp::Mod, 9),
+            "**" => (BinaryOp::Pow, 10),
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    fn binary_expression(&mut self, min_prec: usize) -> Result<Expression, ParseError> {
+        let mut lhs = self.unary_expression()?;
+        while let Some((op, prec)) = self.binary_op() {
+            if prec < min_prec {
+                break;
+            }
+            self.advance();
+            let rhs = self.binary_expression(prec + 1)?;
+            lhs = Expression::binary(op, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn unary_expression(&mut self) -> Result<Expression, ParseError> {
+        let op = match *self.peek() {
+            Token::Punct("+") => UnaryOp::Plus,
+            Token::Punct("-") => UnaryOp::Minus,
+            Token::Punct("~") => UnaryOp::Not,
+            Token::Punct("!") => UnaryOp::LogNot,
+            Token::Punct("&") => UnaryOp::RedAnd,
+            Token::Punct("~&") => UnaryOp::RedNand,
+            Token::Punct("|") => UnaryOp::RedOr,
+            Token::Punct("~|") => UnaryOp::RedNor,
+            Token::Punct("^") => UnaryOp::RedXor,
+            Token::Punct("~^") | Token::Punct("^~") => UnaryOp::RedXnor,
+            _ => return self.primary(),
+        };
+        self.advance();
+        Ok(Expression::unary(op, self.unary_expression()?))
+    }
+
+    fn primary(&mut self) -> Result<Expression, ParseError> {
+        match *self.peek() {
+            Token::Number{..} | Token::Real(_) => {
+                Ok(Expression::Const( Operand::Literal(self.number()?) ))
+            },
+            Token::Ident(_) if *self.peek_next() == Token::Punct("(") => {
+                let name = self.identifier()?;
+                Ok(Expression::Call(name, self.call_arguments()?))
+            },
+            Token::Ident(_) => Ok(Expression::Const( self.select()? )),
+            Token::Str(ref s) => {
+                // a string is a number, eight bits to a character
+                let s = s.clone();
+                if s.len() * 8 > MAX_WIDTH {
+                    return Err(self.error(&format!("strings longer than {} characters are not supported",
+                        MAX_WIDTH / 8)));
+                }
+                self.advance();
+                let n = s.bytes().fold(0, |n, c| (n << 8) | c as u64);
+                Ok(Expression::Const( Operand::Literal(Value::new(n, 8 * s.len().max(1))) ))
+            },
+            Token::SysIdent(ref name) => {
+                let name = name.clone();
+                self.advance();
+                let mut args: Vec<Expression> = vec![];
+                if self.eat_punct("(") {
+                    args.push( self.expression()? );
+                    while self.eat_punct(",") {
+                        args.push( self.expression()? );
+                    }
+                    self.expect_punct(")")?;
+                }
+                Ok(Expression::SysCall(name, args))
+            },
+            Token::Punct("(") => {
+                self.advance();
+                let e = self.expression()?;
+                self.expect_punct(")")?;
+                Ok(e)
+            },
+            Token::Punct("{") => {
+                self.advance();
+                let first = self.expression()?;
+                if self.is_punct("{") {
+                    // {4{a}}
+                    self.advance();
+                    let repeated = self.concatenation_items()?;
+                    self.expect_punct("}")?;
+                    return Ok(Expression::Replicate(
+                        Box::new(first), Box::new(repeated)));
+                }
+                let mut exprs = vec![first];
+                while self.eat_punct(",") {
+                    exprs.push( self.expression()? );
+                }
+                self.expect_punct("}")?;
+                Ok(Expression::Concat(exprs))
+            },
+            _ => Err(self.unexpected("an expression")),
+        }
+    }
+
+    // the inside of {...}, having eaten the opening brace
+    fn concatenation_items(&mut self) -> Result<Expression, ParseError> {
+        let mut exprs = vec![ self.expression()? ];
+        while self.eat_punct(",") {
+            exprs.push( self.expression()? );
+        }
+        self.expect_punct("}")?;
+        if exprs.len() == 1 {
+            Ok(exprs.remove(0))
+        } else {
+            Ok(Expression::Concat(exprs))
+        }
+    }
+
+    // a, a[3] or a[7:4]
+    fn select(&mut self) -> Result<Operand, ParseError> {
+        let mut var = self.identifier()?;
+        loop {
+            while self.eat_punct(".") {
+                // a hierarchical name, top.u1.q
+                var = format!("{}.{}", var, self.identifier()?);
+            }
+            if !self.eat_punct("[") {
+                return Ok(Operand::Identifier(var));
+            }
+            let index = self.expression()?;
+            if self.eat_punct(":") {
+                // elaboration checks the bounds are constant
+                let lsb = self.expression()?;
+                self.expect_punct("]")?;
+                return Ok(Operand::PartSelect(var, Box::new(index), Box::new(lsb)));
+            }
+            self.expect_punct("]")?;
+            if !self.is_punct(".") {
+                return Ok(Operand::BitSelect(var, Box::new(index)));
+            }
+            // a generated scope, stage[2].q, or stage[i - 1].q, which
+            // elaboration folds into a number
+            let n = match index {
+                Expression::Const(Operand::Literal(ref num)) => num.to_u64(),
+                _ => None,
+            };
+            var = match n {
+                Some(n) => format!("{}[{}]", var, n),
+                None => format!("{}[{}]", var, index),
+            };
+        }
+    }
+
+    // {a, b[3], c[7:4]} on the left hand side of an assignment
+    fn concatenation(&mut self, item: fn(&mut Parser) -> Result<Operand, ParseError>)
+        -> Result<Operand, ParseError>
+    {
+        self.expect_punct("{")?;
+        let mut ops: Vec<Operand> = vec![];
+        loop {
+            ops.push( item(self)? );
+            if !self.eat_punct(",") {
+                break;
+            }
+        }
+        self.expect_punct("}")?;
+        Ok(Operand::Concat(ops))
+    }
+
+    // what can go on the left of an assignment
+    fn lvalue(&mut self) -> Result<Operand, ParseError> {
+        if self.is_punct("{") {
+            self.concatenation(Parser::lvalue)
+        } else {
+            self.select()
+        }
+    }
+}
+
+fn build_proc(kind: ProcedureType, scope: &str, stmts: Vec<Statement>) -> Procedure {
+    let mut p = Procedure::new(kind);
+    p.scope = scope.to_string();
+    for stmt in stmts {
+        p.push(stmt);
+    }
+    p
+}
+
+const RESERVED: [&str; 81] = [
+    "module", "endmodule", "input", "output", "inout", "reg", "wire",
+    "initial", "always", "begin", "end", "posedge", "negedge", "or",
+    "assign", "parameter", "if", "else", "case", "casez", "casex",
+    "endcase", "default", "for", "while", "repeat", "forever",
+    "localparam", "defparam", "generate", "endgenerate", "genvar",
+    "task", "endtask", "function", "endfunction", "automatic",
+    "fork", "join", "join_any", "join_none", "disable", "event", "wait",
+    "and", "nand", "nor", "xor", "xnor", "buf", "not", "bufif0", "bufif1",
+    "notif0", "notif1", "primitive", "endprimitive", "table", "endtable",
+    "tri", "wand", "wor", "tri0", "tri1", "supply0", "supply1", "pullup",
+    "pulldown", "strong0", "strong1", "pull0", "pull1", "weak0", "weak1",
+    "highz0", "highz1", "integer", "time", "real", "realtime", "signed",
+];
+
+fn is_reserved(word: &str) -> bool {
+    RESERVED.contains(&word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(src: &str) -> Module {
+        match parse_str("t.v", src, &mut HashMap::new()).unwrap().pop() {
+            Some(Description::Module(m)) => m,
+            _ => panic!("no module in {}", src),
+        }
+    }
+
+    fn error(src: &str) -> String {
+        match parse_str("t.v", src, &mut HashMap::new()) {
+            Ok(_) => panic!("{} parsed", src),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    fn statements(m: &Module) -> Vec<String> {
+        m.items.iter().filter_map(|item| match *item {
+            ModuleItem::Proc(ref p) => Some(p),
+            _ => None,
+        }).flat_map(|p| p.stmts.iter().map(|s| s.to_string())).collect()
+    }
+
+    #[test]
+    fn expressions_follow_precedence() {
+        let expr = parse_expression("a + b * 2 == c ? {2{d}} : -e[3:0]").unwrap();
+        assert_eq!(expr.to_string(), "((a + (b * 2)) == c) ? {2{d}} : -e[3:0]");
+        assert_eq!(parse_expression("a + ").unwrap_err().to_string(),
+            ":1:5: expected an expression, found end of file");
+    }
+
+    #[test]
+    fn numbers_become_values() {
+        let expr = parse_expression("8'shf0 + 4'b1x0z").unwrap();
+        match expr {
+            Expression::Binary(BinaryOp::Add, ref a, ref b) => {
+                assert_eq!(**a, Expression::Const(Operand::Literal(
+                    Value::new(0xf0, 8).with_type(Type::Signed))));
+                assert_eq!(**b, Expression::Const(Operand::Literal(
+                    Value::parse(Some(4), 'b', "1x0z").unwrap())));
+            },
+            _ => panic!("{} isn't an addition", expr),
+        }
+        assert_eq!(parse_expression("65'h0").unwrap_err().to_string(),
+            ":1:1: numbers wider than 64 bits are not supported");
+        assert_eq!(parse_expression("4'b102").unwrap_err().to_string(),
+            ":1:1: malformed number '102'");
+    }
+
+    #[test]
+    fn ports_declarations_and_initial_values() {
+        let m = module("
+            module m(input clk, output reg [3:0] q);
+              reg r = 1'b1;
+              integer i = 0, j;
+              wire w = r;
+            endmodule");
+        assert_eq!(m.ports, vec!["clk", "q"]);
+        let decls: Vec<String> = m.decls().iter().map(|d| d.to_string()).collect();
+        assert_eq!(decls, vec!["input clk", "output [3:0] q", "reg [3:0] q",
+            "reg r", "integer i", "integer j", "wire w"]);
+        assert_eq!(statements(&m), vec!["r = 1", "i = 0"]);
+    }
+
+    #[test]
+    fn named_blocks_scope_their_variables() {
+        let m = module("
+            module m;
+              integer k;
+              initial begin : main
+                integer k;
+                k = 1;
+              end
+            endmodule");
+        let decls: Vec<String> = m.decls().iter().map(|d| d.to_string()).collect();
+        assert_eq!(decls, vec!["integer k", "integer main.k"]);
+        assert_eq!(statements(&m), vec!["main.k = 1"]);
+    }
+
+    #[test]
+    fn errors_point_at_line_and_column() {
+        assert_eq!(error("module m;\n  reg [3:0] a\n  initial a = 1;\nendmodule"),
+            "t.v:3:3: expected ';', found 'initial'");
+        assert_eq!(error("module m;\n  wire w = 1, x;\n  initial begin : b reg r = 0; end\nendmodule"),
+            "t.v:3:29: a block's variables can't have initial values");
+        assert_eq!(error("module m;\nendmodule\nmodule"),
+            "t.v:3:7: expected an identifier, found end of file");
+    }
+}
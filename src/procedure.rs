@@ -1,16 +1,54 @@
-/// Verilog Expression
+//! Verilog Expression
 
 use std::fmt;
+use std::collections::HashMap;
+use std::rc::Rc;
 
-pub type Value = usize;
+pub use value::Value;
+use net::DriveStrength;
 pub type Time = usize;
 pub type ProcId = usize;
+pub type AssignId = usize;
+
+// what to change on a walk through some code, see Statement::rewrite;
+// operands and expressions are visited innermost first
+pub trait Rewrite {
+    fn operand(&self, _op: &mut Operand) {}
+    fn expression(&self, _expr: &mut Expression) {}
+    fn function(&self, _name: &mut String, _args: &[Expression]) {}
+    fn task(&self, _name: &mut String, _args: &[Expression]) {}
+}
+
+// gives every variable, task and function a new name
+// variables go through `f`, task and function names through `calls`
+struct Renamer<'a> {
+    f: &'a dyn Fn(&str) -> String,
+    calls: &'a dyn Fn(&str) -> String,
+}
+
+impl<'a> Rewrite for Renamer<'a> {
+
+    fn operand(&self, op: &mut Operand) {
+        op.rename(self.f);
+    }
+
+    fn function(&self, name: &mut String, _args: &[Expression]) {
+        *name = (self.calls)(name);
+    }
+
+    fn task(&self, name: &mut String, _args: &[Expression]) {
+        *name = (self.calls)(name);
+    }
+}
 
 #[derive(PartialEq, Debug, Clone)]
 #[allow(dead_code)]
 pub enum Operand {
     Literal(Value),
     Identifier(String),
+    BitSelect(String, Box<Expression>), // a[3], a[i]
+    PartSelect(String, Box<Expression>, Box<Expression>), // a[7:4], a[W-1:0]
+    Concat(Vec<Operand>),               // {a, b} as an lvalue, most significant first
 }
 
 impl fmt::Display for Operand {
@@ -22,28 +60,182 @@ impl fmt::Display for Operand {
             Operand::Identifier(ref var) => {
                 write!(f, "{}", var)
             },
+            Operand::BitSelect(ref var, ref bit) => {
+                write!(f, "{}[{}]", var, bit)
+            },
+            Operand::PartSelect(ref var, ref msb, ref lsb) => {
+                write!(f, "{}[{}:{}]", var, msb, lsb)
+            },
+            Operand::Concat(ref ops) => {
+                let ops_str: Vec<String> = ops.iter().map(|op| format!("{}", op)).collect();
+                write!(f, "{{{}}}", ops_str.join(", "))
+            },
         }
     }
 }
 
 impl Operand {
-    pub fn get_identifier(&self) -> Option<String> {
-        if let Operand::Identifier(ref var) = *self {
-            Some(var.clone())
-        } else {
-            None
+
+    // visit every operand in here, innermost first
+    pub fn rewrite(&mut self, r: &dyn Rewrite) {
+        match *self {
+            Operand::BitSelect(_, ref mut bit) => bit.rewrite(r),
+            Operand::PartSelect(_, ref mut msb, ref mut lsb) => {
+                msb.rewrite(r);
+                lsb.rewrite(r);
+            },
+            Operand::Concat(ref mut ops) => {
+                for op in ops {
+                    op.rewrite(r);
+                }
+            },
+            _ => {},
+        }
+        r.operand(self);
+    }
+
+    // rename the variable, eg to give it its hierarchical name
+    pub fn rename(&mut self, f: &dyn Fn(&str) -> String) {
+        match *self {
+            Operand::Identifier(ref mut var) |
+            Operand::BitSelect(ref mut var, _) |
+            Operand::PartSelect(ref mut var, _, _) => *var = f(var),
+            _ => {},
+        }
+    }
+
+    pub fn get_identifiers(&self) -> Vec<String> {
+        match *self {
+            Operand::Literal(_) => vec![],
+            Operand::Identifier(ref var) => vec![var.clone()],
+            Operand::BitSelect(ref var, ref bit) => {
+                let mut vars = vec![var.clone()];
+                vars.append( &mut bit.get_identifiers() );
+                vars
+            },
+            Operand::PartSelect(ref var, ref msb, ref lsb) => {
+                let mut vars = vec![var.clone()];
+                vars.append( &mut msb.get_identifiers() );
+                vars.append( &mut lsb.get_identifiers() );
+                vars
+            },
+            Operand::Concat(ref ops) => {
+                let mut vars: Vec<String> = vec![];
+                for op in ops {
+                    vars.append( &mut op.get_identifiers() );
+                }
+                vars
+            },
+        }
+    }
+
+    // what an lvalue reads: "y[i] = a" moves when i does
+    pub fn get_index_identifiers(&self) -> Vec<String> {
+        match *self {
+            Operand::BitSelect(_, ref bit) => bit.get_identifiers(),
+            Operand::PartSelect(_, ref msb, ref lsb) => {
+                let mut vars = msb.get_identifiers();
+                vars.append( &mut lsb.get_identifiers() );
+                vars
+            },
+            Operand::Concat(ref ops) => {
+                let mut vars: Vec<String> = vec![];
+                for op in ops {
+                    vars.append( &mut op.get_index_identifiers() );
+                }
+                vars
+            },
+            Operand::Literal(_) | Operand::Identifier(_) => vec![],
         }
     }
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum UnaryOp {
+    Plus,       // +a
+    Minus,      // -a
+    Not,        // ~a
+    LogNot,     // !a
+    RedAnd,     // &a
+    RedNand,    // ~&a
+    RedOr,      // |a
+    RedNor,     // ~|a
+    RedXor,     // ^a
+    RedXnor,    // ~^a
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        let op = match *self {
+            UnaryOp::Plus => "+",
+            UnaryOp::Minus => "-",
+            UnaryOp::Not => "~",
+            UnaryOp::LogNot => "!",
+            UnaryOp::RedAnd => "&",
+            UnaryOp::RedNand => "~&",
+            UnaryOp::RedOr => "|",
+            UnaryOp::RedNor => "~|",
+            UnaryOp::RedXor => "^",
+            UnaryOp::RedXnor => "~^",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum BinaryOp {
+    Add, Sub, Mul, Div, Mod, Pow,
+    And, Or, Xor, Xnor,
+    LogAnd, LogOr,
+    Lt, Le, Gt, Ge,
+    Eq, Ne, CaseEq, CaseNe,
+    Shl, Shr, AShl, AShr,
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        let op = match *self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Pow => "**",
+            BinaryOp::And => "&",
+            BinaryOp::Or => "|",
+            BinaryOp::Xor => "^",
+            BinaryOp::Xnor => "~^",
+            BinaryOp::LogAnd => "&&",
+            BinaryOp::LogOr => "||",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::CaseEq => "===",
+            BinaryOp::CaseNe => "!==",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+            BinaryOp::AShl => "<<<",
+            BinaryOp::AShr => ">>>",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
 #[allow(dead_code)]
 pub enum Expression {
     Const(Operand),
-    And(Operand, Operand),
-    Or(Operand, Operand),
-    Not(Operand),
+    Unary(UnaryOp, Box<Expression>),
+    Binary(BinaryOp, Box<Expression>, Box<Expression>),
+    Ternary(Box<Expression>, Box<Expression>, Box<Expression>), // c ? a : b
+    Concat(Vec<Expression>),                  // {a, b & c}
+    Replicate(Box<Expression>, Box<Expression>), // {4{a}}
+    SysCall(String, Vec<Expression>),            // $time
+    Call(String, Vec<Expression>),               // a function of our own
 }
 
 impl fmt::Display for Expression {
@@ -52,40 +244,141 @@ impl fmt::Display for Expression {
             Expression::Const(ref num) => {
                 write!(f, "{}", num)
             },
-            Expression::And(ref a, ref b) => {
-                write!(f, "{} & {}", a, b)
+            Expression::Unary(ref op, ref a) => {
+                write!(f, "{}{}", op, a.nested())
+            },
+            Expression::Binary(ref op, ref a, ref b) => {
+                write!(f, "{} {} {}", a.nested(), op, b.nested())
+            },
+            Expression::Ternary(ref c, ref a, ref b) => {
+                write!(f, "{} ? {} : {}", c.nested(), a.nested(), b.nested())
             },
-            Expression::Or(ref a, ref b) => {
-                write!(f, "{} | {}", a, b)
+            Expression::Concat(ref exprs) => {
+                let strs: Vec<String> = exprs.iter().map(|e| format!("{}", e)).collect();
+                write!(f, "{{{}}}", strs.join(", "))
             },
-            Expression::Not(ref a) => {
-                write!(f, "~{}", a)
+            Expression::Replicate(ref n, ref a) => {
+                write!(f, "{{{}{{{}}}}}", n, a)
+            },
+            Expression::SysCall(ref name, ref args) => {
+                if args.is_empty() {
+                    write!(f, "{}", name)
+                } else {
+                    let strs: Vec<String> = args.iter().map(|e| format!("{}", e)).collect();
+                    write!(f, "{}({})", name, strs.join(", "))
+                }
+            },
+            Expression::Call(ref name, ref args) => {
+                let strs: Vec<String> = args.iter().map(|e| format!("{}", e)).collect();
+                write!(f, "{}({})", name, strs.join(", "))
             },
         }
     }
 }
 
 impl Expression {
-    fn get_identifiers(&self) -> Vec<String> {
-        let mut vars: Vec<String> = vec![];
+
+    pub fn unary(op: UnaryOp, a: Expression) -> Expression {
+        Expression::Unary(op, Box::new(a))
+    }
+
+    pub fn binary(op: BinaryOp, a: Expression, b: Expression) -> Expression {
+        Expression::Binary(op, Box::new(a), Box::new(b))
+    }
+
+    pub fn ternary(c: Expression, a: Expression, b: Expression) -> Expression {
+        Expression::Ternary(Box::new(c), Box::new(a), Box::new(b))
+    }
+
+    // an unsized number, like a plain 5 in the source
+    pub fn number(n: usize) -> Expression {
+        Expression::Const( Operand::Literal(Value::from(n)) )
+    }
+
+    pub fn identifier(var: &str) -> Expression {
+        Expression::Const( Operand::Identifier(var.to_string()) )
+    }
+
+    // for printing sub-expressions: parenthesise anything with an operator
+    pub fn nested(&self) -> String {
         match *self {
-            Expression::Const(ref num) => {
-                if let Some(var) = num.get_identifier() {
-                    vars.push(var);
-                }
+            Expression::Binary(..) | Expression::Ternary(..) => format!("({})", self),
+            _ => format!("{}", self),
+        }
+    }
+
+    // what an output can drive: a variable, a select of one, or a
+    // concatenation of those
+    pub fn lvalue(&self) -> Option<Operand> {
+        match *self {
+            Expression::Const(Operand::Literal(_)) => None,
+            Expression::Const(ref op) => Some(op.clone()),
+            Expression::Concat(ref exprs) => {
+                let ops: Option<Vec<Operand>> = exprs.iter().map(Expression::lvalue).collect();
+                ops.map(Operand::Concat)
             },
-            Expression::And(ref a, ref b) | 
-            Expression::Or(ref a, ref b) => {
-                if let Some(var) = a.get_identifier() {
-                    vars.push(var);
+            _ => None,
+        }
+    }
+
+    pub fn rename(&mut self, f: &dyn Fn(&str) -> String) {
+        self.rewrite(&Renamer { f, calls: f });
+    }
+
+    pub fn rewrite(&mut self, r: &dyn Rewrite) {
+        match *self {
+            Expression::Const(ref mut op) => op.rewrite(r),
+            Expression::Unary(_, ref mut a) => a.rewrite(r),
+            Expression::Binary(_, ref mut a, ref mut b) |
+            Expression::Replicate(ref mut a, ref mut b) => {
+                a.rewrite(r);
+                b.rewrite(r);
+            },
+            Expression::Ternary(ref mut c, ref mut a, ref mut b) => {
+                c.rewrite(r);
+                a.rewrite(r);
+                b.rewrite(r);
+            },
+            Expression::Concat(ref mut exprs) |
+            Expression::SysCall(_, ref mut exprs) => {
+                for e in exprs {
+                    e.rewrite(r);
                 }
-                if let Some(var) = b.get_identifier() {
-                    vars.push(var);
+            },
+            Expression::Call(ref mut name, ref mut args) => {
+                for e in args.iter_mut() {
+                    e.rewrite(r);
                 }
+                r.function(name, args);
             },
-            Expression::Not(ref a) => {
-                if let Some(var) = a.get_identifier() {
-                    vars.push(var);
+        }
+        r.expression(self);
+    }
+
+    pub fn get_identifiers(&self) -> Vec<String> {
+        let mut vars: Vec<String> = vec![];
+        match *self {
+            Expression::Const(ref num) => {
+                vars.append( &mut num.get_identifiers() );
+            },
+            Expression::Unary(_, ref a) => {
+                vars.append( &mut a.get_identifiers() );
+            },
+            Expression::Binary(_, ref a, ref b) |
+            Expression::Replicate(ref a, ref b) => {
+                vars.append( &mut a.get_identifiers() );
+                vars.append( &mut b.get_identifiers() );
+            },
+            Expression::Ternary(ref c, ref a, ref b) => {
+                vars.append( &mut c.get_identifiers() );
+                vars.append( &mut a.get_identifiers() );
+                vars.append( &mut b.get_identifiers() );
+            },
+            Expression::Concat(ref exprs) |
+            Expression::SysCall(_, ref exprs) |
+            Expression::Call(_, ref exprs) => {
+                for e in exprs {
+                    vars.append( &mut e.get_identifiers() );
                 }
             },
         }
@@ -95,6 +388,31 @@ impl Expression {
 
 
 
+// an argument to a system task: "a=%b", a
+#[derive(PartialEq, Debug, Clone)]
+pub enum Argument {
+    Str(String),
+    Expr(Expression),
+}
+
+impl Argument {
+    pub fn rewrite(&mut self, r: &dyn Rewrite) {
+        if let Argument::Expr(ref mut expr) = *self {
+            expr.rewrite(r);
+        }
+    }
+}
+
+impl fmt::Display for Argument {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Argument::Str(ref s) => write!(f, "{:?}", s),
+            Argument::Expr(ref expr) => write!(f, "{}", expr),
+        }
+    }
+}
+
+
 #[derive(Hash, PartialEq, Eq, Debug, Clone)]
 pub enum Edge {
     Rise(String),  // zero to non-zero
@@ -102,6 +420,16 @@ pub enum Edge {
     Any(String),   // anything else, eg 1 to 2
 }
 
+impl Edge {
+    pub fn rename(&mut self, f: &dyn Fn(&str) -> String) {
+        match *self {
+            Edge::Rise(ref mut var) |
+            Edge::Fall(ref mut var) |
+            Edge::Any(ref mut var) => *var = f(var),
+        }
+    }
+}
+
 impl fmt::Display for Edge {
     fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -118,21 +446,103 @@ impl fmt::Display for Edge {
     }
 }
 
+// which changes of an expression an event control waits for
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Sense {
+    Any,
+    Rise,
+    Fall,
+}
+
+// "posedge a & b": an event control on something more than a variable,
+// which fires when the expression's value changes
+#[derive(PartialEq, Debug, Clone)]
+pub struct EventExpr {
+    pub sense: Sense,
+    pub expr: Expression,
+}
+
+impl fmt::Display for EventExpr {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match self.sense {
+            Sense::Any => write!(f, "{}", self.expr),
+            Sense::Rise => write!(f, "posedge {}", self.expr),
+            Sense::Fall => write!(f, "negedge {}", self.expr),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum CaseKind {
+    Case,
+    Casez,  // z and ? bits are don't-cares
+    Casex,  // x, z and ? bits are don't-cares
+}
+
+impl fmt::Display for CaseKind {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CaseKind::Case => write!(f, "case"),
+            CaseKind::Casez => write!(f, "casez"),
+            CaseKind::Casex => write!(f, "casex"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CaseItem {
+    pub labels: Vec<Expression>,
+    pub stmts: Vec<Statement>,
+}
 
+// Statements come in two flavours. The structured ones (if, case and
+// the loops) are what the parser and builders write; Procedure::push()
+// lowers them into the flat ones (Jump, BranchIfNot, Switch and the
+// repeat counter) so a procedure stays a flat list walked by a counter,
+// and a delay or @ inside a loop body suspends and resumes like any other.
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub enum Statement {
-    Delay             {dly: Time},
+    Delay             {dly: Expression},
     BlockingAssign    {id: Operand, expr: Expression},
     NonBlockingAssign {id: Operand, expr: Expression},
     AtChange          {edges: Vec<Edge>},
+    AtExpr            {events: Vec<EventExpr>}, // @(posedge a & b or c[i])
+    // $monitor(...), and the named blocks it's inside for %m, "main.inner"
+    SystemTask        {name: String, args: Vec<Argument>, block: Option<String>},
+    Call              {name: String, args: Vec<Expression>}, // a task of our own
+    Return,                                                  // from a task or function
+    Fork              {join: JoinKind, branches: Vec<Rc<Vec<Statement>>>}, // each lowered
+    DisableFork,                                             // stop the threads it forked
+    Trigger           {event: String},                       // ->ev
+    Wait              {cond: Expression},                    // until it's true
+    NonBlockingAfter  {id: Operand, expr: Expression, control: Box<Statement>}, // q <= #2 d
+    Hold              {id: Operand, expr: Expression},       // "a = #3 b" is a hold, the
+    Release,                                                 // delay, then a release
+
+    // structured
+    BlockingAfter {id: Operand, expr: Expression, control: Box<Statement>}, // a = #3 b
+    If      {cond: Expression, then_stmts: Vec<Statement>, else_stmts: Vec<Statement>},
+    Case    {kind: CaseKind, expr: Expression, items: Vec<CaseItem>, default: Vec<Statement>},
+    For     {init: Box<Statement>, cond: Expression, step: Box<Statement>, body: Vec<Statement>},
+    While   {cond: Expression, body: Vec<Statement>},
+    Repeat  {count: Expression, body: Vec<Statement>},
+    Forever {body: Vec<Statement>},
+
+    // flat
+    Jump        {target: usize},
+    BranchIfNot {cond: Expression, target: usize},  // taken on false, x or z
+    Switch      {kind: CaseKind, expr: Expression, arms: Vec<(Vec<Expression>, usize)>,
+                 default: usize},
+    RepeatStart {count: Expression},    // push a loop counter
+    RepeatCheck {target: usize},        // count down, pop and leave at zero
 }
 
 impl fmt::Display for Statement {
     fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
         match *self {
             Statement::Delay{ref dly} => {
-                write!(f, "#{}", dly)
+                write!(f, "#{}", dly.nested())
             },
             Statement::BlockingAssign{ref id, ref expr} => {
                 write!(f, "{} = {}", id, expr)
@@ -150,20 +560,287 @@ impl fmt::Display for Statement {
                 let sensitivity_list = ids_str.join(" or ");
                 write!(f, "@({})", sensitivity_list)
             },
+            Statement::AtExpr{ref events} => {
+                let events: Vec<String> = events.iter().map(|e| format!("{}", e)).collect();
+                write!(f, "@({})", events.join(" or "))
+            },
+            Statement::SystemTask{ref name, ref args, ..} => {
+                let args_str: Vec<String> = args.iter().map(|a| format!("{}", a)).collect();
+                write!(f, "{}({})", name, args_str.join(", "))
+            },
+            Statement::Call{ref name, ref args} => {
+                let args_str: Vec<String> = args.iter().map(|a| format!("{}", a)).collect();
+                write!(f, "{}({})", name, args_str.join(", "))
+            },
+            Statement::Return => {
+                write!(f, "return")
+            },
+            Statement::Fork{ref join, ref branches} => {
+                write!(f, "fork ({} threads) {}", branches.len(), join)
+            },
+            Statement::DisableFork => {
+                write!(f, "disable fork")
+            },
+            Statement::Trigger{ref event} => {
+                write!(f, "->{}", event)
+            },
+            Statement::Wait{ref cond} => {
+                write!(f, "wait ({})", cond)
+            },
+            Statement::NonBlockingAfter{ref id, ref expr, ref control} => {
+                write!(f, "{} <= {} {}", id, control, expr)
+            },
+            Statement::Hold{ref id, ref expr} => {
+                write!(f, "hold {} = {}", id, expr)
+            },
+            Statement::Release => {
+                write!(f, "release")
+            },
+            Statement::BlockingAfter{ref id, ref expr, ref control} => {
+                write!(f, "{} = {} {}", id, control, expr)
+            },
+            Statement::If{ref cond, ..} => {
+                write!(f, "if ({})", cond)
+            },
+            Statement::Case{ref kind, ref expr, ..} => {
+                write!(f, "{} ({})", kind, expr)
+            },
+            Statement::For{ref init, ref cond, ref step, ..} => {
+                write!(f, "for ({}; {}; {})", init, cond, step)
+            },
+            Statement::While{ref cond, ..} => {
+                write!(f, "while ({})", cond)
+            },
+            Statement::Repeat{ref count, ..} => {
+                write!(f, "repeat ({})", count)
+            },
+            Statement::Forever{..} => {
+                write!(f, "forever")
+            },
+            Statement::Jump{ref target} => {
+                write!(f, "goto {}", target)
+            },
+            Statement::BranchIfNot{ref cond, ref target} => {
+                write!(f, "if !({}) goto {}", cond, target)
+            },
+            Statement::Switch{ref kind, ref expr, ref arms, ref default} => {
+                let mut arms_str: Vec<String> = vec![];
+                for &(ref labels, target) in arms {
+                    let labels: Vec<String> = labels.iter().map(|l| format!("{}", l)).collect();
+                    arms_str.push( format!("{}: goto {}", labels.join(", "), target) );
+                }
+                write!(f, "{} ({}) {}; default: goto {}",
+                       kind, expr, arms_str.join("; "), default)
+            },
+            Statement::RepeatStart{ref count} => {
+                write!(f, "repeat count {}", count)
+            },
+            Statement::RepeatCheck{ref target} => {
+                write!(f, "repeat done? goto {}", target)
+            },
         }
     }
 }
 
 impl Statement {
+
+    pub fn rewrite(&mut self, r: &dyn Rewrite) {
+        match *self {
+            Statement::Delay{dly: ref mut expr} |
+            Statement::Wait{cond: ref mut expr} |
+            Statement::BranchIfNot{cond: ref mut expr, ..} |
+            Statement::RepeatStart{count: ref mut expr} => {
+                expr.rewrite(r);
+            },
+            Statement::BlockingAssign{ref mut id, ref mut expr} |
+            Statement::NonBlockingAssign{ref mut id, ref mut expr} |
+            Statement::Hold{ref mut id, ref mut expr} => {
+                id.rewrite(r);
+                expr.rewrite(r);
+            },
+            Statement::BlockingAfter{ref mut id, ref mut expr, ref mut control} |
+            Statement::NonBlockingAfter{ref mut id, ref mut expr, ref mut control} => {
+                id.rewrite(r);
+                expr.rewrite(r);
+                control.rewrite(r);
+            },
+            Statement::SystemTask{ref mut args, ..} => {
+                for arg in args {
+                    arg.rewrite(r);
+                }
+            },
+            Statement::AtExpr{ref mut events} => {
+                for event in events {
+                    event.expr.rewrite(r);
+                }
+            },
+            Statement::Call{ref mut name, ref mut args} => {
+                for arg in args.iter_mut() {
+                    arg.rewrite(r);
+                }
+                r.task(name, args);
+            },
+            Statement::Fork{ref mut branches, ..} => {
+                for branch in branches {
+                    rewrite_all(Rc::make_mut(branch).as_mut_slice(), r);
+                }
+            },
+            Statement::If{ref mut cond, ref mut then_stmts, ref mut else_stmts} => {
+                cond.rewrite(r);
+                rewrite_all(then_stmts, r);
+                rewrite_all(else_stmts, r);
+            },
+            Statement::Case{ref mut expr, ref mut items, ref mut default, ..} => {
+                expr.rewrite(r);
+                for item in items {
+                    for label in &mut item.labels {
+                        label.rewrite(r);
+                    }
+                    rewrite_all(&mut item.stmts, r);
+                }
+                rewrite_all(default, r);
+            },
+            Statement::For{ref mut init, ref mut cond, ref mut step, ref mut body} => {
+                init.rewrite(r);
+                cond.rewrite(r);
+                step.rewrite(r);
+                rewrite_all(body, r);
+            },
+            Statement::While{cond: ref mut expr, ref mut body} |
+            Statement::Repeat{count: ref mut expr, ref mut body} => {
+                expr.rewrite(r);
+                rewrite_all(body, r);
+            },
+            Statement::Forever{ref mut body} => {
+                rewrite_all(body, r);
+            },
+            Statement::Switch{ref mut expr, ref mut arms, ..} => {
+                expr.rewrite(r);
+                for arm in arms {
+                    for label in &mut arm.0 {
+                        label.rewrite(r);
+                    }
+                }
+            },
+            Statement::AtChange{..} |
+            Statement::Jump{..} |
+            Statement::RepeatCheck{..} |
+            Statement::Return |
+            Statement::DisableFork |
+            Statement::Trigger{..} |
+            Statement::Release => {},
+        }
+    }
+
+    // rename every variable in a (lowered) statement
+    pub fn rename(&mut self, f: &dyn Fn(&str) -> String) {
+        self.rename_apart(f, f);
+    }
+
+    // inside a function `f` is its result but `f(n)` calls it, so the
+    // names of calls are looked up separately
+    pub fn rename_apart(&mut self, f: &dyn Fn(&str) -> String, calls: &dyn Fn(&str) -> String) {
+        if let Statement::Fork{ref mut branches, ..} = *self {
+            for branch in branches {
+                for stmt in Rc::make_mut(branch).iter_mut() {
+                    stmt.rename_apart(f, calls);
+                }
+            }
+            return;
+        }
+        self.rewrite(&Renamer { f, calls });
+        self.rename_events(f);
+    }
+
+    // the names rewrite() doesn't reach: what's waited on or triggered
+    fn rename_events(&mut self, f: &dyn Fn(&str) -> String) {
+        match *self {
+            Statement::AtChange{ref mut edges} => {
+                for edge in edges {
+                    edge.rename(f);
+                }
+            },
+            Statement::Trigger{ref mut event} => *event = f(event),
+            Statement::BlockingAfter{ref mut control, ..} |
+            Statement::NonBlockingAfter{ref mut control, ..} => control.rename_events(f),
+            _ => {},
+        }
+    }
+
+    // get_identifiers() less the variables that are only assigned to
+    pub fn get_reads(&self) -> Vec<String> {
+        match *self {
+            Statement::BlockingAssign{ref id, ref expr} |
+            Statement::NonBlockingAssign{ref id, ref expr} |
+            Statement::Hold{ref id, ref expr} => {
+                let mut vars = expr.get_identifiers();
+                vars.append( &mut id.get_index_identifiers() );
+                vars
+            },
+            Statement::NonBlockingAfter{ref id, ref expr, ref control} => {
+                let mut vars = expr.get_identifiers();
+                vars.append( &mut id.get_index_identifiers() );
+                vars.append( &mut control.get_reads() );
+                vars
+            },
+            Statement::Fork{ref branches, ..} => {
+                branches.iter().flat_map(|branch| branch.iter())
+                    .flat_map(|stmt| stmt.get_reads())
+                    .collect()
+            },
+            _ => self.get_identifiers(),
+        }
+    }
+
     pub fn get_identifiers(&self) -> Vec<String> {
         let mut vars: Vec<String> = vec![];
         match *self {
             Statement::BlockingAssign{ref id, ref expr} |
-            Statement::NonBlockingAssign{ref id, ref expr} => {
-                if let Some(var) = id.get_identifier() {
-                    vars.push(var.clone());
+            Statement::NonBlockingAssign{ref id, ref expr} |
+            Statement::Hold{ref id, ref expr} => {
+                vars.append( &mut id.get_identifiers() );
+                vars.append( &mut expr.get_identifiers() );
+            },
+            Statement::NonBlockingAfter{ref id, ref expr, ref control} => {
+                vars.append( &mut id.get_identifiers() );
+                vars.append( &mut expr.get_identifiers() );
+                vars.append( &mut control.get_identifiers() );
+            },
+            Statement::Delay{dly: ref expr} |
+            Statement::Wait{cond: ref expr} |
+            Statement::BranchIfNot{cond: ref expr, ..} |
+            Statement::RepeatStart{count: ref expr} => {
+                vars.append( &mut expr.get_identifiers() );
+            },
+            Statement::SystemTask{ref args, ..} => {
+                for arg in args {
+                    if let Argument::Expr(ref expr) = *arg {
+                        vars.append( &mut expr.get_identifiers() );
+                    }
+                }
+            },
+            Statement::AtExpr{ref events} => {
+                for event in events {
+                    vars.append( &mut event.expr.get_identifiers() );
+                }
+            },
+            Statement::Call{ref args, ..} => {
+                for arg in args {
+                    vars.append( &mut arg.get_identifiers() );
+                }
+            },
+            Statement::Fork{ref branches, ..} => {
+                for stmt in branches.iter().flat_map(|branch| branch.iter()) {
+                    vars.append( &mut stmt.get_identifiers() );
                 }
+            },
+            Statement::Switch{ref expr, ref arms, ..} => {
                 vars.append( &mut expr.get_identifiers() );
+                for (labels, _) in arms {
+                    for label in labels {
+                        vars.append( &mut label.get_identifiers() );
+                    }
+                }
             },
             _ => {}, // don't care about anything in other statement types
         }
@@ -171,11 +848,136 @@ impl Statement {
     }
 }
 
+// what "@*" in front of these statements waits on: every variable they
+// read, but not the ones they only assign to
+pub fn implicit_sensitivity(stmts: &[Statement]) -> Vec<Edge> {
+    let mut vars: Vec<String> = vec![];
+    for stmt in lower_body(stmts.to_vec()) {
+        vars.append( &mut stmt.get_reads() );
+    }
+    vars.sort();
+    vars.dedup();
+    vars.into_iter().map(Edge::Any).collect()
+}
+
+fn rewrite_all(stmts: &mut [Statement], r: &dyn Rewrite) {
+    for stmt in stmts {
+        stmt.rewrite(r);
+    }
+}
+
+// flatten a statement onto the end of `code`, jump targets are indices
+// into `code`
+fn lower(stmt: Statement, code: &mut Vec<Statement>) {
+    match stmt {
+        Statement::BlockingAfter{id, expr, control} => {
+            code.push( Statement::Hold{id, expr} );
+            code.push( *control );
+            code.push( Statement::Release );
+        },
+
+        Statement::If{cond, then_stmts, else_stmts} => {
+            let branch = code.len();
+            code.push( Statement::BranchIfNot{cond, target: 0} );
+            lower_all(then_stmts, code);
+            if else_stmts.is_empty() {
+                patch_here(code, branch);
+            } else {
+                let jump = code.len();
+                code.push( Statement::Jump{target: 0} );
+                patch_here(code, branch);
+                lower_all(else_stmts, code);
+                patch_here(code, jump);
+            }
+        },
+
+        Statement::Case{kind, expr, items, default} => {
+            let switch = code.len();
+            code.push( Statement::Jump{target: 0} ); // becomes the Switch
+            let mut arms: Vec<(Vec<Expression>, usize)> = vec![];
+            let mut jumps: Vec<usize> = vec![];
+            for item in items {
+                arms.push( (item.labels, code.len()) );
+                lower_all(item.stmts, code);
+                jumps.push(code.len());
+                code.push( Statement::Jump{target: 0} );
+            }
+            let default_target = code.len();
+            lower_all(default, code);
+            for jump in jumps {
+                patch_here(code, jump);
+            }
+            code[switch] = Statement::Switch{kind, expr, arms, default: default_target};
+        },
+
+        Statement::For{init, cond, step, body} => {
+            lower(*init, code);
+            let top = code.len();
+            code.push( Statement::BranchIfNot{cond, target: 0} );
+            lower_all(body, code);
+            lower(*step, code);
+            code.push( Statement::Jump{target: top} );
+            patch_here(code, top);
+        },
+
+        Statement::While{cond, body} => {
+            let top = code.len();
+            code.push( Statement::BranchIfNot{cond, target: 0} );
+            lower_all(body, code);
+            code.push( Statement::Jump{target: top} );
+            patch_here(code, top);
+        },
+
+        Statement::Repeat{count, body} => {
+            code.push( Statement::RepeatStart{count} );
+            let top = code.len();
+            code.push( Statement::RepeatCheck{target: 0} );
+            lower_all(body, code);
+            code.push( Statement::Jump{target: top} );
+            patch_here(code, top);
+        },
+
+        Statement::Forever{body} => {
+            let top = code.len();
+            lower_all(body, code);
+            code.push( Statement::Jump{target: top} );
+        },
+
+        _ => code.push(stmt),
+    }
+}
+
+fn lower_all(stmts: Vec<Statement>, code: &mut Vec<Statement>) {
+    for stmt in stmts {
+        lower(stmt, code);
+    }
+}
+
+// the flat code for a task or function body
+pub fn lower_body(stmts: Vec<Statement>) -> Vec<Statement> {
+    let mut code: Vec<Statement> = vec![];
+    lower_all(stmts, &mut code);
+    code
+}
+
+// point a forward jump at the end of the code so far
+fn patch_here(code: &mut [Statement], at: usize) {
+    let to = code.len();
+    match code[at] {
+        Statement::Jump{ref mut target} |
+        Statement::BranchIfNot{ref mut target, ..} |
+        Statement::RepeatCheck{ref mut target} => *target = to,
+        _ => panic!("*ERROR* can't patch a jump into: {}", code[at]),
+    }
+}
+
 // Procedure
+#[derive(Debug, Clone, Copy)]
 #[allow(dead_code)]
 pub enum ProcedureType {
     Initial,
     Always,
+    Fork,    // a thread of its own, from a fork or a "q <= @(clk) d", which runs once
 }
 
 impl fmt::Display for ProcedureType {
@@ -183,21 +985,99 @@ impl fmt::Display for ProcedureType {
         match *self {
             ProcedureType::Initial => write!(f, "INITIAL"),
             ProcedureType::Always => write!(f, "ALWAYS"),
+            ProcedureType::Fork => write!(f, "FORK"),
+        }
+    }
+}
+
+
+// how long a fork waits for the threads it starts
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum JoinKind {
+    All,  // join
+    Any,  // join_any, the first one to finish
+    None, // join_none, not at all
+}
+
+impl fmt::Display for JoinKind {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            JoinKind::All => write!(f, "join"),
+            JoinKind::Any => write!(f, "join_any"),
+            JoinKind::None => write!(f, "join_none"),
         }
     }
 }
 
+// a task or function call in progress
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub name    : String,               // the task or function, for %m
+    pub stmts   : Rc<Vec<Statement>>,
+    pub counter : usize,
+    pub repeats : Vec<u64>,
+    pub locals  : HashMap<String, Value>,  // an automatic one's variables
+    pub outputs : Vec<(String, Operand)>,  // copied to the caller on return
+}
+
+impl Frame {
 
+    pub fn new(name: &str, stmts: Rc<Vec<Statement>>) -> Frame {
+        Frame {
+            name: name.to_string(),
+            stmts,
+            counter: 0,
+            repeats: vec![],
+            locals: HashMap::new(),
+            outputs: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Procedure {
     pub kind    : ProcedureType,
     pub counter : usize,
     pub stmts   : Vec<Statement>,
+    pub repeats : Vec<u64>,   // counters for the repeat loops we're in
+    pub scope   : String,     // the module it's in, for %m
+    pub frames  : Vec<Frame>, // the tasks it's inside, innermost last
+    pub parent  : Option<ProcId>, // who forked it
+    pub children: Vec<ProcId>,    // the threads it forked that are still going
+    pub joining : Option<(JoinKind, Vec<ProcId>)>, // blocked until these finish
+    pub finished: bool,           // a forked thread that's done, or disabled
+    pub held    : Option<(Operand, Value)>, // "a = #3 b" waiting to be assigned
 }
 
 impl Procedure {
 
+    pub fn new(kind: ProcedureType) -> Procedure {
+        Procedure {
+            kind,
+            counter: 0,
+            stmts: vec![],
+            repeats: vec![],
+            scope: String::new(),
+            frames: vec![],
+            parent: None,
+            children: vec![],
+            joining: None,
+            finished: false,
+            held: None,
+        }
+    }
+
     pub fn next_stmt(&mut self) -> Option<Statement> {
         let mut stmt : Option<Statement> = None;
+
+        // inside a task, run that until it's done
+        if let Some(frame) = self.frames.last_mut() {
+            if frame.counter < frame.stmts.len() {
+                frame.counter += 1;
+                return Some(frame.stmts[frame.counter - 1].clone());
+            }
+            return Some(Statement::Return);
+        }
         
         // always can go again...
         if let ProcedureType::Always = self.kind {
@@ -213,8 +1093,59 @@ impl Procedure {
         stmt
     }
 
+    // where a jump goes, in whatever's running
+    pub fn jump(&mut self, target: usize) {
+        match self.frames.last_mut() {
+            Some(frame) => frame.counter = target,
+            None => self.counter = target,
+        }
+    }
+
+    // go back to the statement just fetched, to run it again when woken
+    pub fn retry(&mut self) {
+        match self.frames.last_mut() {
+            Some(frame) => frame.counter -= 1,
+            None => self.counter -= 1,
+        }
+    }
+
+    pub fn repeats(&mut self) -> &mut Vec<u64> {
+        match self.frames.last_mut() {
+            Some(frame) => &mut frame.repeats,
+            None => &mut self.repeats,
+        }
+    }
+
+    // what %m prints: the innermost task, or the module
+    pub fn current_scope(&self) -> &str {
+        match self.frames.last() {
+            Some(frame) => &frame.name,
+            None => &self.scope,
+        }
+    }
+
+    // an automatic variable of the innermost task, if it has one
+    pub fn local(&self, var: &str) -> Option<&Value> {
+        self.frames.last().and_then(|frame| frame.locals.get(var))
+    }
+
+    pub fn local_mut(&mut self, var: &str) -> Option<&mut Value> {
+        self.frames.last_mut().and_then(|frame| frame.locals.get_mut(var))
+    }
+
+    pub fn rewrite(&mut self, r: &dyn Rewrite) {
+        rewrite_all(&mut self.stmts, r);
+    }
+
+    pub fn rename(&mut self, f: &dyn Fn(&str) -> String) {
+        for stmt in &mut self.stmts {
+            stmt.rename(f);
+        }
+    }
+
+    // structured statements get flattened on the way in
     pub fn push(&mut self, stmt: Statement ) {
-        self.stmts.push(stmt);
+        lower(stmt, &mut self.stmts);
     }
 
     pub fn get_identifiers(&self) -> Vec<String> {
@@ -234,8 +1165,63 @@ impl Procedure {
     pub fn show(&self) {
         println!("{}", self.kind);
         for i in 0..self.stmts.len() {
-            println!(" {}", self.stmts[i]);
+            println!(" {:3}: {}", i, self.stmts[i]);
+        }
+    }
+}
+
+
+// Continuous assignment: "assign #dly y = a & b;"
+//
+// Not a procedure - the engine re-evaluates it whenever one of the
+// variables on the right hand side changes. With a delay, the update
+// is inertial: a change that comes along before the last one has
+// landed replaces it.
+#[derive(Debug, Clone)]
+pub struct ContinuousAssign {
+    pub id: Operand,
+    pub expr: Expression,
+    pub dly: Option<Expression>,
+    pub strength: DriveStrength, // "assign (weak0, weak1) y = a;"
+}
+
+impl fmt::Display for ContinuousAssign {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        write!(f, "assign ")?;
+        if self.strength != DriveStrength::default() {
+            write!(f, "{} ", self.strength)?;
+        }
+        if let Some(ref dly) = self.dly {
+            write!(f, "#{} ", dly.nested())?;
         }
+        write!(f, "{} = {}", self.id, self.expr)
     }
 }
 
+impl ContinuousAssign {
+
+    pub fn new(id: Operand, expr: Expression, dly: Option<Expression>) -> ContinuousAssign {
+        ContinuousAssign { id, expr, dly, strength: DriveStrength::default() }
+    }
+
+    pub fn rewrite(&mut self, r: &dyn Rewrite) {
+        self.id.rewrite(r);
+        self.expr.rewrite(r);
+        if let Some(ref mut dly) = self.dly {
+            dly.rewrite(r);
+        }
+    }
+
+    pub fn rename(&mut self, f: &dyn Fn(&str) -> String) {
+        self.rewrite(&Renamer { f, calls: f });
+    }
+
+    pub fn get_identifiers(&self) -> Vec<String> {
+        let mut vars = self.id.get_identifiers();
+        vars.append( &mut self.expr.get_identifiers() );
+        if let Some(ref dly) = self.dly {
+            vars.append( &mut dly.get_identifiers() );
+        }
+        vars
+    }
+}
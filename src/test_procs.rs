@@ -3,49 +3,41 @@ use procedure::*;
 
 #[allow(dead_code)]
 pub fn build_delay(dly: usize) -> Procedure {
-    let mut p = Procedure {
-            kind: ProcedureType::Initial,
-            counter: 0,
-            stmts: vec![],
-    };
-    p.push( Statement::Delay{dly: dly} );
+    let mut p = Procedure::new(ProcedureType::Initial);
+    p.push( Statement::Delay{dly: Expression::number(dly)} );
     p
 }
 
 // build up a clock
 #[allow(dead_code)]
 pub fn build_clock(half_period: usize, cycles: usize) -> Procedure {
-    let mut p = Procedure {
-            kind: ProcedureType::Initial,
-            counter: 0,
-            stmts: vec![],
-    };
+    let mut p = Procedure::new(ProcedureType::Initial);
 
     p.push( Statement::BlockingAssign{
         id: Operand::Identifier("clk".to_string()),
         expr: Expression::Const(
-            Operand::Literal(0),
+            Operand::Literal(Value::from(0)),
             )
         });
 
     for _ in 0..cycles {
-        p.push( Statement::Delay{dly: half_period} );
+        p.push( Statement::Delay{dly: Expression::number(half_period)} );
 
         // rise
         p.push( Statement::BlockingAssign{
             id: Operand::Identifier("clk".to_string()),
             expr: Expression::Const(
-                Operand::Literal(1),
+                Operand::Literal(Value::from(1)),
                 )
             });
 
-        p.push( Statement::Delay{dly: half_period} );
+        p.push( Statement::Delay{dly: Expression::number(half_period)} );
 
         // fall
         p.push( Statement::BlockingAssign{
             id: Operand::Identifier("clk".to_string()),
             expr: Expression::Const(
-                Operand::Literal(0),
+                Operand::Literal(Value::from(0)),
                 )
             });
     }
@@ -56,11 +48,7 @@ pub fn build_clock(half_period: usize, cycles: usize) -> Procedure {
 // build up a flip-flop
 #[allow(dead_code)]
 pub fn build_flop(ff_in: &str, ff_out: &str) -> Procedure {
-    let mut p = Procedure {
-            kind: ProcedureType::Always,
-            counter: 0,
-            stmts: vec![],
-    };
+    let mut p = Procedure::new(ProcedureType::Always);
 
     p.push( Statement::AtChange{
         edges: vec![
@@ -78,14 +66,22 @@ pub fn build_flop(ff_in: &str, ff_out: &str) -> Procedure {
     p
 }
 
+// build up a reset: give a reg its starting value at time 0, regs are
+// x until something's written to them
+#[allow(dead_code)]
+pub fn build_reset(reg: &str, value: usize) -> Procedure {
+    let mut p = Procedure::new(ProcedureType::Initial);
+    p.push( Statement::BlockingAssign{
+        id: Operand::Identifier(reg.to_string()),
+        expr: Expression::Const(Operand::Literal(Value::from(value))),
+        });
+    p
+}
+
 // build up a flip-flop with a reset - need 'if' statement
 #[allow(dead_code)]
 pub fn build_flop_with_resetb(ff_in: &str, ff_out: &str) -> Procedure {
-    let mut p = Procedure {
-            kind: ProcedureType::Always,
-            counter: 0,
-            stmts: vec![],
-    };
+    let mut p = Procedure::new(ProcedureType::Always);
 
     p.push( Statement::AtChange{
         edges: vec![
@@ -114,87 +110,52 @@ pub fn build_bitstream(wire: &str,
                        offset:usize
                       ) -> Procedure {
 
-    let mut p = Procedure {
-            kind: ProcedureType::Initial,
-            counter: 0,
-            stmts: vec![],
-    };
+    let mut p = Procedure::new(ProcedureType::Initial);
 
     assert!(len <= 32);
     assert!(period <= 50);
 
     if offset > 0 {
-        p.push( Statement::Delay{dly: offset} );
+        p.push( Statement::Delay{dly: Expression::number(offset)} );
     }
 
     for i in 0..len {
         let data = ( data >> i ) & 1;
         p.push( Statement::BlockingAssign{
             id: Operand::Identifier(wire.to_string()),
-            expr: Expression::Const(Operand::Literal(data)),
+            expr: Expression::Const(Operand::Literal(Value::from(data))),
             });
 
-        p.push( Statement::Delay{dly: period} );
+        p.push( Statement::Delay{dly: Expression::number(period)} );
     }
 
     p
 }
 
-// build up a bitstream
+// build up an and gate
 #[allow(dead_code)]
-pub fn build_and(y: &str, a: &str, b: &str) -> Procedure {
-
-    let mut p = Procedure {
-            kind: ProcedureType::Always,
-            counter: 0,
-            stmts: vec![],
-    };
-
-    p.push( Statement::AtChange{
-        edges: vec![
-            Edge::Any(a.to_string()),
-            Edge::Any(b.to_string()),
-        ],  
-        });
-
-
-    p.push( Statement::BlockingAssign{
-        id: Operand::Identifier(y.to_string()),
-        expr: Expression::And(
-            Operand::Identifier(a.to_string()),
-            Operand::Identifier(b.to_string()),
-            )
-        });
-
-    p
-
+pub fn build_and(y: &str, a: &str, b: &str) -> ContinuousAssign {
+    ContinuousAssign::new(
+        Operand::Identifier(y.to_string()),
+        Expression::binary(
+            BinaryOp::And,
+            Expression::identifier(a),
+            Expression::identifier(b),
+            ),
+        None,
+        )
 }
 
 // build up a inverter
 #[allow(dead_code)]
-pub fn build_inverter(y: &str, a: &str) -> Procedure {
-
-    let mut p = Procedure {
-            kind: ProcedureType::Always,
-            counter: 0,
-            stmts: vec![],
-    };
-
-    p.push( Statement::AtChange{
-        edges: vec![
-            Edge::Any(a.to_string()),
-        ],  
-        });
-
-
-    p.push( Statement::BlockingAssign{
-        id: Operand::Identifier(y.to_string()),
-        expr: Expression::Not(
-            Operand::Identifier(a.to_string()),
-            )
-        });
-
-    p
-
+pub fn build_inverter(y: &str, a: &str) -> ContinuousAssign {
+    ContinuousAssign::new(
+        Operand::Identifier(y.to_string()),
+        Expression::unary(
+            UnaryOp::Not,
+            Expression::identifier(a),
+            ),
+        None,
+        )
 }
 
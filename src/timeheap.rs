@@ -1,72 +1,87 @@
-///
-/// Min-heap pointing to the next procedure to run at the closest time
-///
+//!
+//! Min-heap pointing to the next thing to happen at the closest time
+//!
+//! Anything can be scheduled: procedures waiting out a delay, or the
+//! delayed update of a continuous assignment. Things due at the same
+//! time come back in the order they were pushed.
+//!
 
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
 
-use procedure::{Time, ProcId};
+use procedure::Time;
 
-#[derive(Eq, PartialEq)]
-struct Entry {
+struct Entry<T> {
     time: Time,
-    proc_id: ProcId,
+    seq: usize, // keeps the heap stable for equal times
+    item: T,
 }
 
-impl Ord for Entry {
-    fn cmp(&self, other: &Entry) -> Ordering {
+impl<T> Ord for Entry<T> {
+    fn cmp(&self, other: &Entry<T>) -> Ordering {
         // note we flip the ordering here
         other.time.cmp(&self.time)
+            .then_with(|| other.seq.cmp(&self.seq))
     }
 }
 
-impl PartialOrd for Entry {
-    fn partial_cmp(&self, other: &Entry) -> Option<Ordering> {
+impl<T> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Entry<T>) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
+impl<T> PartialEq for Entry<T> {
+    fn eq(&self, other: &Entry<T>) -> bool {
+        self.time == other.time && self.seq == other.seq
+    }
+}
+
+impl<T> Eq for Entry<T> {}
 
-pub struct TimeHeap {
-    heap: BinaryHeap<Entry>,
+
+pub struct TimeHeap<T> {
+    heap: BinaryHeap<Entry<T>>,
+    seq: usize,
 }
 
-impl TimeHeap {
+impl<T> TimeHeap<T> {
 
-    pub fn new() -> TimeHeap {
+    pub fn new() -> TimeHeap<T> {
         TimeHeap{
             heap: BinaryHeap::new(),
+            seq: 0,
         }
     }
 
-    pub fn push(&mut self, proc_id: ProcId, time: Time) {
-        self.heap.push( Entry {time: time, proc_id: proc_id } );
+    pub fn push(&mut self, item: T, time: Time) {
+        self.seq += 1;
+        self.heap.push( Entry { time, seq: self.seq, item } );
     }
 
 
-    // activate all procedures which share the next lowest time
-    pub fn activate(&mut self) -> (Option<Time>, Vec<ProcId>) {
+    // activate everything which shares the next lowest time
+    pub fn activate(&mut self) -> (Option<Time>, Vec<T>) {
         let mut time: Option<Time> = None;
-        let mut proc_ids: Vec<ProcId> = vec![];
+        let mut items: Vec<T> = vec![];
 
         // pop the first entry
         if let Some(entry) = self.heap.pop() { 
             time = Some(entry.time);
-            proc_ids.push(entry.proc_id);
+            items.push(entry.item);
         }
 
         // now get any others that activate at the same time
         while let Some(entry) = self.heap.pop() {
             if entry.time == time.unwrap() {
-                proc_ids.push(entry.proc_id);
+                items.push(entry.item);
             } else {
                 self.heap.push(entry);
                 break;
             }
         }
 
-        (time, proc_ids)
+        (time, items)
     }
 
 }
-
@@ -0,0 +1,294 @@
+//! User-defined primitives
+//!
+//! The table of a `primitive ... endprimitive`, one row per line, and
+//! looking an output up in it. A combinational table gives the output
+//! for the levels on the inputs; a sequential one also has the current
+//! state, and rows that only match when an input goes through an edge
+//! such as `(01)` or `r`.
+
+use std::fmt;
+
+use value::Logic;
+
+// the levels a table symbol matches, as a set of 0, 1 and x
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Levels(u8);
+
+const ZERO: u8 = 1;
+const ONE: u8 = 2;
+const X: u8 = 4;
+
+impl Levels {
+
+    fn from_symbol(c: char) -> Option<Levels> {
+        match c {
+            '0' => Some(Levels(ZERO)),
+            '1' => Some(Levels(ONE)),
+            'x' | 'X' => Some(Levels(X)),
+            'b' | 'B' => Some(Levels(ZERO | ONE)),
+            '?' => Some(Levels(ZERO | ONE | X)),
+            _ => None,
+        }
+    }
+
+    // z on an input is as good as x
+    pub fn matches(self, value: Logic) -> bool {
+        let bit = match value {
+            Logic::Zero => ZERO,
+            Logic::One => ONE,
+            Logic::X | Logic::Z => X,
+        };
+        self.0 & bit != 0
+    }
+}
+
+impl fmt::Display for Levels {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        let c = match self.0 {
+            ZERO => '0',
+            ONE => '1',
+            X => 'x',
+            b if b == ZERO | ONE => 'b',
+            _ => '?',
+        };
+        write!(f, "{}", c)
+    }
+}
+
+// what goes in an input column
+#[derive(PartialEq, Debug, Clone)]
+pub enum Entry {
+    Level(Levels),
+    Edge(Vec<(Levels, Levels)>), // any of these from -> to
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Entry::Level(levels) => write!(f, "{}", levels),
+            Entry::Edge(ref edges) => {
+                for &(from, to) in edges {
+                    write!(f, "({}{})", from, to)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl Entry {
+
+    fn is_edge(&self) -> bool {
+        match *self {
+            Entry::Edge(_) => true,
+            Entry::Level(_) => false,
+        }
+    }
+
+    fn matches(&self, old: Logic, new: Logic) -> bool {
+        match *self {
+            Entry::Level(levels) => levels.matches(new),
+            Entry::Edge(ref edges) => {
+                edges.iter().any(|&(from, to)| from.matches(old) && to.matches(new))
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Row {
+    pub inputs: Vec<Entry>,
+    pub state: Option<Levels>, // sequential tables only
+    pub output: Option<Logic>, // None for -, no change
+}
+
+impl fmt::Display for Row {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        let inputs: Vec<String> = self.inputs.iter().map(|e| format!("{}", e)).collect();
+        write!(f, "{} : ", inputs.join(" "))?;
+        if let Some(state) = self.state {
+            write!(f, "{} : ", state)?;
+        }
+        match self.output {
+            Some(out) => write!(f, "{};", out),
+            None => write!(f, "-;"),
+        }
+    }
+}
+
+impl Row {
+
+    // "01?:0:1" or "(01)0:?:-", with the whitespace already gone
+    pub fn parse(text: &str, inputs: usize, sequential: bool) -> Result<Row, String> {
+        let fields: Vec<&str> = text.split(':').collect();
+        let (ins, state, out) = match (fields.len(), sequential) {
+            (2, false) => (fields[0], None, fields[1]),
+            (3, true) => (fields[0], Some(fields[1]), fields[2]),
+            (_, false) => return Err("a combinational table row is inputs : output".to_string()),
+            (_, true) => return Err("a sequential table row is inputs : state : output".to_string()),
+        };
+
+        let mut row = Row { inputs: vec![], state: None, output: None };
+        let mut chars = ins.chars();
+        while let Some(c) = chars.next() {
+            let entry = if c == '(' {
+                let from = chars.next().and_then(Levels::from_symbol);
+                let to = chars.next().and_then(Levels::from_symbol);
+                match (from, to, chars.next()) {
+                    (Some(from), Some(to), Some(')')) => Entry::Edge(vec![(from, to)]),
+                    _ => return Err(format!("bad edge in table row '{}'", text)),
+                }
+            } else if let Some(levels) = Levels::from_symbol(c) {
+                Entry::Level(levels)
+            } else if let Some(edges) = edge_symbol(c) {
+                Entry::Edge(edges)
+            } else {
+                return Err(format!("'{}' can't be an input in a table", c));
+            };
+            row.inputs.push(entry);
+        }
+        if row.inputs.len() != inputs {
+            return Err(format!("table row '{}' has {} inputs, the primitive has {}",
+                text, row.inputs.len(), inputs));
+        }
+        match row.inputs.iter().filter(|e| e.is_edge()).count() {
+            0 => {},
+            1 if sequential => {},
+            1 => return Err("a combinational table can't have edges".to_string()),
+            _ => return Err(format!("table row '{}' has more than one edge", text)),
+        }
+
+        if let Some(state) = state {
+            let mut chars = state.chars();
+            row.state = match (chars.next().and_then(Levels::from_symbol), chars.next()) {
+                (Some(levels), None) => Some(levels),
+                _ => return Err(format!("bad state '{}' in table row", state)),
+            };
+        }
+        row.output = match out {
+            "0" => Some(Logic::Zero),
+            "1" => Some(Logic::One),
+            "x" | "X" => Some(Logic::X),
+            "-" if sequential => None,
+            _ => return Err(format!("bad output '{}' in table row", out)),
+        };
+        Ok(row)
+    }
+
+    fn has_edge(&self) -> bool {
+        self.inputs.iter().any(|e| e.is_edge())
+    }
+}
+
+// the shorthands for edges
+fn edge_symbol(c: char) -> Option<Vec<(Levels, Levels)>> {
+    let (zero, one, x) = (Levels(ZERO), Levels(ONE), Levels(X));
+    match c {
+        'r' | 'R' => Some(vec![(zero, one)]),
+        'f' | 'F' => Some(vec![(one, zero)]),
+        'p' | 'P' => Some(vec![(zero, one), (zero, x), (x, one)]),
+        'n' | 'N' => Some(vec![(one, zero), (one, x), (x, zero)]),
+        '*' => Some(vec![(Levels(ZERO | ONE | X), Levels(ZERO | ONE | X))]),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub sequential: bool,
+    pub rows: Vec<Row>,
+}
+
+impl Table {
+
+    // the output after input `changed` goes from `old` to what's now in
+    // `inputs`, or after nothing in particular when it's None. Rows on
+    // levels alone take priority over rows on edges, and nothing
+    // matching at all gives x
+    pub fn evaluate(&self, inputs: &[Logic], changed: Option<(usize, Logic)>, state: Logic)
+        -> Logic
+    {
+        let matches = |row: &Row| {
+            row.state.is_none_or(|s| s.matches(state)) &&
+            row.inputs.iter().enumerate().all(|(i, entry)| match (entry, changed) {
+                (&Entry::Level(levels), _) => levels.matches(inputs[i]),
+                // an edge is only ever on the input that moved
+                (_, Some((j, old))) if i == j => entry.matches(old, inputs[i]),
+                _ => false,
+            })
+        };
+        let level = self.rows.iter().find(|row| !row.has_edge() && matches(row));
+        let row = match level {
+            Some(row) => Some(row),
+            None if changed.is_some() => self.rows.iter().find(|row| row.has_edge() && matches(row)),
+            None => None,
+        };
+        match row {
+            Some(row) => row.output.unwrap_or(state),
+            None => Logic::X,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use value::Logic::{One, X, Z, Zero};
+
+    fn table(rows: &[&str], inputs: usize, sequential: bool) -> Table {
+        let rows = rows.iter().map(|r| Row::parse(r, inputs, sequential).unwrap()).collect();
+        Table { sequential, rows }
+    }
+
+    #[test]
+    fn combinational_lookup() {
+        // a mux: sel, a, b
+        let mux = table(&["0?0:0", "0?1:1", "10?:0", "11?:1", "?00:0", "?11:1"], 3, false);
+        assert_eq!(mux.evaluate(&[Zero, One, Zero], None, X), Zero);
+        assert_eq!(mux.evaluate(&[One, One, Zero], None, X), One);
+        assert_eq!(mux.evaluate(&[X, One, One], None, X), One);
+        assert_eq!(mux.evaluate(&[X, One, Zero], None, X), X);
+        // z on an input is taken as x
+        assert_eq!(mux.evaluate(&[Z, Zero, Zero], None, X), Zero);
+        assert_eq!(mux.evaluate(&[Z, Zero, One], None, X), X);
+    }
+
+    #[test]
+    fn level_rows_before_edge_rows() {
+        // a flop on clk and d with an active high reset
+        let flop = table(&["r0?:?:0", "r1?:?:1", "??1:?:0", "n??:?:-", "?*0:?:-"], 3, true);
+        assert_eq!(flop.evaluate(&[One, One, Zero], Some((0, Zero)), Zero), One);
+        assert_eq!(flop.evaluate(&[One, One, One], Some((0, Zero)), Zero), Zero);
+        assert_eq!(flop.evaluate(&[Zero, One, Zero], Some((0, One)), One), One);
+        assert_eq!(flop.evaluate(&[One, Zero, Zero], Some((1, One)), One), One);
+        // no edge row for 0 -> x on the clock, so x
+        assert_eq!(flop.evaluate(&[X, One, Zero], Some((0, Zero)), Zero), X);
+        // with no change to go on only the level rows count
+        assert_eq!(flop.evaluate(&[One, One, Zero], None, Zero), X);
+    }
+
+    #[test]
+    fn edge_shorthands() {
+        let p = table(&["p:?:1", "n:?:0"], 1, true);
+        assert_eq!(p.evaluate(&[One], Some((0, Zero)), Zero), One);
+        assert_eq!(p.evaluate(&[X], Some((0, Zero)), Zero), One);
+        assert_eq!(p.evaluate(&[One], Some((0, X)), Zero), One);
+        assert_eq!(p.evaluate(&[X], Some((0, One)), One), Zero);
+    }
+
+    #[test]
+    fn bad_rows() {
+        let error = |text: &str, inputs: usize, sequential: bool| {
+            Row::parse(text, inputs, sequential).unwrap_err()
+        };
+        assert_eq!(error("01:0:1", 2, false), "a combinational table row is inputs : output");
+        assert_eq!(error("01:1", 2, true), "a sequential table row is inputs : state : output");
+        assert_eq!(error("r1:0", 2, false), "a combinational table can't have edges");
+        assert_eq!(error("rr:?:1", 2, true), "table row 'rr:?:1' has more than one edge");
+        assert_eq!(error("0:1", 2, false), "table row '0:1' has 1 inputs, the primitive has 2");
+        assert_eq!(error("0:-", 1, false), "bad output '-' in table row");
+        assert_eq!(error("(0q):?:1", 1, true), "bad edge in table row '(0q):?:1'");
+        assert_eq!(error("z:1", 1, false), "'z' can't be an input in a table");
+    }
+}
@@ -0,0 +1,787 @@
+//! Four-state logic values
+//!
+//! Each bit is one of 0, 1, x (unknown) or z (high impedance). Bits are
+//! packed into two planes, the same way the Verilog PLI does it:
+//!
+//!   bit | aval bval
+//!   ----+----------
+//!    0  |   0    0
+//!    1  |   1    0
+//!    z  |   0    1
+//!    x  |   1    1
+//!
+//! A value also knows whether its bits are unsigned, two's complement
+//! or, 64 of them, the bits of a real number.
+
+use std::fmt;
+
+pub const MAX_WIDTH: usize = 64;
+
+// width of an unsized literal like "12" or 'hff
+pub const INTEGER_WIDTH: usize = 32;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Logic {
+    Zero,
+    One,
+    X,
+    Z,
+}
+
+impl fmt::Display for Logic {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Logic::Zero => write!(f, "0"),
+            Logic::One => write!(f, "1"),
+            Logic::X => write!(f, "x"),
+            Logic::Z => write!(f, "z"),
+        }
+    }
+}
+
+impl Logic {
+    fn planes(self) -> (u64, u64) {
+        match self {
+            Logic::Zero => (0, 0),
+            Logic::One => (1, 0),
+            Logic::Z => (0, 1),
+            Logic::X => (1, 1),
+        }
+    }
+
+    fn from_planes(a: u64, b: u64) -> Logic {
+        match (a & 1, b & 1) {
+            (0, 0) => Logic::Zero,
+            (1, 0) => Logic::One,
+            (0, _) => Logic::Z,
+            _ => Logic::X,
+        }
+    }
+}
+
+// what a value's bits mean, and the type an expression is worked out in
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Type {
+    Unsigned,
+    Signed,
+    Real,
+}
+
+#[derive(Eq, Debug, Clone)]
+pub struct Value {
+    aval: u64,
+    bval: u64,
+    width: usize,
+    ty: Type,
+}
+
+// the same bits are the same value, whatever they're taken to mean
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.aval == other.aval && self.bval == other.bval && self.width == other.width
+    }
+}
+
+fn mask(width: usize) -> u64 {
+    if width >= 64 {
+        !0
+    } else {
+        (1 << width) - 1
+    }
+}
+
+impl From<usize> for Value {
+    // an unsized integer literal
+    fn from(n: usize) -> Value {
+        Value::new(n as u64, INTEGER_WIDTH)
+    }
+}
+
+impl Value {
+
+    pub fn new(n: u64, width: usize) -> Value {
+        let width = width.clamp(1, MAX_WIDTH);
+        Value { aval: n & mask(width), bval: 0, width, ty: Type::Unsigned }
+    }
+
+    pub fn x(width: usize) -> Value {
+        let width = width.clamp(1, MAX_WIDTH);
+        Value { aval: mask(width), bval: mask(width), width, ty: Type::Unsigned }
+    }
+
+    pub fn z(width: usize) -> Value {
+        let width = width.clamp(1, MAX_WIDTH);
+        Value { aval: 0, bval: mask(width), width, ty: Type::Unsigned }
+    }
+
+    pub fn real(n: f64) -> Value {
+        Value { aval: n.to_bits(), bval: 0, width: 64, ty: Type::Real }
+    }
+
+    // a real rounded to the nearest integer, half away from zero
+    pub fn from_real(n: f64, width: usize) -> Value {
+        if n.is_finite() {
+            Value::new(n.round() as i64 as u64, width)
+        } else {
+            Value::x(width)
+        }
+    }
+
+    pub fn ty(&self) -> Type {
+        self.ty
+    }
+
+    pub fn is_signed(&self) -> bool {
+        self.ty == Type::Signed
+    }
+
+    pub fn is_real(&self) -> bool {
+        self.ty == Type::Real
+    }
+
+    // the same bits, taken to mean something else
+    pub fn with_type(mut self, ty: Type) -> Value {
+        self.ty = ty;
+        self
+    }
+
+    pub fn bit(&self, i: usize) -> Logic {
+        if i >= self.width {
+            return Logic::Zero;
+        }
+        Logic::from_planes(self.aval >> i, self.bval >> i)
+    }
+
+    pub fn set_bit(&mut self, i: usize, bit: Logic) {
+        if i >= self.width {
+            return;
+        }
+        let (a, b) = bit.planes();
+        self.aval = (self.aval & !(1 << i)) | (a << i);
+        self.bval = (self.bval & !(1 << i)) | (b << i);
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    // the lowest and highest bits where two values differ, if they do
+    pub fn changed(&self, other: &Value) -> Option<(usize, usize)> {
+        let diff = ((self.aval ^ other.aval) | (self.bval ^ other.bval))
+            & mask(self.width.max(other.width));
+        if diff == 0 {
+            None
+        } else {
+            Some((diff.trailing_zeros() as usize, 63 - diff.leading_zeros() as usize))
+        }
+    }
+
+    pub fn has_xz(&self) -> bool {
+        self.bval != 0
+    }
+
+    // zero-extend or truncate
+    pub fn resize(&self, width: usize) -> Value {
+        let width = width.clamp(1, MAX_WIDTH);
+        Value {
+            aval: self.aval & mask(width),
+            bval: self.bval & mask(width),
+            width,
+            ty: self.ty,
+        }
+    }
+
+    // sign-extend a signed value, zero-extend an unsigned one
+    pub fn extend(&self, width: usize) -> Value {
+        let mut v = self.resize(width);
+        if self.is_signed() {
+            let top = self.bit(self.width - 1);
+            for i in self.width..v.width {
+                v.set_bit(i, top);
+            }
+        }
+        v
+    }
+
+    // bits [offset + width - 1 : offset], x where that runs off the end
+    pub fn slice(&self, offset: usize, width: usize) -> Value {
+        let mut v = Value::new(0, width);
+        for i in 0..v.width {
+            if offset + i < self.width {
+                v.set_bit(i, self.bit(offset + i));
+            } else {
+                v.set_bit(i, Logic::X);
+            }
+        }
+        v
+    }
+
+    // overwrite bits starting at offset, dropping anything off the end
+    pub fn set_slice(&mut self, offset: usize, value: &Value) {
+        for i in 0..value.width {
+            self.set_bit(offset + i, value.bit(i));
+        }
+    }
+
+    // {self, low}
+    pub fn concat(&self, low: &Value) -> Value {
+        let mut v = low.resize(self.width + low.width);
+        v.set_slice(low.width, self);
+        v
+    }
+
+    // a Verilog number: size, base and the digits after the tick.
+    // Digits may include x, z and ?, which fill 1, 3 or 4 bits at a
+    // time depending on the base.
+    pub fn parse(size: Option<usize>, base: char, digits: &str) -> Option<Value> {
+        let width = size.unwrap_or(INTEGER_WIDTH);
+        if width > MAX_WIDTH {
+            return None;
+        }
+
+        if base == 'd' {
+            if digits == "x" || digits == "z" || digits == "?" {
+                let v = if digits == "x" { Value::x(width) } else { Value::z(width) };
+                return Some(v);
+            }
+            return match digits.parse::<u64>() {
+                Ok(n) => Some(Value::new(n, width)),
+                Err(_) => None,
+            };
+        }
+
+        let bits_per_digit = match base {
+            'b' => 1,
+            'o' => 3,
+            'h' => 4,
+            _ => return None,
+        };
+        let mut v = Value::new(0, width);
+        let mut pos = 0;
+        let mut top = Logic::Zero;
+        for c in digits.chars().rev() {
+            let (a, b) = match c {
+                'x' => (mask(bits_per_digit), mask(bits_per_digit)),
+                'z' | '?' => (0, mask(bits_per_digit)),
+                _ => match c.to_digit(1 << bits_per_digit) {
+                    Some(d) => (d as u64, 0),
+                    None => return None,
+                },
+            };
+            for i in 0..bits_per_digit {
+                let bit = Logic::from_planes(a >> i, b >> i);
+                v.set_bit(pos, bit);
+                top = bit;
+                pos += 1;
+            }
+        }
+        // a leading x or z fills the rest of the number
+        if top == Logic::X || top == Logic::Z {
+            while pos < width {
+                v.set_bit(pos, top);
+                pos += 1;
+            }
+        }
+        Some(v)
+    }
+
+    //
+    // Operators
+    //
+    // Operands are zero-extended to the wider of the two first
+
+    pub fn not(&self) -> Value {
+        // ~0 = 1, ~1 = 0, ~x = ~z = x
+        Value {
+            aval: (!self.aval | self.bval) & mask(self.width),
+            bval: self.bval,
+            width: self.width,
+            ty: self.ty,
+        }
+    }
+
+    pub fn and(&self, other: &Value) -> Value {
+        let width = self.width.max(other.width);
+        let (a, b) = (self.resize(width), other.resize(width));
+        // a known 0 on either side wins, otherwise any x/z gives x
+        let zero_a = !a.aval & !a.bval;
+        let zero_b = !b.aval & !b.bval;
+        let one = a.aval & !a.bval & b.aval & !b.bval;
+        let zero = zero_a | zero_b;
+        let unknown = !(zero | one);
+        Value {
+            aval: (one | unknown) & mask(width),
+            bval: unknown & mask(width),
+            width,
+            ty: Type::Unsigned,
+        }
+    }
+
+    pub fn or(&self, other: &Value) -> Value {
+        let width = self.width.max(other.width);
+        let (a, b) = (self.resize(width), other.resize(width));
+        // a known 1 on either side wins, otherwise any x/z gives x
+        let one_a = a.aval & !a.bval;
+        let one_b = b.aval & !b.bval;
+        let zero = !a.aval & !a.bval & !b.aval & !b.bval;
+        let one = one_a | one_b;
+        let unknown = !(zero | one);
+        Value {
+            aval: (one | unknown) & mask(width),
+            bval: unknown & mask(width),
+            width,
+            ty: Type::Unsigned,
+        }
+    }
+
+    pub fn xor(&self, other: &Value) -> Value {
+        let width = self.width.max(other.width);
+        let (a, b) = (self.resize(width), other.resize(width));
+        // any x/z bit gives x
+        let unknown = a.bval | b.bval;
+        Value {
+            aval: ((a.aval ^ b.aval) | unknown) & mask(width),
+            bval: unknown & mask(width),
+            width,
+            ty: Type::Unsigned,
+        }
+    }
+
+    pub fn xnor(&self, other: &Value) -> Value {
+        self.xor(other).not()
+    }
+
+    //
+    // Arithmetic: any x or z bit in an operand makes the whole result x.
+    // Division, remainders, powers and comparisons are signed when both
+    // operands are
+    //
+    pub fn add(&self, other: &Value) -> Value {
+        self.arith(other, |a, b| Some(a.wrapping_add(b)))
+    }
+
+    pub fn sub(&self, other: &Value) -> Value {
+        self.arith(other, |a, b| Some(a.wrapping_sub(b)))
+    }
+
+    pub fn mul(&self, other: &Value) -> Value {
+        self.arith(other, |a, b| Some(a.wrapping_mul(b)))
+    }
+
+    pub fn div(&self, other: &Value) -> Value {
+        if self.is_signed() && other.is_signed() {
+            return self.signed_arith(other, |a, b| if b == 0 { None } else { Some(a.wrapping_div(b)) });
+        }
+        self.arith(other, |a, b| a.checked_div(b))
+    }
+
+    pub fn rem(&self, other: &Value) -> Value {
+        if self.is_signed() && other.is_signed() {
+            return self.signed_arith(other, |a, b| if b == 0 { None } else { Some(a.wrapping_rem(b)) });
+        }
+        self.arith(other, |a, b| a.checked_rem(b))
+    }
+
+    // a negative power of anything but 1 or -1 is a fraction, so 0,
+    // except of 0, which is x
+    pub fn pow(&self, other: &Value) -> Value {
+        if self.is_signed() && other.is_signed() {
+            return self.signed_arith(other, |a, b| match (a, b) {
+                (_, 0..) => Some(a.wrapping_pow(b.min(u32::MAX as i64) as u32)),
+                (1, _) => Some(1),
+                (-1, _) => Some(if b % 2 == 0 { 1 } else { -1 }),
+                (0, _) => None,
+                _ => Some(0),
+            });
+        }
+        self.arith(other, |a, b| Some(a.wrapping_pow(b.min(u32::MAX as u64) as u32)))
+    }
+
+    pub fn neg(&self) -> Value {
+        Value::new(0, self.width).sub(self).with_type(self.ty)
+    }
+
+    fn arith<F>(&self, other: &Value, op: F) -> Value
+        where F: Fn(u64, u64) -> Option<u64>
+    {
+        let width = self.width.max(other.width);
+        if self.has_xz() || other.has_xz() {
+            return Value::x(width);
+        }
+        match op(self.aval, other.aval) {
+            Some(n) => Value::new(n, width),
+            None => Value::x(width), // divide by zero
+        }
+    }
+
+    fn signed_arith<F>(&self, other: &Value, op: F) -> Value
+        where F: Fn(i64, i64) -> Option<i64>
+    {
+        let width = self.width.max(other.width);
+        match (self.extend(width).to_i64(), other.extend(width).to_i64()) {
+            (Some(a), Some(b)) => match op(a, b) {
+                Some(n) => Value::new(n as u64, width).with_type(Type::Signed),
+                None => Value::x(width),
+            },
+            _ => Value::x(width),
+        }
+    }
+
+    //
+    // Shifts: the amount is unsigned and x/z in it gives all x
+    //
+    pub fn shl(&self, amount: &Value) -> Value {
+        match amount.to_u64() {
+            Some(n) if n >= self.width as u64 => Value::new(0, self.width),
+            Some(n) => Value {
+                aval: (self.aval << n) & mask(self.width),
+                bval: (self.bval << n) & mask(self.width),
+                width: self.width,
+                ty: self.ty,
+            },
+            None => Value::x(self.width),
+        }
+    }
+
+    pub fn shr(&self, amount: &Value) -> Value {
+        match amount.to_u64() {
+            Some(n) if n >= self.width as u64 => Value::new(0, self.width),
+            Some(n) => Value {
+                aval: self.aval >> n,
+                bval: self.bval >> n,
+                width: self.width,
+                ty: self.ty,
+            },
+            None => Value::x(self.width),
+        }
+    }
+
+    // >>> fills a signed value with copies of its sign bit
+    pub fn ashr(&self, amount: &Value) -> Value {
+        if !self.is_signed() {
+            return self.shr(amount);
+        }
+        let top = self.bit(self.width - 1);
+        match amount.to_u64() {
+            Some(n) => {
+                let mut v = self.shr(amount);
+                let fill = (n.min(self.width as u64)) as usize;
+                for i in self.width - fill..self.width {
+                    v.set_bit(i, top);
+                }
+                v
+            },
+            None => Value::x(self.width),
+        }
+    }
+
+    //
+    // Logical and relational operators give a single bit
+    //
+
+    // non-zero is true, zero is false, anything with x/z and no 1 is x
+    pub fn truth(&self) -> Logic {
+        if self.aval & !self.bval != 0 {
+            Logic::One
+        } else if self.bval != 0 {
+            Logic::X
+        } else {
+            Logic::Zero
+        }
+    }
+
+    pub fn from_logic(bit: Logic) -> Value {
+        let (aval, bval) = bit.planes();
+        Value { aval, bval, width: 1, ty: Type::Unsigned }
+    }
+
+    pub fn from_bool(b: bool) -> Value {
+        Value::new(b as u64, 1)
+    }
+
+    pub fn log_not(&self) -> Value {
+        Value::from_logic(self.truth()).not()
+    }
+
+    pub fn log_and(&self, other: &Value) -> Value {
+        Value::from_logic(self.truth()).and( &Value::from_logic(other.truth()) )
+    }
+
+    pub fn log_or(&self, other: &Value) -> Value {
+        Value::from_logic(self.truth()).or( &Value::from_logic(other.truth()) )
+    }
+
+    pub fn lt(&self, other: &Value) -> Value {
+        self.compare(other, |a, b| a < b)
+    }
+
+    pub fn le(&self, other: &Value) -> Value {
+        self.compare(other, |a, b| a <= b)
+    }
+
+    pub fn gt(&self, other: &Value) -> Value {
+        self.compare(other, |a, b| a > b)
+    }
+
+    pub fn ge(&self, other: &Value) -> Value {
+        self.compare(other, |a, b| a >= b)
+    }
+
+    fn compare<F>(&self, other: &Value, op: F) -> Value
+        where F: Fn(i128, i128) -> bool
+    {
+        let width = self.width.max(other.width);
+        let signed = self.is_signed() && other.is_signed();
+        let number = |v: &Value| if signed {
+            v.extend(width).to_i64().map(|n| n as i128)
+        } else {
+            v.to_u64().map(|n| n as i128)
+        };
+        match (number(self), number(other)) {
+            (Some(a), Some(b)) => Value::from_bool( op(a, b) ),
+            _ => Value::x(1),
+        }
+    }
+
+    // x if the known bits match but some aren't known
+    pub fn eq(&self, other: &Value) -> Value {
+        let width = self.width.max(other.width);
+        let (a, b) = (self.resize(width), other.resize(width));
+        let known = !a.bval & !b.bval & mask(width);
+        if (a.aval ^ b.aval) & known != 0 {
+            Value::from_bool(false)
+        } else if known != mask(width) {
+            Value::x(1)
+        } else {
+            Value::from_bool(true)
+        }
+    }
+
+    pub fn ne(&self, other: &Value) -> Value {
+        self.eq(other).not()
+    }
+
+    // x and z compared literally, always 0 or 1
+    pub fn case_eq(&self, other: &Value) -> Value {
+        let width = self.width.max(other.width);
+        let (a, b) = (self.resize(width), other.resize(width));
+        Value::from_bool(a.aval == b.aval && a.bval == b.bval)
+    }
+
+    pub fn case_ne(&self, other: &Value) -> Value {
+        self.case_eq(other).not()
+    }
+
+    // casez ignores z bits on either side, casex x bits as well
+    pub fn case_match(&self, other: &Value, ignore_z: bool, ignore_x: bool) -> bool {
+        let width = self.width.max(other.width);
+        let (a, b) = (self.resize(width), other.resize(width));
+        let mut dont_care = 0;
+        if ignore_z {
+            dont_care |= (a.bval & !a.aval) | (b.bval & !b.aval);
+        }
+        if ignore_x {
+            dont_care |= a.bval | b.bval;
+        }
+        let care = !dont_care & mask(width);
+        (a.aval ^ b.aval) & care == 0 && (a.bval ^ b.bval) & care == 0
+    }
+
+    //
+    // Reductions
+    //
+    pub fn reduce_and(&self) -> Value {
+        (0..self.width).fold(Value::new(1, 1), |acc, i| {
+            acc.and( &Value::from_logic(self.bit(i)) )
+        })
+    }
+
+    pub fn reduce_or(&self) -> Value {
+        (0..self.width).fold(Value::new(0, 1), |acc, i| {
+            acc.or( &Value::from_logic(self.bit(i)) )
+        })
+    }
+
+    pub fn reduce_xor(&self) -> Value {
+        (0..self.width).fold(Value::new(0, 1), |acc, i| {
+            acc.xor( &Value::from_logic(self.bit(i)) )
+        })
+    }
+
+    // where two values disagree the result is x, for "x ? a : b"
+    pub fn merge(&self, other: &Value) -> Value {
+        let width = self.width.max(other.width);
+        let (a, b) = (self.resize(width), other.resize(width));
+        let differ = (a.aval ^ b.aval) | a.bval | b.bval;
+        let same = !differ & mask(width);
+        Value {
+            aval: (a.aval & same) | (differ & mask(width)),
+            bval: differ & mask(width),
+            width,
+            ty: Type::Unsigned,
+        }
+    }
+
+    // the number, if every bit is known
+    pub fn to_u64(&self) -> Option<u64> {
+        if self.has_xz() {
+            None
+        } else {
+            Some(self.aval)
+        }
+    }
+
+    // the number in two's complement, if every bit is known
+    pub fn to_i64(&self) -> Option<i64> {
+        let n = self.to_u64()?;
+        if self.width >= 64 {
+            Some(n as i64)
+        } else {
+            let shift = 64 - self.width;
+            Some(((n << shift) as i64) >> shift)
+        }
+    }
+
+    // x and z bits make a real 0
+    pub fn to_real(&self) -> f64 {
+        match self.ty {
+            Type::Real => f64::from_bits(self.aval),
+            Type::Signed => self.to_i64().unwrap_or(0) as f64,
+            Type::Unsigned => self.to_u64().unwrap_or(0) as f64,
+        }
+    }
+
+    // MSB first, for VCD files and %b
+    pub fn to_bin_string(&self) -> String {
+        (0..self.width).rev().map(|i| format!("{}", self.bit(i))).collect()
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        if self.is_real() {
+            write!(f, "{}", self.to_real())
+        } else if self.width == 1 {
+            write!(f, "{}", self.bit(0))
+        } else if self.has_xz() {
+            write!(f, "{}'b{}", self.width, self.to_bin_string())
+        } else if let (true, Some(n)) = (self.is_signed(), self.to_i64()) {
+            write!(f, "{}", n)
+        } else {
+            write!(f, "{}", self.aval)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "01xz" as a 4-bit value, msb first
+    fn bits(digits: &str) -> Value {
+        Value::parse(Some(digits.len()), 'b', digits).unwrap()
+    }
+
+    #[test]
+    fn bitwise_operators_are_four_state() {
+        // every pairing of 0, 1, x and z, down the columns
+        let a = bits("00001111xxxxzzzz");
+        let b = bits("01xz01xz01xz01xz");
+        assert_eq!(a.and(&b).to_bin_string(), "000001xx0xxx0xxx");
+        assert_eq!(a.or(&b).to_bin_string(), "01xx1111x1xxx1xx");
+        assert_eq!(a.xor(&b).to_bin_string(), "01xx10xxxxxxxxxx");
+        assert_eq!(bits("01xz").not().to_bin_string(), "10xx");
+    }
+
+    #[test]
+    fn equality_with_unknown_bits() {
+        assert_eq!(bits("1x").eq(&bits("0x")), Value::from_bool(false));
+        assert_eq!(bits("1x").eq(&bits("1x")), Value::x(1));
+        assert_eq!(bits("1x").case_eq(&bits("1x")), Value::from_bool(true));
+        assert_eq!(bits("1x").case_eq(&bits("1z")), Value::from_bool(false));
+        assert!(bits("1z").case_match(&bits("10"), true, false));
+        assert!(!bits("1x").case_match(&bits("10"), true, false));
+        assert!(bits("1x").case_match(&bits("10"), true, true));
+    }
+
+    #[test]
+    fn truth_reductions_and_arithmetic() {
+        assert_eq!(bits("0x0").truth(), Logic::X);
+        assert_eq!(bits("0x1").truth(), Logic::One);
+        assert_eq!(bits("000").truth(), Logic::Zero);
+        assert_eq!(bits("1x0").reduce_and().bit(0), Logic::Zero);
+        assert_eq!(bits("1x1").reduce_and().bit(0), Logic::X);
+        assert_eq!(bits("0z1").reduce_or().bit(0), Logic::One);
+        assert_eq!(bits("101").reduce_xor().bit(0), Logic::Zero);
+        assert_eq!(bits("0101").add(&bits("000z")), Value::x(4));
+        assert_eq!(bits("0101").div(&bits("0000")), Value::x(4));
+        assert_eq!(bits("1111").add(&bits("0001")), Value::new(0, 4));
+        assert_eq!(bits("10x1").merge(&bits("1001")).to_bin_string(), "10x1");
+        assert_eq!(bits("1011").merge(&bits("1001")).to_bin_string(), "10x1");
+    }
+
+    #[test]
+    fn numbers_with_unknown_digits() {
+        assert_eq!(Value::parse(Some(8), 'h', "x5").unwrap().to_bin_string(), "xxxx0101");
+        assert_eq!(Value::parse(Some(6), 'o', "z?").unwrap().to_bin_string(), "zzzzzz");
+        assert_eq!(Value::parse(Some(8), 'b', "x1").unwrap().to_bin_string(), "xxxxxxx1");
+        assert_eq!(Value::parse(Some(8), 'b', "1").unwrap().to_bin_string(), "00000001");
+        assert_eq!(Value::parse(Some(4), 'd', "z").unwrap(), Value::z(4));
+        assert_eq!(Value::parse(Some(4), 'h', "g"), None);
+    }
+
+    #[test]
+    fn changed_bits() {
+        assert_eq!(bits("0000").changed(&bits("0000")), None);
+        assert_eq!(bits("0100").changed(&bits("0z00")), Some((2, 2)));
+        assert_eq!(bits("1001").changed(&bits("0000")), Some((0, 3)));
+    }
+
+    fn signed(n: i64, width: usize) -> Value {
+        Value::new(n as u64, width).with_type(Type::Signed)
+    }
+
+    #[test]
+    fn sign_extension() {
+        assert_eq!(signed(-3, 4).extend(8).to_bin_string(), "11111101");
+        assert_eq!(signed(5, 4).extend(8).to_bin_string(), "00000101");
+        assert_eq!(bits("1101").extend(8).to_bin_string(), "00001101");
+        assert_eq!(bits("x101").with_type(Type::Signed).extend(6).to_bin_string(), "xxx101");
+        assert_eq!(signed(-3, 4).to_i64(), Some(-3));
+        assert_eq!(signed(-3, 4).to_u64(), Some(13));
+        assert_eq!(signed(-1, 64).to_i64(), Some(-1));
+    }
+
+    #[test]
+    fn signed_only_when_both_are() {
+        let minus_seven = signed(-7, 8);
+        assert_eq!(minus_seven.div(&signed(2, 8)).to_i64(), Some(-3));
+        assert_eq!(minus_seven.rem(&signed(2, 8)).to_i64(), Some(-1));
+        assert_eq!(minus_seven.lt(&signed(1, 8)), Value::from_bool(true));
+        assert_eq!(minus_seven.lt(&Value::new(1, 8)), Value::from_bool(false));
+        assert_eq!(minus_seven.div(&Value::new(2, 8)).to_u64(), Some(124));
+        assert_eq!(signed(-2, 8).pow(&signed(3, 8)).to_i64(), Some(-8));
+        assert_eq!(signed(2, 8).pow(&signed(-1, 8)).to_i64(), Some(0));
+        assert_eq!(signed(0, 8).pow(&signed(-1, 8)), Value::x(8));
+    }
+
+    #[test]
+    fn arithmetic_shift_copies_the_sign() {
+        let v = signed(-16, 8);
+        assert_eq!(v.ashr(&Value::new(2, 8)).to_bin_string(), "11111100");
+        assert_eq!(v.shr(&Value::new(2, 8)).to_bin_string(), "00111100");
+        assert_eq!(bits("11110000").ashr(&Value::new(2, 8)).to_bin_string(), "00111100");
+        assert_eq!(v.ashr(&Value::new(20, 8)).to_bin_string(), "11111111");
+    }
+
+    #[test]
+    fn reals_round_half_away_from_zero() {
+        assert_eq!(Value::from_real(2.5, 32).to_u64(), Some(3));
+        assert_eq!(Value::from_real(-2.5, 32).with_type(Type::Signed).to_i64(), Some(-3));
+        assert_eq!(Value::from_real(f64::NAN, 8), Value::x(8));
+        assert_eq!(Value::real(1.5).to_real(), 1.5);
+        assert_eq!(signed(-3, 4).to_real(), -3.0);
+        assert_eq!(signed(-3, 4).to_string(), "-3");
+        assert_eq!(Value::new(13, 4).to_string(), "13");
+    }
+}
@@ -1,10 +1,9 @@
-/// VCD routines
+//! VCD routines
 
 // Needs access to the symbol table
 // don't need close() cos Rust?
 
 use std::io::prelude::*;
-use std::error::Error;
 use std::fs::File;
 use std::path::Path;
 
@@ -30,10 +29,10 @@ impl VcdWriter {
         };
 
         // open the path to write
-        writer.file = match File::create(&path) {
+        writer.file = match File::create(path) {
             Err(why) => {
                     println!("*ERROR* can't open VCD file {}: {}",
-                        display, why.description() );
+                        display, why );
                     None
             },
             Ok(file) => Some(file),
@@ -53,40 +52,49 @@ $comment
    Any comment text.
 $end
 $timescale 1ps $end
-$scope module logic $end
 ";
         if let Some(ref mut file) = self.file {
             let _ = file.write_all(header.as_bytes());
         }
     }
 
-    pub fn declare_vars(&mut self, vars: &[String]) {
-        let mut vcd_id = '#';
-        let mut i = 0;
-        for var in vars {
-            // map the identifier names to a VCD single-letter
-            self.lut.insert(var.clone(), vcd_id.to_string());
-
-            // create the definition line
-            let line = format!("$var wire 1 {} {} $end\n", vcd_id, var);
-            if let Some(ref mut file) = self.file {
-                let _ = file.write_all(line.as_bytes());
+    // one $scope per module instance, "top.u1.q" is q in top's u1
+    pub fn declare_vars(&mut self, vars: &[String], widths: &[usize], reals: &[bool]) {
+        let mut order: Vec<usize> = (0..vars.len()).collect();
+        order.sort_by_key(|&i| split_scope(&vars[i]));
+
+        let mut lines = String::new();
+        let mut open: Vec<&str> = vec![];
+        for (n, &i) in order.iter().enumerate() {
+            let (scopes, name) = split_scope(&vars[i]);
+
+            // leave the scopes this one isn't in, enter the ones it is
+            let common = open.iter().zip(&scopes).take_while(|&(a, b)| a == b).count();
+            while open.len() > common {
+                open.pop();
+                lines.push_str("$upscope $end\n");
             }
-            vcd_id = char::from(vcd_id as u8 + 1 );
-            i += 1;
-            if i > 32 {
-                println!("*WARNING* Too many identifiers for VCD file");
-                return
+            for scope in &scopes[common..] {
+                lines.push_str( &format!("$scope module {} $end\n", scope) );
+                open.push(scope);
             }
+
+            // map the identifier names to a short VCD code
+            let vcd_id = vcd_code(n);
+            let kind = if reals[i] { "real" } else { "wire" };
+            lines.push_str( &format!("$var {} {} {} {} $end\n", kind, widths[i], vcd_id, name) );
+            self.lut.insert(vars[i].clone(), vcd_id);
+        }
+        for _ in open {
+            lines.push_str("$upscope $end\n");
         }
 
         // write the turnaround
-        let turnaround = "$upscope $end
-$enddefinitions $end
+        lines.push_str("$enddefinitions $end
 $dumpvars
-$end\n";
+$end\n");
         if let Some(ref mut file) = self.file {
-            let _ = file.write_all(turnaround.as_bytes());
+            let _ = file.write_all(lines.as_bytes());
         }
     }
 
@@ -99,7 +107,11 @@ $end\n";
             for var in vars {
                 let vcd_id = &self.lut[var];
                 if let Some(value) = data.get(var) {
-                    let line = format!("b{:b} {}\n", value, vcd_id); 
+                    let line = if value.is_real() {
+                        format!("r{} {}\n", value.to_real(), vcd_id)
+                    } else {
+                        format!("b{} {}\n", value.to_bin_string(), vcd_id)
+                    };
                     let _ = file.write_all(line.as_bytes());
                 }
             }
@@ -110,3 +122,30 @@ $end\n";
 }
 
 
+
+// "top.u1.q" -> (["top", "u1"], "q"), anything outside a module goes
+// in a scope of its own
+fn split_scope(var: &str) -> (Vec<&str>, &str) {
+    let mut parts: Vec<&str> = var.split('.').collect();
+    let name = parts.pop().unwrap_or("");
+    if parts.is_empty() {
+        parts.push("logic");
+    }
+    (parts, name)
+}
+
+// VCD identifier codes are strings of the printable characters
+fn vcd_code(mut n: usize) -> String {
+    let first = b'!';
+    let radix = (b'~' - first + 1) as usize;
+    let mut code = String::new();
+    loop {
+        code.push( char::from(first + (n % radix) as u8) );
+        n /= radix;
+        if n == 0 {
+            break;
+        }
+        n -= 1;
+    }
+    code
+}